@@ -1,6 +1,6 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    fmt::{Debug, Write},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    fmt::Debug,
     path::PathBuf,
     sync::{mpsc, Arc, Mutex},
     time::Duration,
@@ -10,12 +10,15 @@ use diagnostic::{
     miette::{diagnostic, Diagnostic},
     DynDr,
 };
-use feather_parser::parse_module;
-use files::{InputFile, Path, Source, SourceData, SourceType, Str};
+use feather_parser::{module_definition_map, parse_module, TreeCache};
+use files::{InputFile, Path, Source, SourceData, SourceSpan, SourceType, Str};
+use formatter::{Atom, IndentStyle, DEFAULT_MAX_WIDTH};
 use kernel::{
+    de_bruijn::DeBruijnIndex,
     definition::Definition,
     expr::{
-        ArgumentStyle, Binder, BinderStructure, Expression, ExpressionData, InvocationStyle, Usage,
+        unshadowed_name, ArgumentStyle, Binder, BinderStructure, DisplayOptions, Expression,
+        ExpressionData, InvocationStyle, Precedence, Usage,
     },
 };
 use notify_debouncer_mini::notify::RecursiveMode;
@@ -27,7 +30,15 @@ use thiserror::Error;
 pub struct FeatherDatabase {
     storage: salsa::Storage<Self>,
     project_root: PathBuf,
+    /// The input for each file that has been read, keyed by the path returned by
+    /// [`FeatherDatabase::file_key`].
+    /// Files that were found to be missing have an input too, so that queries notice if they are created.
     files: Arc<Mutex<HashMap<PathBuf, InputFile>>>,
+    /// The keys in `files` of files whose contents were set in memory by [`FeatherDatabase::set_overlay`],
+    /// such as unsaved editor buffers.
+    /// These shadow the files on disk with the same path, so changes on disk are ignored.
+    overlays: Arc<Mutex<HashSet<PathBuf>>>,
+    trees: Arc<TreeCache>,
     watcher: Arc<
         Mutex<notify_debouncer_mini::Debouncer<notify_debouncer_mini::notify::RecommendedWatcher>>,
     >,
@@ -46,6 +57,8 @@ impl salsa::ParallelDatabase for FeatherDatabase {
             storage: self.storage.snapshot(),
             project_root: self.project_root.clone(),
             files: Arc::clone(&self.files),
+            overlays: Arc::clone(&self.overlays),
+            trees: Arc::clone(&self.trees),
             watcher: Arc::clone(&self.watcher),
         })
     }
@@ -53,80 +66,178 @@ impl salsa::ParallelDatabase for FeatherDatabase {
 
 impl files::Db for FeatherDatabase {
     fn input_file(&self, path: PathBuf) -> std::io::Result<InputFile> {
-        let path = self.project_root.join(&path).canonicalize().map_err(|e| {
-            std::io::Error::new(e.kind(), format!("failed to read {}", path.display()))
-        })?;
+        let path = self.file_key(&path);
         Ok(match self.files.lock().unwrap().entry(path.clone()) {
-            // If the file already exists in our cache then just return it.
+            // If the file already exists in our cache, or has been overlaid, then just return it.
             Entry::Occupied(entry) => *entry.get(),
             // If we haven't read this file yet set up the watch, read the
             // contents, store it in the cache, and return it.
+            // Missing files are stored too, so that we notice if they are created.
             Entry::Vacant(entry) => {
                 // Set up the watch before reading the contents to try to avoid
                 // race conditions.
-                let watcher = &mut *self.watcher.lock().unwrap();
-                watcher
-                    .watcher()
-                    .watch(&path, RecursiveMode::NonRecursive)
-                    .unwrap();
-                let contents = std::fs::read_to_string(&path).map_err(|e| {
-                    std::io::Error::new(e.kind(), format!("failed to read {}", path.display()))
-                })?;
-                *entry.insert(InputFile::new(self, path, Arc::new(contents)))
+                self.watch(&path);
+                let contents = read_file(&path)?;
+                *entry.insert(InputFile::new(self, path, contents))
             }
         })
     }
 }
 
+/// Reads the file at the given path, returning `None` if it does not exist.
+fn read_file(path: &std::path::Path) -> std::io::Result<Option<Arc<String>>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(Arc::new(contents))),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(std::io::Error::new(
+            err.kind(),
+            format!("failed to read {}", path.display()),
+        )),
+    }
+}
+
+impl feather_parser::Db for FeatherDatabase {
+    fn tree_cache(&self) -> &TreeCache {
+        &self.trees
+    }
+}
+
+/// The formatter atoms representing an expression,
+/// which are built by [`write_expression`] to implement [`kernel::Db::format_expression`].
+#[derive(Default)]
+struct ExpressionAtoms {
+    atoms: Vec<Atom>,
+    /// Used to generate unique ids for groups of breakpoints.
+    groups: usize,
+}
+
+impl ExpressionAtoms {
+    fn literal(&mut self, text: impl Into<String>) {
+        self.atoms.push(Atom::Literal(text.into()));
+    }
+
+    /// Writes a keyword or operator, surrounded by spaces.
+    fn keyword(&mut self, keyword: &str) {
+        self.atoms.push(Atom::Space);
+        self.literal(keyword);
+        self.atoms.push(Atom::Space);
+    }
+
+    /// Writes a point at which the line is broken if it would otherwise be too long.
+    fn breakpoint(&mut self) {
+        self.groups += 1;
+        self.atoms.push(Atom::Breakpoint {
+            spaced: true,
+            group: self.groups,
+        });
+    }
+
+    /// Writes the opening brace of a block whose items are each written on their own line.
+    fn open_block(&mut self) {
+        self.keyword("{");
+        self.atoms.push(Atom::IndentStart);
+    }
+
+    /// Writes the closing brace of a block opened with [`ExpressionAtoms::open_block`].
+    fn close_block(&mut self) {
+        self.atoms.push(Atom::IndentEnd);
+        self.atoms.push(Atom::Hardline);
+        self.keyword("}");
+    }
+
+    /// Starts a new item in a block.
+    fn block_item(&mut self) {
+        self.atoms.push(Atom::Hardline);
+    }
+}
+
 /// Internally used to implement [`kernel::Db::format_expression`].
-/// Writes badly-formatted but clear and unambiguous Feather code representing the given expression.
-/// This will then be run through the formatter.
-/// TODO: Precedence levels (this function will currently produce some incorrect results).
+/// Writes the formatter atoms for Feather code representing the given expression,
+/// laid out in the same way as the Feather query file lays out Feather code.
+///
+/// The expression is wrapped in parentheses if it binds more loosely than the given `precedence`,
+/// which is the precedence required by the position the expression is written in.
 fn write_expression(
     db: &FeatherDatabase,
     expr: Expression,
     locals: &[Str],
-    w: &mut impl Write,
-) -> std::fmt::Result {
+    precedence: Precedence,
+    w: &mut ExpressionAtoms,
+) {
+    if Precedence::of(db, expr) < precedence {
+        w.literal("(");
+        w.atoms.push(Atom::IndentStart);
+        write_expression_unparenthesised(db, expr, locals, w);
+        w.atoms.push(Atom::IndentEnd);
+        w.literal(")");
+    } else {
+        write_expression_unparenthesised(db, expr, locals, w)
+    }
+}
+
+/// The name of the given local variable, or a placeholder if it is not bound.
+fn local_name(db: &FeatherDatabase, index: DeBruijnIndex, locals: &[Str]) -> String {
+    match locals.get(index.value() as usize) {
+        // Binders are renamed by `unshadowed_name` as they are written,
+        // so this name unambiguously refers to this local variable.
+        Some(name) => name.text(db).clone(),
+        None => format!("<local {}>", index.value()),
+    }
+}
+
+fn write_expression_unparenthesised(
+    db: &FeatherDatabase,
+    expr: Expression,
+    locals: &[Str],
+    w: &mut ExpressionAtoms,
+) {
     match expr.data(db) {
-        ExpressionData::Local(index) => match locals.get(index.value() as usize) {
-            Some(name) => {
-                // TODO: Check if there is something with the same name at a lower index.
-                write!(w, "{}", name.text(db))
-            }
-            None => write!(w, "<local {}>", index.value()),
-        },
+        ExpressionData::Local(index) => w.literal(local_name(db, index, locals)),
         ExpressionData::Apply { left, right } => {
-            write_expression(db, left, locals, w)?;
-            write!(w, " ( ")?;
-            write_expression(db, right, locals, w)?;
-            write!(w, " )")
+            // Application is left-associative.
+            write_expression(db, left, locals, Precedence::Apply, w);
+            w.atoms.push(Atom::Space);
+            write_expression(db, right, locals, Precedence::Atom, w)
         }
         ExpressionData::Lambda(binder) => {
-            write!(w, "fun ")?;
-            write_binder(db, binder, locals, w)
+            w.keyword("fun");
+            write_binder(db, binder, locals, w);
         }
         ExpressionData::Pi(binder) => {
-            write!(w, "for ")?;
-            write_binder(db, binder, locals, w)
+            w.keyword("for");
+            write_binder(db, binder, locals, w);
         }
         ExpressionData::Let {
             name,
             to_assign,
             body,
         } => {
-            write!(w, "let {} = ", name.text(db))?;
-            write_expression(db, to_assign, locals, w)?;
-            writeln!(w, " ;")?;
+            let name = unshadowed_name(db, name, |name| locals.contains(name));
+            w.keyword("let");
+            w.literal(name.text(db).clone());
+            w.keyword("=");
+            write_expression(db, to_assign, locals, Precedence::Binder, w);
+            w.literal(";");
+            w.atoms.push(Atom::Hardline);
             let mut new_locals = locals.to_vec();
             new_locals.insert(0, name);
-            write_expression(db, body, &new_locals, w)
+            write_expression(db, body, &new_locals, Precedence::Binder, w)
         }
         ExpressionData::Sort(universe) => {
-            write!(w, "Sort {}", universe.0)
+            w.keyword("Sort");
+            w.literal(universe.display(db));
         }
-        ExpressionData::Inst(path) => {
-            write!(w, "inst {}", path.display(db))
+        ExpressionData::Inst { path, universes } => {
+            w.keyword("inst");
+            if universes.is_empty() {
+                w.literal(path.display(db));
+            } else {
+                let universes = universes
+                    .iter()
+                    .map(|universe| universe.display(db))
+                    .collect::<Vec<_>>();
+                w.literal(format!("{}.{{{}}}", path.display(db), universes.join(", ")));
+            }
         }
         ExpressionData::Intro {
             path,
@@ -134,57 +245,69 @@ fn write_expression(
             variant,
             fields,
         } => {
-            write!(w, "intro {}", path.display(db))?;
+            w.keyword("intro");
+            w.literal(path.display(db));
             for param in parameters {
-                write!(w, " ( ")?;
-                write_expression(db, param, locals, w)?;
-                write!(w, " )")?;
+                w.atoms.push(Atom::Space);
+                write_expression(db, param, locals, Precedence::Atom, w);
             }
-            write!(w, " / {} {{", variant.text(db))?;
+            w.keyword("/");
+            w.literal(variant.text(db).clone());
+            w.open_block();
             for (name, field) in fields.iter() {
-                write!(w, "\n{} = ", name.text(db))?;
-                write_expression(db, *field, locals, w)?;
-                write!(w, " , ")?;
+                w.block_item();
+                w.literal(name.text(db).clone());
+                w.keyword("=");
+                write_expression(db, *field, locals, Precedence::Binder, w);
+                w.literal(",");
             }
-            write!(w, "\n}}")
+            w.close_block();
         }
         ExpressionData::Match {
             subject,
             return_ty,
             cases,
         } => {
-            write!(w, "match ")?;
-            write_expression(db, subject, locals, w)?;
-            write!(w, " return ")?;
-            write_expression(db, return_ty, locals, w)?;
-            write!(w, " {{")?;
+            w.keyword("match");
+            write_expression(db, subject, locals, Precedence::Binder, w);
+            w.keyword("return");
+            write_expression(db, return_ty, locals, Precedence::Binder, w);
+            w.open_block();
             for (name, case) in cases.iter() {
-                write!(w, "\n{} -> ", name.text(db))?;
-                write_expression(db, *case, locals, w)?;
-                write!(w, " ,")?;
+                w.block_item();
+                w.literal(name.text(db).clone());
+                w.keyword("->");
+                w.breakpoint();
+                write_expression(db, *case, locals, Precedence::Binder, w);
+                w.literal(",");
             }
-            write!(w, "\n}}")
+            w.close_block();
         }
         ExpressionData::Fix {
             binder,
             rec_name,
             body,
         } => {
-            write!(w, "fix ")?;
-            write_binder(db, binder, locals, w)?;
-            write!(w, " with {} ; ", rec_name.text(db))?;
+            w.keyword("fix");
+            let name = write_binder(db, binder, locals, w);
             let mut new_locals = locals.to_vec();
-            new_locals.insert(0, binder.structure.bound.name);
+            new_locals.insert(0, name);
+            let rec_name = unshadowed_name(db, rec_name, |name| new_locals.contains(name));
+            w.keyword("with");
+            w.literal(rec_name.text(db).clone());
+            w.literal(";");
+            w.breakpoint();
             new_locals.insert(0, rec_name);
-            write_expression(db, body, &new_locals, w)
+            write_expression(db, body, &new_locals, Precedence::Binder, w)
         }
         ExpressionData::Ref(ty) => {
-            write!(w, "ref ")?;
-            write_expression(db, ty, locals, w)
+            w.keyword("ref");
+            write_expression(db, ty, locals, Precedence::Atom, w)
         }
         ExpressionData::Deref(value) => {
-            write!(w, "* ")?;
-            write_expression(db, value, locals, w)
+            w.literal("*");
+            w.atoms.push(Atom::Space);
+            write_expression(db, value, locals, Precedence::Atom, w)
         }
         ExpressionData::Loan {
             local,
@@ -192,146 +315,188 @@ fn write_expression(
             with,
             body,
         } => {
-            let local = match locals.get(local.value() as usize) {
-                Some(local) => local.text(db).clone(),
-                None => format!("<local {}>", local.value()),
-            };
-            write!(
-                w,
-                "loan {} as {} with {} ; ",
-                local,
-                loan_as.text(db),
-                with.text(db)
-            )?;
+            let local = local_name(db, local, locals);
             let mut new_locals = locals.to_vec();
+            let loan_as = unshadowed_name(db, loan_as, |name| new_locals.contains(name));
             new_locals.insert(0, loan_as);
+            let with = unshadowed_name(db, with, |name| new_locals.contains(name));
             new_locals.insert(0, with);
-            write_expression(db, body, &new_locals, w)
+            w.keyword("loan");
+            w.literal(local);
+            w.keyword("as");
+            w.literal(loan_as.text(db).clone());
+            w.keyword("with");
+            w.literal(with.text(db).clone());
+            w.literal(";");
+            w.breakpoint();
+            write_expression(db, body, &new_locals, Precedence::Binder, w)
         }
         ExpressionData::Take {
             local,
             proofs,
             body,
         } => {
-            let local = match locals.get(local.value() as usize) {
-                Some(local) => local.text(db).clone(),
-                None => format!("<local {}>", local.value()),
-            };
-            write!(w, "take {} {{", local)?;
+            w.keyword("take");
+            w.literal(local_name(db, local, locals));
+            w.open_block();
             for (name, proof) in proofs.iter() {
-                let local = match locals.get(name.value() as usize) {
-                    Some(local) => local.text(db).clone(),
-                    None => format!("<local {}>", name.value()),
-                };
-                write!(w, "\n{local} -> ")?;
-                write_expression(db, *proof, locals, w)?;
-                write!(w, " ,")?;
+                w.block_item();
+                w.literal(local_name(db, *name, locals));
+                w.keyword("->");
+                w.breakpoint();
+                write_expression(db, *proof, locals, Precedence::Binder, w);
+                w.literal(",");
             }
-            write!(w, "\n}} ;\n")?;
-            write_expression(db, body, locals, w)
+            w.close_block();
+            w.literal(";");
+            w.atoms.push(Atom::Hardline);
+            write_expression(db, body, locals, Precedence::Binder, w)
         }
         ExpressionData::In { reference, target } => {
-            write_expression(db, reference, locals, w)?;
-            write!(w, " in ")?;
-            write_expression(db, target, locals, w)
+            // `in` is left-associative.
+            write_expression(db, reference, locals, Precedence::In, w);
+            w.keyword("in");
+            write_expression(db, target, locals, Precedence::Apply, w)
         }
         ExpressionData::LocalConstant(constant) => {
-            write!(w, "{}", constant.structure.bound.name.text(db))
+            w.literal(constant.structure.bound.name.text(db).clone())
         }
-        ExpressionData::Hole(hole) => write!(w, "{}", hole.id),
+        ExpressionData::Hole(hole) => w.literal(hole.id.to_string()),
     }
 }
 
+/// Writes the given binder, returning the name that was used for the bound variable.
 fn write_binder(
     db: &FeatherDatabase,
     binder: Binder,
     locals: &[Str],
-    w: &mut impl Write,
-) -> std::fmt::Result {
-    write_binder_structure(db, binder.structure, locals, w)?;
+    w: &mut ExpressionAtoms,
+) -> Str {
+    let name = unshadowed_name(db, binder.structure.bound.name, |name| {
+        locals.contains(name)
+    });
+    write_binder_structure(db, name, binder.structure, locals, w);
     let mut new_locals = locals.to_vec();
-    new_locals.insert(0, binder.structure.bound.name);
-    write_expression(db, binder.body, &new_locals, w)
+    new_locals.insert(0, name);
+    write_expression(db, binder.body, &new_locals, Precedence::Binder, w);
+    name
 }
 
 fn write_binder_structure(
     db: &FeatherDatabase,
+    name: Str,
     structure: BinderStructure,
     locals: &[Str],
-    w: &mut impl Write,
-) -> std::fmt::Result {
-    match structure.argument_style {
-        ArgumentStyle::Explicit => write!(w, "( ")?,
-        ArgumentStyle::ImplicitEager => write!(w, "{{ ")?,
-        ArgumentStyle::ImplicitWeak => write!(w, "{{{{ ")?,
-    }
-    write!(w, "{} : ", structure.bound.name.text(db))?;
+    w: &mut ExpressionAtoms,
+) {
+    let (open, close) = match structure.argument_style {
+        ArgumentStyle::Explicit => ("(", ")"),
+        ArgumentStyle::ImplicitEager => ("{", "}"),
+        ArgumentStyle::ImplicitWeak => ("{{", "}}"),
+    };
+    w.literal(open);
+    w.atoms.push(Atom::IndentStart);
+    w.literal(name.text(db).clone());
+    w.literal(":");
+    w.atoms.push(Atom::Space);
     if structure.bound.usage == Usage::Erased {
-        write!(w, "0 ")?;
-    }
-    write_expression(db, structure.bound.ty, locals, w)?;
-    match structure.argument_style {
-        ArgumentStyle::Explicit => write!(w, " )")?,
-        ArgumentStyle::ImplicitEager => write!(w, " }}")?,
-        ArgumentStyle::ImplicitWeak => write!(w, " }}}}")?,
+        w.literal("0");
+        w.atoms.push(Atom::Space);
     }
+    write_expression(db, structure.bound.ty, locals, Precedence::Binder, w);
+    w.atoms.push(Atom::IndentEnd);
+    w.literal(close);
     match structure.invocation_style {
-        InvocationStyle::Once => write!(w, " -> ")?,
-        InvocationStyle::Many => write!(w, " => ")?,
+        InvocationStyle::Once => w.keyword("->"),
+        InvocationStyle::Many => w.keyword("=>"),
     }
-    Ok(())
+    w.breakpoint();
 }
 
 impl kernel::Db for FeatherDatabase {
     fn format_expression(&self, expr: Expression) -> String {
-        // The formatter only works on whole source files,
-        // so we need to essentially embed this expression in a source file.
-        const INITIAL: &str = "module print def f: Sort 0 = ";
-        let mut input = INITIAL.to_owned();
-        match write_expression(self, expr, &[], &mut input) {
-            Ok(()) => match formatter::format_feather(&input) {
-                Some(result) => result[INITIAL.len()..].trim().to_owned(),
-                None => format!("<failed to format expression: {input}>"),
-            },
-            Err(_) => unreachable!("should not error while writing to a string"),
+        // We build the formatter atoms directly, rather than writing Feather code and parsing it again.
+        let mut atoms = ExpressionAtoms::default();
+        write_expression(self, expr, &[], Precedence::Binder, &mut atoms);
+        match formatter::format_expression_atoms(
+            &atoms.atoms,
+            IndentStyle::Spaces(4),
+            DEFAULT_MAX_WIDTH,
+        ) {
+            Ok(result) => result.trim().to_owned(),
+            Err(err) => {
+                tracing::warn!("failed to format expression: {err}");
+                expr.display(self, &DisplayOptions::default())
+            }
         }
     }
 
+    fn expression_span(&self, path: Path, expr: Expression) -> Option<SourceSpan> {
+        let (module, name) = path.split_last(self);
+        let source = Source::new(self, module, SourceType::Feather);
+        // Only spans inside the definition itself are relevant,
+        // since the same expression may have been written in other definitions.
+        let definition = parse_module(self, source)
+            .value()?
+            .definitions()
+            .iter()
+            .find(|def| def.contents.name.contents == name)?
+            .provenance?
+            .span;
+        feather_parser::expression_spans(self, source)
+            .get(&expr)?
+            .iter()
+            .find(|span| definition.contains(span))
+            .map(|span| SourceSpan::new(source, *span))
+    }
+
+    fn module_exists(&self, module: Path) -> bool {
+        let source = Source::new(self, module, SourceType::Feather);
+        files::source(self, source).value().is_some()
+    }
+
     fn get_definition_impl(&self, path: Path) -> DynDr<Definition> {
         let (path, name) = path.split_last(self);
         let source = Source::new(self, path, SourceType::Feather);
-        parse_module(self, source).to_dynamic().bind(|module| {
-            match module
-                .definitions
-                .into_iter()
-                .find(|def| def.contents.name.contents == name)
-            {
-                Some(def) => DynDr::new(def.contents),
-                None => DynDr::new_err(GetDefinitionError {
-                    src: source.data(self),
-                    definition: name.text(self).to_owned(),
-                    module: path.display(self),
-                })
-                .to_dynamic(),
-            }
-        })
+        module_definition_map(self, source)
+            .clone()
+            .to_dynamic()
+            .bind(|definitions| match definitions.get(&name) {
+                Some(def) => DynDr::new(def.clone()),
+                None => {
+                    let mut available = definitions
+                        .keys()
+                        .map(|name| name.text(self).as_str())
+                        .collect::<Vec<_>>();
+                    available.sort_unstable();
+                    DynDr::new_err(UnknownDefinition {
+                        src: source.data(self),
+                        definition: name.text(self).to_owned(),
+                        module: path.display(self),
+                        available: available.join(", "),
+                    })
+                    .to_dynamic()
+                }
+            })
     }
 }
 
 #[derive(Error, Diagnostic, Debug, Clone, PartialEq, Eq, Hash)]
 #[error("could not find definition {definition} in module {module}")]
-#[diagnostic(help = "this is a bug in the compiler")]
-struct GetDefinitionError {
+#[diagnostic(help = "the module defines: {available}")]
+struct UnknownDefinition {
     #[source_code]
     src: SourceData,
     definition: String,
     module: String,
+    /// The names of the definitions in the module, separated by commas.
+    available: String,
 }
 
 impl FeatherDatabase {
     /// Returns the database, along with a receiver for file update events.
     /// If running as a language server, this channel should be watched,
-    /// and any updated paths should be processed by the database.
+    /// and any updated paths should be processed by [`FeatherDatabase::process_file_event`].
     /// If running as a standalone compiler, the channel may be ignored,
     /// although receiving file update events may still be desirable in certain cases.
     pub fn new(
@@ -352,9 +517,848 @@ impl FeatherDatabase {
             storage: Default::default(),
             project_root,
             files: Default::default(),
+            overlays: Default::default(),
+            trees: Default::default(),
             watcher: Arc::new(Mutex::new(debouncer)),
         };
 
         (this, rx)
     }
+
+    /// Sets the contents of the file at the given path, relative to the project root,
+    /// without reading from or writing to disk.
+    /// The path must include the file extension, for example `test/test.ftr`.
+    /// This shadows any file on disk with the same path.
+    pub fn set_overlay(&mut self, path: PathBuf, contents: String) {
+        let path = self.file_key(&path);
+        let contents = Some(Arc::new(contents));
+        self.overlays.lock().unwrap().insert(path.clone());
+
+        let existing = self.files.lock().unwrap().get(&path).copied();
+        match existing {
+            // Every query that read this path depends on its input,
+            // including queries that found no file on disk, so they will see the overlay.
+            Some(file) => {
+                file.set_contents(self).to(contents);
+            }
+            None => {
+                let file = InputFile::new(self, path.clone(), contents);
+                self.files.lock().unwrap().insert(path, file);
+            }
+        }
+    }
+
+    /// Processes a file update event from the receiver returned by [`FeatherDatabase::new`].
+    /// If we have read the changed file before, its contents are reloaded from disk,
+    /// which invalidates every query that depends on them.
+    ///
+    /// The debouncer does not tell us what kind of change happened,
+    /// so we compare the file on disk with what we last read.
+    /// If the file no longer exists, for example because it was deleted or moved away,
+    /// queries that read it are invalidated so they report that it is missing,
+    /// and we watch its directory instead so that we notice if it is created again.
+    /// When it is, its contents are read and the file itself is watched again.
+    /// The directory is then no longer watched, unless another missing file in it still needs the watch.
+    ///
+    /// Changes to files that have been overlaid using [`FeatherDatabase::set_overlay`] are ignored.
+    pub fn process_file_event(
+        &mut self,
+        event: notify_debouncer_mini::DebouncedEvent,
+    ) -> std::io::Result<()> {
+        // We watch the paths that we store files under, so the event's path can be looked up directly.
+        // Events for other files in the directory of a missing file are ignored.
+        let path = event.path;
+        let file = match self.files.lock().unwrap().get(&path) {
+            Some(file) => *file,
+            None => return Ok(()),
+        };
+        if self.overlays.lock().unwrap().contains(&path) {
+            return Ok(());
+        }
+        let contents = read_file(&path)?;
+        let old = file.contents(self);
+        if old == contents {
+            return Ok(());
+        }
+        match (old, &contents) {
+            (Some(_), None) => {
+                tracing::debug!("{} was deleted", path.display());
+                // The operating system may already have removed the watch along with the file.
+                let _ = self.watcher.lock().unwrap().watcher().unwatch(&path);
+                self.watch(&path);
+                // The tree parsed from the old contents is no use for incremental parsing any more.
+                if let Some(source) = self.source_of_file(&path) {
+                    self.trees.forget(source);
+                }
+            }
+            (None, Some(_)) => {
+                tracing::debug!("{} was created", path.display());
+                self.watch(&path);
+                self.unwatch_directory(&path);
+            }
+            _ => tracing::debug!("reloaded {}", path.display()),
+        }
+        file.set_contents(self).to(contents);
+        Ok(())
+    }
+
+    /// The path that the file at the given path, relative to the project root, is stored under in `files`.
+    /// This is the canonical path of its directory joined with its file name,
+    /// so it can be computed for files that do not exist.
+    fn file_key(&self, path: &std::path::Path) -> PathBuf {
+        let path = self.project_root.join(path);
+        let directory = path
+            .parent()
+            .and_then(|directory| directory.canonicalize().ok());
+        match (directory, path.file_name()) {
+            (Some(directory), Some(name)) => directory.join(name),
+            _ => path,
+        }
+    }
+
+    /// Watches the file stored under the given path for changes.
+    /// If the file does not exist, its directory is watched instead,
+    /// so that we are told when it is created.
+    ///
+    /// If the file can't be watched, for example because we have run out of
+    /// inotify watches, we can still use its contents, but we won't see updates.
+    fn watch(&self, path: &std::path::Path) {
+        let target = match path.parent() {
+            Some(directory) if !path.exists() => directory,
+            _ => path,
+        };
+        let watcher = &mut *self.watcher.lock().unwrap();
+        if let Err(err) = watcher.watcher().watch(target, RecursiveMode::NonRecursive) {
+            tracing::warn!(
+                "could not watch {}, so changes to it will not be seen: {err}",
+                target.display()
+            );
+        }
+    }
+
+    /// Stops watching the directory of the file stored under the given path,
+    /// which was watched while that file was missing.
+    /// The directory stays watched if another file stored in it is still missing,
+    /// so that we notice when that file is created.
+    fn unwatch_directory(&self, path: &std::path::Path) {
+        let directory = match path.parent() {
+            Some(directory) => directory,
+            None => return,
+        };
+        let still_needed = self.files.lock().unwrap().iter().any(|(other, file)| {
+            other != path && other.parent() == Some(directory) && file.contents(self).is_none()
+        });
+        if !still_needed {
+            // The directory may not have been watched, for example if we ran out of inotify watches.
+            let _ = self.watcher.lock().unwrap().watcher().unwatch(directory);
+        }
+    }
+
+    /// The source file stored under the given path, if it is a Feather or Quill file in the project.
+    fn source_of_file(&self, path: &std::path::Path) -> Option<Source> {
+        let root = self.project_root.canonicalize().ok()?;
+        self.source_at(path.strip_prefix(root).ok()?)
+    }
+
+    /// The source file at the given path relative to the project root,
+    /// if it is a Feather or Quill file.
+    /// The path of a module is the path of its file relative to the project root, without the extension.
+    fn source_at(&self, relative: &std::path::Path) -> Option<Source> {
+        let ty = match relative
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some(extension) if extension == SourceType::Feather.extension() => SourceType::Feather,
+            Some(extension) if extension == SourceType::Quill.extension() => SourceType::Quill,
+            _ => return None,
+        };
+        let segments = relative
+            .with_extension("")
+            .iter()
+            .map(|segment| Str::new(self, segment.to_string_lossy().into_owned()))
+            .collect();
+        Some(Source::new(self, Path::new(self, segments), ty))
+    }
+
+    /// Parses every source file in the project, and certifies every definition in them.
+    /// This is the entry point for checking a whole project at once, for example in a build tool.
+    ///
+    /// The diagnostics from every module and definition are collected,
+    /// and the result is in the `err` state if any of them is a warning or an error.
+    pub fn check_project(&self) -> DynDr<()> {
+        let modules = self
+            .project_sources()
+            .into_iter()
+            .map(|source| self.check_module(source));
+        DynDr::sequence_unfail(modules)
+            .map(|_| ())
+            .promote_warnings_to_errors()
+    }
+
+    /// Parses the given module, and certifies each of its definitions.
+    fn check_module(&self, source: Source) -> DynDr<()> {
+        parse_module(self, source).to_dynamic().bind(|module| {
+            DynDr::sequence_unfail(module.definitions().iter().map(|definition| {
+                let path = source
+                    .path(self)
+                    .with(self, definition.contents.name.contents);
+                kernel::certify_definition(self, path).clone().map(|_| ())
+            }))
+            .map(|_| ())
+        })
+    }
+
+    /// Finds every Feather and Quill source file under the project root, sorted by path.
+    /// Hidden files and directories, whose names start with a dot, are skipped.
+    /// Overlaid files are included even if they do not exist on disk.
+    fn project_sources(&self) -> Vec<Source> {
+        let mut sources = BTreeMap::new();
+        let mut directories = vec![self.project_root.clone()];
+        while let Some(directory) = directories.pop() {
+            let entries = match std::fs::read_dir(&directory) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    tracing::warn!("could not read {}: {err}", directory.display());
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                if path.is_dir() {
+                    directories.push(path);
+                    continue;
+                }
+                let relative = path
+                    .strip_prefix(&self.project_root)
+                    .expect("files are found inside the project root");
+                if let Some(source) = self.source_at(relative) {
+                    sources.insert(relative.to_owned(), source);
+                }
+            }
+        }
+
+        // Overlays are stored under the paths returned by `file_key`, which are canonical if the directory exists.
+        let root = self
+            .project_root
+            .canonicalize()
+            .unwrap_or_else(|_| self.project_root.clone());
+        for path in self.overlays.lock().unwrap().iter() {
+            if let Ok(relative) = path.strip_prefix(&root) {
+                if let Some(source) = self.source_at(relative) {
+                    sources.insert(relative.to_owned(), source);
+                }
+            }
+        }
+        sources.into_values().collect()
+    }
+
+    /// Calls `compile`, then calls it again every time a file that the database has read changes.
+    /// Events that arrive together are processed as a batch, so `compile` is called once per batch.
+    /// This only returns if the watcher stops sending events.
+    pub fn run_watch_loop(
+        &mut self,
+        rx: &mpsc::Receiver<notify_debouncer_mini::DebouncedEvent>,
+        mut compile: impl FnMut(&Self),
+    ) {
+        loop {
+            compile(self);
+
+            // The output can't change unless the inputs change, so wait for a file update event.
+            let Ok(event) = rx.recv() else {
+                return;
+            };
+            for event in std::iter::once(event).chain(rx.try_iter()) {
+                if let Err(err) = self.process_file_event(event) {
+                    tracing::error!("{err}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::FeatherDatabase;
+    use feather_parser::ParseError;
+    use files::{Path, Source, SourceType, Span, Str};
+    use kernel::{
+        expr::{arbitrary_expression, Expression, ExpressionData},
+        type_check::Reducibility,
+    };
+    use notify_debouncer_mini::{DebouncedEvent, DebouncedEventKind};
+    use proptest::prelude::*;
+
+    /// Returns a database for a project that only exists in memory,
+    /// containing a module with the given name and Feather code.
+    /// Other modules can be added using [`FeatherDatabase::set_overlay`].
+    fn database_with_module(name: &str, code: &str) -> (FeatherDatabase, Source) {
+        // This directory is never created, so every file that is not overlaid is missing.
+        let project_root = std::env::temp_dir().join("feather_test_in_memory");
+        let (mut db, _rx) = FeatherDatabase::new(project_root);
+        db.set_overlay(PathBuf::from(name).with_extension("ftr"), code.to_owned());
+        let path = Path::new(&db, vec![Str::new(&db, name.to_owned())]);
+        let source = Source::new(&db, path, SourceType::Feather);
+        (db, source)
+    }
+
+    /// Writes the given Feather code to a fresh project directory, and returns a database for it.
+    /// This is only needed to test how the database interacts with files on disk.
+    fn database_on_disk(name: &str, code: &str) -> (FeatherDatabase, Source) {
+        let project_root = std::env::temp_dir().join(format!("feather_test_{name}"));
+        std::fs::create_dir_all(&project_root).unwrap();
+        std::fs::write(project_root.join(name).with_extension("ftr"), code).unwrap();
+        let (db, _rx) = FeatherDatabase::new(project_root);
+        let path = Path::new(&db, vec![Str::new(&db, name.to_owned())]);
+        let source = Source::new(&db, path, SourceType::Feather);
+        (db, source)
+    }
+
+    #[test]
+    fn intro_fields_keep_their_values() {
+        let (db, source) = database_with_module(
+            "intro_fields",
+            "module intro_fields\n\
+            def pair: inst Pair =\n\
+            intro Pair / Pair { first = inst A, second = inst B, }\n",
+        );
+        let module = feather_parser::parse_module(&db, source)
+            .value()
+            .cloned()
+            .expect("module should parse");
+        let body = module.definitions[0].contents.body.unwrap();
+        match body.data(&db) {
+            ExpressionData::Intro { fields, .. } => {
+                let fields = fields
+                    .into_iter()
+                    .map(|(name, value)| (name.text(&db).clone(), value.data(&db)))
+                    .collect::<Vec<_>>();
+                assert_eq!(fields.len(), 2);
+                assert_eq!(fields[0].0, "first");
+                assert_eq!(fields[1].0, "second");
+                assert!(
+                    matches!(fields[0].1, ExpressionData::Inst { path, .. } if path.display(&db) == "A")
+                );
+                assert!(
+                    matches!(fields[1].1, ExpressionData::Inst { path, .. } if path.display(&db) == "B")
+                );
+            }
+            _ => panic!("expected an intro expression"),
+        }
+    }
+
+    #[test]
+    fn error_spans_are_byte_offsets() {
+        let code = "module lambdas\ndef λλλ: Sort 0 = λλλx\n";
+        let (db, source) = database_with_module("lambdas", code);
+        let mut errors = Vec::new();
+        feather_parser::parse_module(&db, source)
+            .clone()
+            .map_errs(|err| errors.push(err));
+        let start = code.rfind("λλλx").unwrap();
+        let expected = Span::from(start..start + "λλλx".len());
+        match errors.as_slice() {
+            [ParseError::UnknownVariable { label_span, .. }] => {
+                assert_eq!(*label_span, expected)
+            }
+            _ => panic!("expected a single unknown variable error, found {errors:?}"),
+        }
+    }
+
+    #[test]
+    fn format_expression_lays_out_atoms() {
+        let (db, source) = database_with_module(
+            "format_expression",
+            "module format_expression\n\
+            def f: for (x: Sort 0) -> Sort 0 =\n    fun (x: Sort 0) -> let y = x; y\n",
+        );
+        let module = feather_parser::parse_module(&db, source)
+            .value()
+            .cloned()
+            .expect("module should parse");
+        let def = &module.definitions[0].contents;
+        assert_eq!(
+            kernel::Db::format_expression(&db, def.ty),
+            "for (x: Sort 0) -> Sort 0"
+        );
+        assert_eq!(
+            kernel::Db::format_expression(&db, def.body.unwrap()),
+            "fun (x: Sort 0) -> let y = x;\ny"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn formatted_expressions_parse_to_themselves(shape in arbitrary_expression()) {
+            let (db, _) = database_with_module("roundtrip", "module roundtrip\n");
+            let code = format!(
+                "module roundtrip\ndef test: Sort 0 =\n{}\n",
+                kernel::Db::format_expression(&db, shape.build(&db))
+            );
+            let (db, source) = database_with_module("roundtrip", &code);
+            let body = feather_parser::parse_module(&db, source)
+                .value()
+                .and_then(|module| module.definitions().first())
+                .and_then(|definition| definition.contents.body);
+            prop_assert!(
+                body.map_or(false, |body| body.alpha_eq(shape.build(&db), &db)),
+                "the formatted expression did not parse to itself:\n{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn expression_debug_shows_the_tree() {
+        let (db, source) = database_with_module(
+            "expression_debug",
+            "module expression_debug\ndef id: for (x: Sort 0) -> Sort 0 = fun (x: Sort 0) -> x\n",
+        );
+        let module = feather_parser::parse_module(&db, source)
+            .value()
+            .cloned()
+            .expect("module should parse");
+        let body = module.definitions()[0].contents.body.unwrap();
+        assert_eq!(
+            format!("{:?}", body.debug(&db)),
+            "Lambda(Binder { structure: BinderStructure { name: \"x\", ty: Sort(0), usage: Present, \
+            argument_style: Explicit, invocation_style: Once }, body: Local(#0) })"
+        );
+    }
+
+    #[test]
+    fn module_accessors() {
+        let (db, source) = database_with_module(
+            "module_accessors",
+            "module module_accessors\ndef a: Sort 0 = Sort 0\ndef b: Sort 1 = Sort 0\n",
+        );
+        let module = feather_parser::parse_module(&db, source)
+            .value()
+            .cloned()
+            .expect("module should parse");
+        assert_eq!(module.path().contents, source.path(&db));
+        assert_eq!(module.definitions().len(), 2);
+        let b = module
+            .definition_named(Str::new(&db, "b".to_owned()))
+            .expect("b should be defined");
+        assert_eq!(*b, module.definitions()[1].contents);
+        assert!(module
+            .definition_named(Str::new(&db, "c".to_owned()))
+            .is_none());
+    }
+
+    #[test]
+    fn module_outline_lists_broken_definitions() {
+        let code =
+            "module outline\ndef a: Sort 0 = Sort 0\ndef b: Sort 0 = y\ndef c: Sort 0 = (inst y\n";
+        let (db, source) = database_with_module("outline", code);
+        let outline = feather_parser::module_outline(&db, source)
+            .value()
+            .cloned()
+            .expect("outline should be produced");
+        let names = outline
+            .iter()
+            .map(|name| name.contents.text(&db).as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c"]);
+        let start = code.find("b:").unwrap();
+        assert_eq!(
+            outline[1].provenance.unwrap().span,
+            Span::from(start..start + 1)
+        );
+    }
+
+    #[test]
+    fn holes_parse() {
+        let (db, source) = database_with_module("holes", "module holes\ndef x: Sort 0 = f _ _\n");
+        let module = feather_parser::parse_module(&db, source)
+            .value()
+            .cloned()
+            .expect("module should parse");
+        let body = module.definitions[0].contents.body.unwrap();
+        let (left, second) = match body.data(&db) {
+            ExpressionData::Apply { left, right } => (left, right),
+            _ => panic!("expected an application"),
+        };
+        let first = match left.data(&db) {
+            ExpressionData::Apply { right, .. } => right,
+            _ => panic!("expected an application"),
+        };
+        match (first.data(&db), second.data(&db)) {
+            (ExpressionData::Hole(first), ExpressionData::Hole(second)) => {
+                assert_ne!(first.id, second.id);
+                assert!(
+                    matches!(first.ty.data(&db), ExpressionData::Hole(ty) if ty.id != first.id && ty.id != second.id)
+                );
+            }
+            _ => panic!("expected two holes"),
+        }
+    }
+
+    #[test]
+    fn syntax_errors_describe_their_context() {
+        let code = "module syntax_errors\ndef x: Sort 0 = (inst y\n";
+        let (db, source) = database_with_module("syntax_errors", code);
+        let mut errors = Vec::new();
+        feather_parser::parse_module(&db, source)
+            .clone()
+            .map_err(|err| errors.push(err))
+            .map_errs(|err| errors.push(err));
+        assert!(
+            errors.iter().any(|err| matches!(
+                err,
+                ParseError::MissingSyntax { message, .. } if message.starts_with("expected `)`")
+            )),
+            "expected a missing `)` error, found {errors:?}"
+        );
+    }
+
+    #[test]
+    fn syntax_errors_only_skip_their_definition() {
+        let (db, source) = database_with_module(
+            "recovery",
+            "module recovery\ndef good: Sort 0 = inst y\ndef bad: Sort 0 = (inst y\n",
+        );
+        let result = feather_parser::parse_module(&db, source);
+        let module = result.value().expect("module should parse");
+        let names = module
+            .definitions
+            .iter()
+            .map(|def| def.contents.name.contents.text(&db).clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["good"]);
+        assert_ne!(result.non_fatal().len(), 0);
+    }
+
+    #[test]
+    fn deleted_files_are_noticed_until_recreated() {
+        let (mut db, source) = database_on_disk("deleted", "module deleted\n");
+        assert!(files::source(&db, source).value().is_some());
+
+        let path = std::env::temp_dir()
+            .join("feather_test_deleted/deleted.ftr")
+            .canonicalize()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        db.process_file_event(DebouncedEvent {
+            path: path.clone(),
+            kind: DebouncedEventKind::Any,
+        })
+        .unwrap();
+        assert!(files::source(&db, source).value().is_none());
+
+        // Re-creating the file is noticed even though `source` last found it missing.
+        std::fs::write(&path, "module deleted\ndef a: Sort 0 = Sort 0\n").unwrap();
+        db.process_file_event(DebouncedEvent {
+            path,
+            kind: DebouncedEventKind::Any,
+        })
+        .unwrap();
+        assert_eq!(
+            files::source(&db, source).value().unwrap().as_str(),
+            "module deleted\ndef a: Sort 0 = Sort 0\n"
+        );
+    }
+
+    #[test]
+    fn overlays_shadow_files_on_disk() {
+        let (mut db, source) = database_on_disk("overlay", "module overlay\n");
+        assert_eq!(
+            files::source(&db, source).value().unwrap().as_str(),
+            "module overlay\n"
+        );
+
+        db.set_overlay(
+            "overlay.ftr".into(),
+            "module overlay\ndef a: Sort 0 = Sort 0\n".into(),
+        );
+        assert_eq!(
+            files::source(&db, source).value().unwrap().as_str(),
+            "module overlay\ndef a: Sort 0 = Sort 0\n"
+        );
+    }
+
+    #[test]
+    fn overlays_create_missing_files() {
+        let (mut db, _) = database_with_module("overlay_missing", "module overlay_missing\n");
+        let path = Path::new(&db, vec![Str::new(&db, "unsaved".to_owned())]);
+        let source = Source::new(&db, path, SourceType::Feather);
+        assert!(files::source(&db, source).value().is_none());
+
+        db.set_overlay("unsaved.ftr".into(), "module unsaved\n".into());
+        assert_eq!(
+            files::source(&db, source).value().unwrap().as_str(),
+            "module unsaved\n"
+        );
+    }
+
+    #[test]
+    fn definitions_resolve_by_path() {
+        let (db, source) = database_with_module(
+            "definitions",
+            "module definitions\ndef a: Sort 0 = Sort 0\ndef b: Sort 0 = Sort 0\n",
+        );
+        let path = source.path(&db);
+        let b = kernel::get_definition(&db, path.with(&db, Str::new(&db, "b".to_owned())));
+        assert_eq!(b.value().unwrap().name.contents.text(&db), "b");
+
+        let c = kernel::get_definition(&db, path.with(&db, Str::new(&db, "c".to_owned())));
+        assert!(c.value().is_none());
+    }
+
+    #[test]
+    fn definitions_resolve_across_modules() {
+        let (mut db, source) = database_with_module(
+            "imports",
+            "module imports\n\
+            def a: Sort 1 = inst exports::b\n\
+            def c: Sort 1 = inst exports::missing\n\
+            def d: Sort 1 = inst missing::b\n",
+        );
+        db.set_overlay(
+            "exports.ftr".into(),
+            "module exports\ndef b: Sort 1 = Sort 0\n".into(),
+        );
+        let errors = |name: &str| {
+            let path = source.path(&db).with(&db, Str::new(&db, name.to_owned()));
+            let (_, reports) = kernel::certify_definition(&db, path).clone().to_reports();
+            reports
+                .iter()
+                .map(|report| report.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(errors("a").is_empty());
+        assert_eq!(
+            errors("c"),
+            ["could not find a type correct definition named exports::missing"]
+        );
+        assert_eq!(
+            errors("d"),
+            ["could not find the module missing containing missing::b"]
+        );
+
+        let missing = Path::parse(&db, "missing::b").unwrap();
+        let (_, reports) = kernel::get_definition(&db, missing).clone().to_reports();
+        assert!(reports[0]
+            .to_string()
+            .starts_with("could not find module missing"));
+    }
+
+    #[test]
+    fn check_project_certifies_every_module() {
+        let (mut db, _) =
+            database_with_module("project", "module project\ndef a: Sort 1 = Sort 0\n");
+        assert!(db.check_project().is_ok());
+
+        db.set_overlay(
+            "nested/inner.ftr".into(),
+            "module nested::inner\ndef b: Sort 1 = Sort 0\ndef c: Sort 0 = Sort 0\n".into(),
+        );
+        let (value, reports) = db.check_project().to_reports();
+        assert!(value.is_none());
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            reports[0].to_string(),
+            "universe mismatch: expected Sort 0, found Sort 1"
+        );
+    }
+
+    #[test]
+    fn cyclic_definitions_are_reported() {
+        let (db, source) = database_with_module(
+            "cycle",
+            "module cycle\ndef a: Sort 0 = inst cycle::b\ndef b: Sort 0 = inst cycle::a\n",
+        );
+        let a = source.path(&db).with(&db, Str::new(&db, "a".to_owned()));
+        let (value, reports) = kernel::certify_definition(&db, a).clone().to_reports();
+        assert!(value.is_none());
+        assert!(reports[0]
+            .to_string()
+            .contains("cycle::a -> cycle::b -> cycle::a"));
+    }
+
+    #[test]
+    fn type_errors_point_at_sub_expressions() {
+        let code = "module located\ndef a: Sort 1 = fun (x : Sort 0) => inst missing\n";
+        let (db, source) = database_with_module("located", code);
+        let a = source.path(&db).with(&db, Str::new(&db, "a".to_owned()));
+        let (value, reports) = kernel::certify_definition(&db, a).clone().to_reports();
+        assert!(value.is_none());
+        let labels = reports[0]
+            .labels()
+            .expect("the error should have a label")
+            .collect::<Vec<_>>();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].offset(), code.find("inst missing").unwrap());
+        assert_eq!(labels[0].len(), "inst missing".len());
+    }
+
+    #[test]
+    fn errors_show_definitions_in_other_modules() {
+        let (mut db, source) =
+            database_with_module("related", "module related\ndef a: Sort 0 = inst other::b\n");
+        let other = "module other\ndef b: Sort 1 = Sort 0\n";
+        db.set_overlay("other.ftr".into(), other.to_owned());
+        let a = source.path(&db).with(&db, Str::new(&db, "a".to_owned()));
+        let (_, reports) = kernel::certify_definition(&db, a).clone().to_reports();
+        assert_eq!(
+            reports[0].to_string(),
+            "universe mismatch: expected Sort 0, found Sort 1"
+        );
+        let related = reports[0]
+            .related()
+            .expect("the error should show where other::b is defined")
+            .collect::<Vec<_>>();
+        assert_eq!(related.len(), 1);
+        assert_eq!(related[0].to_string(), "other::b is defined here");
+        let labels = related[0].labels().unwrap().collect::<Vec<_>>();
+        assert_eq!(labels[0].offset(), other.find("b:").unwrap());
+    }
+
+    #[test]
+    fn unsolved_holes_are_reported() {
+        let code = "module unsolved\ndef a: Sort 1 = _\n";
+        let (db, source) = database_with_module("unsolved", code);
+        let a = source.path(&db).with(&db, Str::new(&db, "a".to_owned()));
+        let (value, reports) = kernel::certify_definition(&db, a).clone().to_reports();
+        assert!(value.is_none());
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].to_string().starts_with("could not infer"));
+        let labels = reports[0].labels().unwrap().collect::<Vec<_>>();
+        assert_eq!(labels[0].offset(), code.find('_').unwrap());
+    }
+
+    #[test]
+    fn axioms_are_certified() {
+        let (db, source) = database_with_module(
+            "axioms",
+            "module axioms\ndef A: Sort 1\ndef x: inst A\ndef y: inst A = inst x\n",
+        );
+        let path = |name: &str| source.path(&db).with(&db, Str::new(&db, name.to_owned()));
+        for name in ["A", "x", "y"] {
+            let (value, reports) = kernel::certify_definition(&db, path(name))
+                .clone()
+                .to_reports();
+            assert!(reports.is_empty(), "{name}: {reports:?}");
+            assert!(value.is_some());
+        }
+        let x = kernel::get_certified_definition(&db, path("x"))
+            .clone()
+            .unwrap();
+        assert!(x.def().is_axiom());
+        assert_eq!(x.reducibility(), Reducibility::Irreducible);
+        assert!(Expression::new_inst(&db, path("x"))
+            .unfold_definition(&db)
+            .is_none());
+    }
+
+    #[test]
+    fn universe_mismatches_are_reported() {
+        let (db, source) =
+            database_with_module("universes", "module universes\ndef a: Sort 0 = Sort 0\n");
+        let a = source.path(&db).with(&db, Str::new(&db, "a".to_owned()));
+        let (value, reports) = kernel::certify_definition(&db, a).clone().to_reports();
+        assert!(value.is_none());
+        assert_eq!(
+            reports[0].to_string(),
+            "universe mismatch: expected Sort 0, found Sort 1"
+        );
+    }
+
+    #[test]
+    fn match_expressions_are_unsupported() {
+        let (db, source) = database_with_module(
+            "matches",
+            "module matches\n\
+            def A: Sort 1\n\
+            def a: inst A\n\
+            def m: Sort 0 = match inst a return fun (x: inst A) -> Sort 0 { v -> inst A, }\n",
+        );
+        let m = source.path(&db).with(&db, Str::new(&db, "m".to_owned()));
+        let (value, reports) = kernel::certify_definition(&db, m).clone().to_reports();
+        assert!(value.is_none());
+        assert_eq!(
+            reports[0].to_string(),
+            "match expressions are not yet supported by the type checker"
+        );
+    }
+
+    #[test]
+    fn intro_expressions_are_unsupported() {
+        // If `intro` were accepted without the declaration of its inductive type,
+        // `proof` would prove every proposition.
+        let (db, source) = database_with_module(
+            "intros",
+            "module intros\n\
+            def False: Sort 0 = for (p: Sort 0) -> p\n\
+            def proof: inst False = intro False/x {}\n",
+        );
+        let proof = source
+            .path(&db)
+            .with(&db, Str::new(&db, "proof".to_owned()));
+        let (value, reports) = kernel::certify_definition(&db, proof).clone().to_reports();
+        assert!(value.is_none());
+        assert_eq!(
+            reports[0].to_string(),
+            "intro expressions are not yet supported by the type checker"
+        );
+    }
+
+    #[test]
+    fn universe_polymorphic_definitions() {
+        let (db, source) = database_with_module(
+            "polymorphic",
+            "module polymorphic\n\
+            def id.{u}: for (A: Sort u) -> for (x: A) -> A = fun (A: Sort u) -> fun (x: A) -> x\n\
+            def small: for (x: Sort 0) -> Sort 0 = inst id.{1} (Sort 0)\n\
+            def large: for (x: Sort 1) -> Sort 1 = inst id.{2} (Sort 1)\n\
+            def missing: for (x: Sort 0) -> Sort 0 = inst id (Sort 0)\n\
+            def unbound: Sort v+1 = Sort v\n",
+        );
+        let path = |name: &str| source.path(&db).with(&db, Str::new(&db, name.to_owned()));
+        let certify = |name: &str| {
+            kernel::certify_definition(&db, path(name))
+                .clone()
+                .to_reports()
+        };
+        for name in ["id", "small", "large"] {
+            let (value, reports) = certify(name);
+            assert!(reports.is_empty(), "{name}: {reports:?}");
+            assert!(value.is_some());
+        }
+
+        let (value, reports) = certify("missing");
+        assert!(value.is_none());
+        assert_eq!(
+            reports[0].to_string(),
+            "polymorphic::id has 1 universe parameters, but was given 0 universe levels"
+        );
+
+        let (value, reports) = certify("unbound");
+        assert!(value.is_none());
+        assert_eq!(
+            reports[0].to_string(),
+            "the universe variable v is not a universe parameter of this definition"
+        );
+    }
+
+    #[test]
+    fn type_checking_reports_running_out_of_fuel() {
+        let (db, source) = database_with_module(
+            "diverging_type",
+            "module diverging_type\n\
+            def loop: for (x: Sort 1) -> Sort 1 = fix (x: Sort 1) => Sort 1 with f; f x\n\
+            def bad: inst loop (Sort 0) = Sort 0\n",
+        );
+        let bad = source.path(&db).with(&db, Str::new(&db, "bad".to_owned()));
+        let (value, reports) = kernel::certify_definition(&db, bad).clone().to_reports();
+        assert!(value.is_none());
+        // Rather than a type mismatch, the user is told that reduction gave up.
+        assert!(reports[0]
+            .to_string()
+            .ends_with("after 100000 reduction steps"));
+    }
 }
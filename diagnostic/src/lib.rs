@@ -37,7 +37,8 @@ impl Error for Void {}
 impl Diagnostic for Void {}
 
 /// A diagnostic result that tracks both fatal and non-fatal diagnostics.
-/// Non-fatal diagnostics can represent warnings, or simply advice given to the user.
+/// Non-fatal diagnostics can represent warnings, or simply advice given to the user,
+/// and each is tagged with a [`Severity`] to distinguish the two.
 ///
 /// This structure has two states, `ok` and `err`.
 /// In the `ok` state, there is a value of type `T`, and a list of non-fatal diagnostics of type `N`.
@@ -53,9 +54,31 @@ impl Diagnostic for Void {}
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Dr<T, E = DynamicDiagnostic, N = Void> {
     value: Result<T, E>,
-    non_fatal: Vec<N>,
+    /// The non-fatal diagnostics, each paired with its severity, in the order they were produced.
+    non_fatal: Vec<(N, Severity)>,
 }
 
+/// The severity of a non-fatal diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// A problem that does not stop compilation, but that the user should probably fix.
+    Warning,
+    /// A suggestion to the user, which may be safely ignored.
+    Advice,
+}
+
+impl From<Severity> for miette::Severity {
+    fn from(value: Severity) -> Self {
+        match value {
+            Severity::Warning => miette::Severity::Warning,
+            Severity::Advice => miette::Severity::Advice,
+        }
+    }
+}
+
+/// A diagnostic result whose non-fatal diagnostics, and by default its fatal error, may be of any type.
+/// This is the usual return type of queries that combine diagnostics from several crates.
+/// A [`Dr`] with concrete diagnostic types can be converted into a [`DynDr`] using [`Dr::to_dynamic`].
 pub type DynDr<T, E = DynamicDiagnostic> = Dr<T, E, DynamicDiagnostic>;
 
 impl<T, E, N> Debug for Dr<T, E, N>
@@ -89,6 +112,30 @@ impl<T, E, N> Dr<T, E, N> {
         }
     }
 
+    /// Creates a new diagnostic result from a [`Result`], with no non-fatal messages.
+    /// An [`Ok`] value produces the `ok` state, and an [`Err`] value produces the `err` state.
+    pub fn from_result(result: Result<T, E>) -> Self {
+        Dr {
+            value: result,
+            non_fatal: Vec::new(),
+        }
+    }
+
+    /// Creates a new diagnostic result containing the given value if it exists,
+    /// or the given fatal error if it does not.
+    pub fn from_option(value: Option<T>, error: E) -> Self {
+        Self::from_result(value.ok_or(error))
+    }
+
+    /// Splits this diagnostic result into a plain [`Result`] and its non-fatal diagnostics.
+    /// The severities of the non-fatal diagnostics are discarded.
+    pub fn into_result(self) -> (Result<T, E>, Vec<N>) {
+        (
+            self.value,
+            self.non_fatal.into_iter().map(|(diag, _)| diag).collect(),
+        )
+    }
+
     /// Returns true if this diagnostic result is in the `ok` state.
     /// In this case, there is a value of type `T` contained in this struct.
     pub fn is_ok(&self) -> bool {
@@ -106,11 +153,42 @@ impl<T, E, N> Dr<T, E, N> {
         self.value.as_ref().ok()
     }
 
+    /// Retrieves the non-fatal diagnostics accumulated so far, in the order they were produced.
+    pub fn non_fatal(&self) -> impl ExactSizeIterator<Item = &N> {
+        self.non_fatal.iter().map(|(diag, _)| diag)
+    }
+
+    /// Retrieves the severity of each non-fatal diagnostic, in the same order as [`Dr::non_fatal`].
+    pub fn severities(&self) -> impl ExactSizeIterator<Item = Severity> + '_ {
+        self.non_fatal.iter().map(|(_, severity)| *severity)
+    }
+
+    /// Returns the number of non-fatal diagnostics with severity [`Severity::Warning`]
+    /// contained in this diagnostic result.
+    /// Advice is not counted.
+    pub fn warning_count(&self) -> usize {
+        self.severities()
+            .filter(|severity| *severity == Severity::Warning)
+            .count()
+    }
+
+    /// Calls the given function on each non-fatal diagnostic, whatever its severity,
+    /// then returns this diagnostic result unchanged.
+    /// This is useful for side effects such as logging or counting diagnostics.
+    pub fn inspect_warnings(self, f: impl FnMut(&N)) -> Self {
+        self.non_fatal().for_each(f);
+        self
+    }
+
     /// Converts from [`Dr<T, E, N>`] to [`Dr<&T, &E, &N>`].
     pub fn as_ref(&self) -> Dr<&T, &E, &N> {
         Dr {
             value: self.value.as_ref(),
-            non_fatal: self.non_fatal.iter().collect(),
+            non_fatal: self
+                .non_fatal
+                .iter()
+                .map(|(diag, severity)| (diag, *severity))
+                .collect(),
         }
     }
 
@@ -134,10 +212,14 @@ impl<T, E, N> Dr<T, E, N> {
 
     /// Applies the given operation to the contained error, if it exists.
     /// If this diagnostic result is in the `ok` state, no action is performed.
-    pub fn map_errs<O>(self, op: impl FnMut(N) -> O) -> Dr<T, E, O> {
+    pub fn map_errs<O>(self, mut op: impl FnMut(N) -> O) -> Dr<T, E, O> {
         Dr {
             value: self.value,
-            non_fatal: self.non_fatal.into_iter().map(op).collect(),
+            non_fatal: self
+                .non_fatal
+                .into_iter()
+                .map(|(diag, severity)| (op(diag), severity))
+                .collect(),
         }
     }
 
@@ -155,16 +237,22 @@ impl<T, E, N> Dr<T, E, N> {
             non_fatal: self
                 .non_fatal
                 .into_iter()
-                .map(DynamicDiagnostic::new)
+                .map(|(diag, severity)| (DynamicDiagnostic::new(diag), severity))
                 .collect(),
         }
     }
 
-    /// Produces a new diagnostic result by adding the given non-fatal diagnostic.
+    /// Produces a new diagnostic result by adding the given non-fatal diagnostic as a warning.
     /// If this diagnostic result is in the `err` state, no action is performed.
-    pub fn with(mut self, diag: N) -> Self {
+    pub fn with(self, diag: N) -> Self {
+        self.with_severity(diag, Severity::Warning)
+    }
+
+    /// Produces a new diagnostic result by adding the given non-fatal diagnostic with the given severity.
+    /// If this diagnostic result is in the `err` state, no action is performed.
+    pub fn with_severity(mut self, diag: N, severity: Severity) -> Self {
         if self.is_ok() {
-            self.non_fatal.push(diag);
+            self.non_fatal.push((diag, severity));
         }
         self
     }
@@ -187,7 +275,48 @@ impl<T, E, N> Dr<T, E, N> {
         }
     }
 
+    /// Combines two independent diagnostic results into a diagnostic result containing a pair.
+    /// If both are in the `ok` state, the non-fatal error messages of both are combined.
+    /// Otherwise, the first fatal error is returned, together with the non-fatal error messages
+    /// produced before it, as in [`Dr::bind`].
+    pub fn zip<U>(self, other: Dr<U, E, N>) -> Dr<(T, U), E, N> {
+        self.bind(|left| other.map(|right| (left, right)))
+    }
+
+    /// Folds the given items into an accumulated value, starting with `init`.
+    /// Stops at the first fatal error, without calling `f` on any later items.
+    /// The non-fatal diagnostics produced before the fatal error are preserved.
+    pub fn try_fold<I>(
+        items: impl IntoIterator<Item = I>,
+        init: T,
+        mut f: impl FnMut(T, I) -> Dr<T, E, N>,
+    ) -> Self {
+        let mut result = Dr::new(init);
+        for item in items {
+            result = result.bind(|acc| f(acc, item));
+            if result.is_err() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Like [`Dr::sequence`], but each diagnostic result is computed by calling a function.
+    /// Once a fatal error is produced, the remaining functions are not called.
+    pub fn sequence_lazy(
+        results: impl IntoIterator<Item = impl FnOnce() -> Dr<T, E, N>>,
+    ) -> Dr<Vec<T>, E, N> {
+        Dr::try_fold(results, Vec::new(), |mut list, result| {
+            result().map(|value| {
+                list.push(value);
+                list
+            })
+        })
+    }
+
     /// Combines a list of diagnostic results into a single result by binding them all together.
+    /// Every diagnostic result is computed, even after a fatal error;
+    /// use [`Dr::sequence_lazy`] to avoid this.
     pub fn sequence(results: impl IntoIterator<Item = Dr<T, E, N>>) -> Dr<Vec<T>, E, N> {
         results.into_iter().fold(Dr::new(Vec::new()), |acc, i| {
             acc.bind(|mut list| {
@@ -207,20 +336,33 @@ impl<T, E> Dr<T, E, E> {
     /// This choice makes the rendered order of the errors correct.
     pub fn new_err_many(mut errors: Vec<E>) -> Self {
         assert!(!errors.is_empty());
+        let value = Err(errors.pop().unwrap());
         Self {
-            value: Err(errors.pop().unwrap()),
-            non_fatal: errors,
+            value,
+            non_fatal: errors
+                .into_iter()
+                .map(|err| (err, Severity::Warning))
+                .collect(),
         }
     }
 
     /// Converts a failed diagnostic into a successful diagnostic by wrapping
     /// the contained value in an `Option`.
-    pub fn unfail(mut self) -> Dr<Option<T>, E, E> {
+    pub fn unfail(self) -> Dr<Option<T>, E, E> {
+        self.map(Some).recover(|err| (None, err))
+    }
+
+    /// Converts a failed diagnostic into a successful diagnostic.
+    /// If this diagnostic result is in the `err` state, the given function is called on the fatal error
+    /// to produce a fallback value and a diagnostic, which is then recorded as non-fatal.
+    /// If this diagnostic result is in the `ok` state, no action is performed.
+    pub fn recover(mut self, f: impl FnOnce(E) -> (T, E)) -> Self {
         let value = match self.value {
-            Ok(value) => Some(value),
+            Ok(value) => value,
             Err(err) => {
-                self.non_fatal.push(err);
-                None
+                let (value, diag) = f(err);
+                self.non_fatal.push((diag, Severity::Warning));
+                value
             }
         };
         Dr {
@@ -229,6 +371,22 @@ impl<T, E> Dr<T, E, E> {
         }
     }
 
+    /// If this diagnostic result contains any [`Severity::Warning`] diagnostics, converts it to the `err` state.
+    /// The last warning becomes the fatal error, and all other diagnostics remain non-fatal.
+    /// This can be used to implement a mode in which warnings are treated as errors.
+    pub fn promote_warnings_to_errors(mut self) -> Self {
+        if self.is_ok() {
+            if let Some(position) = self
+                .non_fatal
+                .iter()
+                .rposition(|(_, severity)| *severity == Severity::Warning)
+            {
+                self.value = Err(self.non_fatal.remove(position).0);
+            }
+        }
+        self
+    }
+
     /// Combines a list of diagnostic results into a single result by binding them all together.
     /// Any failed diagnostics will be excluded from the output, but their error messages will remain.
     /// Therefore, this function will never fail - it might just produce an empty list as its output.
@@ -287,6 +445,16 @@ impl DynamicDiagnostic {
             inner: Box::new(value),
         }
     }
+
+    /// Returns the contained diagnostic if it has type `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        (&*self.inner as &dyn Any).downcast_ref()
+    }
+
+    /// Returns the contained diagnostic if it has type `T`.
+    pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
+        (&mut *self.inner as &mut dyn Any).downcast_mut()
+    }
 }
 
 impl PartialEq for DynamicDiagnostic {
@@ -357,20 +525,310 @@ impl Diagnostic for DynamicDiagnostic {
     }
 }
 
+/// A non-fatal diagnostic, rendered with the severity it was given in a [`Dr`].
+#[derive(Debug)]
+struct WithSeverity {
+    inner: DynamicDiagnostic,
+    severity: Severity,
+}
+
+impl Display for WithSeverity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.inner, f)
+    }
+}
+
+impl Error for WithSeverity {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+impl Diagnostic for WithSeverity {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.inner.code()
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(self.severity.into())
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.inner.help()
+    }
+
+    fn url<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.inner.url()
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        self.inner.source_code()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        self.inner.labels()
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        self.inner.related()
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        self.inner.diagnostic_source()
+    }
+}
+
 impl<T> Dr<T, DynamicDiagnostic, DynamicDiagnostic> {
-    /// Prints all of the diagnostic messages contained in this diagnostic result.
+    /// Converts all of the diagnostic messages contained in this diagnostic result into reports,
+    /// in the order they should be rendered.
+    /// Non-fatal diagnostics are rendered with their [`Severity`].
+    /// The contained value is returned alongside the reports, if present.
+    pub fn to_reports(self) -> (Option<T>, Vec<Report>) {
+        let mut reports = self
+            .non_fatal
+            .into_iter()
+            .map(|(inner, severity)| Report::new(WithSeverity { inner, severity }))
+            .collect::<Vec<_>>();
+
+        match self.value {
+            Ok(value) => (Some(value), reports),
+            Err(err) => {
+                reports.push(Report::new(err));
+                (None, reports)
+            }
+        }
+    }
+
+    /// Writes all of the diagnostic messages contained in this diagnostic result to the given writer,
+    /// rendered according to the given configuration.
+    /// Then, return the contained value, if present.
+    pub fn write_reports(
+        self,
+        w: &mut impl std::io::Write,
+        config: ReportConfig,
+    ) -> std::io::Result<Option<T>> {
+        let (value, reports) = self.to_reports();
+        for report in reports {
+            writeln!(w, "{}", config.render(&report))?;
+        }
+        Ok(value)
+    }
+
+    /// Prints all of the diagnostic messages contained in this diagnostic result to standard error,
+    /// using the default [`ReportConfig`].
     /// Then, return the contained value, if present.
     pub fn print_reports(self) -> Option<T> {
-        for diag in self.non_fatal {
-            println!("{:?}", Report::new(diag));
+        self.write_reports(&mut std::io::stderr(), ReportConfig::default())
+            .expect("failed printing to stderr")
+    }
+}
+
+/// Controls how [`Dr::write_reports`] renders diagnostic messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportConfig {
+    /// Whether to use ANSI escape codes to colour the output.
+    /// This is only used by [`ReportStyle::Graphical`].
+    pub color: bool,
+    pub style: ReportStyle,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self {
+            color: true,
+            style: ReportStyle::Graphical,
         }
+    }
+}
 
-        match self.value {
-            Ok(value) => Some(value),
-            Err(err) => {
-                println!("{:?}", Report::new(err));
-                None
+/// The handler used to render diagnostic messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportStyle {
+    /// Renders diagnostics with source code snippets and arrows pointing to labels,
+    /// using [`miette::GraphicalReportHandler`].
+    Graphical,
+    /// Renders diagnostics as plain text that can be read aloud by a screen reader,
+    /// using [`miette::NarratableReportHandler`].
+    Narratable,
+}
+
+impl ReportConfig {
+    /// Renders the given report according to this configuration.
+    fn render(self, report: &Report) -> String {
+        let mut output = String::new();
+        let result = match self.style {
+            ReportStyle::Graphical => {
+                let theme = if self.color {
+                    miette::GraphicalTheme::unicode()
+                } else {
+                    miette::GraphicalTheme::unicode_nocolor()
+                };
+                miette::GraphicalReportHandler::new_themed(theme)
+                    .render_report(&mut output, &**report)
             }
+            ReportStyle::Narratable => {
+                miette::NarratableReportHandler::new().render_report(&mut output, &**report)
+            }
+        };
+        result.expect("should not error while writing to a string");
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, error::Error, fmt::Display};
+
+    use miette::Diagnostic;
+
+    use crate::{Dr, DynamicDiagnostic, ReportConfig, ReportStyle, Severity};
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Message(&'static str);
+
+    impl Display for Message {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
         }
     }
+
+    impl Error for Message {}
+
+    impl Diagnostic for Message {}
+
+    #[test]
+    fn write_reports() {
+        let result: Dr<(), DynamicDiagnostic, DynamicDiagnostic> =
+            Dr::new(()).with(DynamicDiagnostic::new(Message("a warning")));
+        let mut output = Vec::new();
+        let value = result
+            .write_reports(
+                &mut output,
+                ReportConfig {
+                    color: false,
+                    style: ReportStyle::Narratable,
+                },
+            )
+            .unwrap();
+        assert_eq!(value, Some(()));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("a warning"));
+        assert!(!output.contains('\x1b'));
+    }
+
+    #[test]
+    fn sequence_lazy_short_circuits() {
+        let calls = Cell::new(0);
+        let result: Dr<Vec<i32>, &str, &str> = Dr::sequence_lazy(
+            [Ok(1), Err("first"), Err("second")]
+                .into_iter()
+                .map(|value| {
+                    let calls = &calls;
+                    move || {
+                        calls.set(calls.get() + 1);
+                        match value {
+                            Ok(value) => Dr::new(value).with("warning"),
+                            Err(err) => Dr::new_err(err),
+                        }
+                    }
+                }),
+        );
+        assert_eq!(calls.get(), 2);
+        assert_eq!(result.non_fatal().collect::<Vec<_>>(), [&"warning"]);
+        assert_eq!(result.into_result().0, Err("first"));
+    }
+
+    #[test]
+    fn to_dynamic_preserves_diagnostics() {
+        let result: Dr<(), Message, Message> = Dr::new(())
+            .with(Message("first"))
+            .with(Message("second"))
+            .bind(|()| Dr::new_err(Message("fatal")));
+        let (value, reports) = result.to_dynamic().to_reports();
+        assert_eq!(value, None);
+        assert_eq!(
+            reports
+                .iter()
+                .map(|report| report.to_string())
+                .collect::<Vec<_>>(),
+            ["first", "second", "fatal"]
+        );
+    }
+
+    #[test]
+    fn with_severity() {
+        let result: Dr<(), &str, &str> = Dr::new(())
+            .with("warning")
+            .with_severity("advice", Severity::Advice);
+        assert_eq!(
+            result.non_fatal().collect::<Vec<_>>(),
+            [&"warning", &"advice"]
+        );
+        assert_eq!(
+            result.severities().collect::<Vec<_>>(),
+            [Severity::Warning, Severity::Advice]
+        );
+
+        // Diagnostics added after a fatal error are discarded, along with their severities.
+        let failed: Dr<(), &str, &str> =
+            Dr::new_err("fatal").with_severity("advice", Severity::Advice);
+        assert_eq!(failed.non_fatal().len(), 0);
+        assert_eq!(failed.severities().len(), 0);
+    }
+
+    #[test]
+    fn reports_are_rendered_with_their_severity() {
+        let result: Dr<(), Message, Message> = Dr::new(())
+            .with(Message("warning"))
+            .with_severity(Message("advice"), Severity::Advice)
+            .bind(|()| Dr::new_err(Message("fatal")));
+        let (_, reports) = result.to_dynamic().to_reports();
+        assert_eq!(
+            reports
+                .iter()
+                .map(|report| report.severity())
+                .collect::<Vec<_>>(),
+            [
+                Some(miette::Severity::Warning),
+                Some(miette::Severity::Advice),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn promote_warnings_to_errors() {
+        let result: Dr<(), &str, &str> = Dr::new(())
+            .with("first")
+            .with("second")
+            .with_severity("advice", Severity::Advice)
+            .promote_warnings_to_errors();
+        assert_eq!(
+            result.non_fatal().collect::<Vec<_>>(),
+            [&"first", &"advice"]
+        );
+        assert_eq!(
+            result.severities().collect::<Vec<_>>(),
+            [Severity::Warning, Severity::Advice]
+        );
+        assert_eq!(result.into_result().0, Err("second"));
+
+        // Advice is never promoted to an error.
+        let advice: Dr<(), &str, &str> = Dr::new(())
+            .with_severity("advice", Severity::Advice)
+            .promote_warnings_to_errors();
+        assert!(advice.is_ok());
+        assert_eq!(advice.non_fatal().collect::<Vec<_>>(), [&"advice"]);
+    }
+
+    #[test]
+    fn warning_count_ignores_advice() {
+        let mut seen = Vec::new();
+        let result: Dr<(), &str, &str> = Dr::new(())
+            .with("warning")
+            .with_severity("advice", Severity::Advice)
+            .inspect_warnings(|diag| seen.push(*diag));
+        assert_eq!(result.warning_count(), 1);
+        assert_eq!(seen, ["warning", "advice"]);
+    }
 }
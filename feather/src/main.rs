@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 
 use database::FeatherDatabase;
-use files::{Path, Source, SourceType, Str};
+use files::{Path, Source, SourceType};
 use kernel::Db;
 
 fn main() {
@@ -18,25 +18,24 @@ fn main() {
         .expect("could not set default tracing subscriber");
     tracing::info!("initialised logging with verbosity level {}", log_level);
 
-    let (db, _rx) = FeatherDatabase::new(PathBuf::new());
-    let path = Path::new(
-        &db,
-        vec![
-            Str::new(&db, "test".to_string()),
-            Str::new(&db, "test".to_string()),
-        ],
-    );
+    let (mut db, rx) = FeatherDatabase::new(PathBuf::new());
+    let path = Path::parse(&db, "test::test").expect("path should be valid");
     let source = Source::new(&db, path, SourceType::Feather);
 
-    if let Some(module) = feather_parser::parse_module(&db, source)
+    // Recompile the module whenever it changes on disk.
+    db.run_watch_loop(&rx, |db| compile(db, source));
+}
+
+fn compile(db: &FeatherDatabase, source: Source) {
+    if let Some(module) = feather_parser::parse_module(db, source)
         .to_dynamic()
         .print_reports()
     {
         tracing::info!("successfully parsed module");
-        for definition in &module.definitions {
+        for definition in module.definitions() {
             tracing::info!(
                 "def {}: {} =\n    {}",
-                definition.contents.name.contents.text(&db),
+                definition.contents.name.contents.text(db),
                 db.format_expression(definition.contents.ty),
                 definition
                     .contents
@@ -45,9 +44,5 @@ fn main() {
                     .unwrap_or_else(|| "<no body>".to_owned()),
             );
         }
-        // tracing::info!("{:#?}", result);
     }
-
-    // TODO: <https://github.com/salsa-rs/salsa/blob/master/examples-2022/lazy-input/src/main.rs>
-    // This helps us set up the main loop for language servers.
 }
@@ -19,11 +19,15 @@ module.exports = grammar({
       definition: $ => seq(
         'def',
         field('name', $.identifier),
+        optional(field('universe_params', $.universe_params)),
         ':',
         field('usage', optional('0')),
         field('ty', $._expr),
-        '=',
-        field('body', $._expr),
+        // A definition without a body is an axiom.
+        optional(seq(
+          '=',
+          field('body', $._expr),
+        )),
       ),
 
       path: $ => seq(
@@ -33,7 +37,30 @@ module.exports = grammar({
 
       identifier: $ => /[\pL\pN\pS]+/,
 
-      universe: $ => /[0-9]+/,
+      // A universe level: a number `0`, a variable `u`, or a variable with an offset `u+1`.
+      // These are all parsed as identifiers, since `+` is a valid identifier character.
+      universe: $ => choice(
+        field('level', $.identifier),
+        seq('(', field('inner', $.universe), ')'),
+        seq('succ', field('inner', $.universe)),
+        seq(field('kind', choice('max', 'imax')), field('left', $.universe), field('right', $.universe)),
+      ),
+
+      // The universe variables that a definition is polymorphic over, such as `.{u, v}` in `def id.{u, v}`.
+      universe_params: $ => seq(
+        '.{',
+        field('param', $.identifier),
+        repeat(seq(',', field('param', $.identifier))),
+        '}',
+      ),
+
+      // The universe levels that a polymorphic definition is used at, such as `.{0, u+1}` in `inst id.{0, u+1}`.
+      universe_args: $ => seq(
+        '.{',
+        field('universe', $.universe),
+        repeat(seq(',', field('universe', $.universe))),
+        '}',
+      ),
 
       line_comment: $ => token(
         seq('//', /[^\n]*/),
@@ -55,12 +82,16 @@ module.exports = grammar({
         $.deref,
         $.loan,
         $.take,
+        $.hole,
       ),
 
       paren: $ => seq("(", field('inner', $._expr), ")"),
 
       local: $ => $.identifier,
 
+      // A placeholder for an expression to be inferred by the elaborator.
+      hole: $ => '_',
+
       app: $ => prec.left(10, seq(
         field('left', $._expr),
         field('right', $._expr)
@@ -124,7 +155,11 @@ module.exports = grammar({
 
       sort: $ => seq('Sort', field('universe', $.universe)),
 
-      inst: $ => seq('inst', field('path', $.path)),
+      inst: $ => seq(
+        'inst',
+        field('path', $.path),
+        optional(field('universes', $.universe_args)),
+      ),
 
       intro: $ => seq(
         'intro',
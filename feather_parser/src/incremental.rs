@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use files::Source;
+use tree_sitter::{InputEdit, Parser, Point, Tree};
+
+/// The most recently parsed syntax tree of each source file,
+/// so that the next version of the file can be parsed incrementally.
+///
+/// Tree-sitter reuses the parts of the old tree that were not affected by an edit,
+/// so small changes to a large file can be reparsed quickly.
+#[derive(Debug, Default)]
+pub struct TreeCache {
+    trees: Mutex<HashMap<Source, (Arc<String>, Tree)>>,
+}
+
+impl TreeCache {
+    /// Parses `code`, reusing the last tree parsed for this source if there is one.
+    /// The resulting tree is stored for the next call.
+    pub(crate) fn parse(
+        &self,
+        parser: &mut Parser,
+        source: Source,
+        code: &Arc<String>,
+    ) -> Option<Tree> {
+        let old_tree = self
+            .trees
+            .lock()
+            .unwrap()
+            .get(&source)
+            .map(|(old_code, tree)| {
+                let mut tree = tree.clone();
+                if old_code != code {
+                    tree.edit(&input_edit(old_code, code));
+                }
+                tree
+            });
+
+        let tree = parser.parse(code.as_str(), old_tree.as_ref())?;
+        self.trees
+            .lock()
+            .unwrap()
+            .insert(source, (Arc::clone(code), tree.clone()));
+        Some(tree)
+    }
+
+    /// Forgets the tree stored for this source, for example because its file was deleted.
+    pub fn forget(&self, source: Source) {
+        self.trees.lock().unwrap().remove(&source);
+    }
+}
+
+/// Describes the change from `old` to `new` as a single edit,
+/// replacing everything between their common prefix and their common suffix.
+fn input_edit(old: &str, new: &str) -> InputEdit {
+    let (old_bytes, new_bytes) = (old.as_bytes(), new.as_bytes());
+    let prefix = old_bytes
+        .iter()
+        .zip(new_bytes)
+        .take_while(|(a, b)| a == b)
+        .count();
+    // The suffix may not overlap the prefix in either string.
+    let suffix = old_bytes[prefix..]
+        .iter()
+        .rev()
+        .zip(new_bytes[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_end_byte = old.len() - suffix;
+    let new_end_byte = new.len() - suffix;
+    InputEdit {
+        start_byte: prefix,
+        old_end_byte,
+        new_end_byte,
+        start_position: point(old_bytes, prefix),
+        old_end_position: point(old_bytes, old_end_byte),
+        new_end_position: point(new_bytes, new_end_byte),
+    }
+}
+
+/// The row and byte column of the given byte offset, as used by tree-sitter.
+fn point(text: &[u8], offset: usize) -> Point {
+    let before = &text[..offset];
+    let row = before.iter().filter(|&&byte| byte == b'\n').count();
+    let column = match before.iter().rposition(|&byte| byte == b'\n') {
+        Some(newline) => offset - newline - 1,
+        None => offset,
+    };
+    Point { row, column }
+}
+
+#[cfg(test)]
+mod test {
+    use tree_sitter::Point;
+
+    use super::input_edit;
+
+    #[test]
+    fn edits() {
+        let edit = input_edit("def a: x\ndef b: y\n", "def a: x\ndef bc: y\n");
+        assert_eq!(
+            (edit.start_byte, edit.old_end_byte, edit.new_end_byte),
+            (14, 14, 15)
+        );
+        assert_eq!(edit.start_position, Point { row: 1, column: 5 });
+        assert_eq!(edit.new_end_position, Point { row: 1, column: 6 });
+
+        // Repeated characters must not be counted in both the prefix and the suffix.
+        let edit = input_edit("aaa", "aaaa");
+        assert_eq!(
+            (edit.start_byte, edit.old_end_byte, edit.new_end_byte),
+            (3, 3, 4)
+        );
+    }
+}
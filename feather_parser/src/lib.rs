@@ -1,76 +1,199 @@
 #![feature(trait_upcasting)]
 
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
 
 use diagnostic::{miette::Diagnostic, Dr};
-use files::{Path, Source, SourceData, SourceSpan, Span, Str, WithProvenance};
+use files::{Path, Source, SourceData, SourceSpan, SourceType, Span, Str, WithProvenance};
 use kernel::{
     de_bruijn::DeBruijnIndex,
     definition::Definition,
     expr::{
-        ArgumentStyle, Binder, BinderStructure, BoundVariable, Expression, InvocationStyle,
-        Universe, Usage,
+        ArgumentStyle, Binder, BinderStructure, BoundVariable, Expression, Hole, HoleId,
+        InvocationStyle, Universe, UniverseVar, Usage,
     },
+    vec_map::VecMap,
 };
 use thiserror::Error;
-use tree_sitter::{Node, TreeCursor};
+use tree_sitter::{Node, Tree, TreeCursor};
+
+mod incremental;
+
+pub use incremental::TreeCache;
 
 pub type ParseDr<T> = Dr<T, ParseError, ParseError>;
 
 #[salsa::jar(db = Db)]
-pub struct Jar(parse_module);
-
-pub trait Db: kernel::Db + salsa::DbWithJar<Jar> {}
+pub struct Jar(
+    parse_module,
+    module_outline,
+    module_definition_map,
+    ExpressionSpan,
+    expression_spans,
+);
 
-impl<T> Db for T where T: kernel::Db + salsa::DbWithJar<Jar> + 'static {}
+pub trait Db: kernel::Db + salsa::DbWithJar<Jar> {
+    /// The syntax trees most recently parsed from each source file, used for incremental parsing.
+    fn tree_cache(&self) -> &TreeCache;
+}
 
+/// Parses the given source file into a [`Module`], using the grammar for its [`SourceType`].
 #[tracing::instrument(level = "debug")]
 #[salsa::tracked]
 pub fn parse_module(db: &dyn Db, source: Source) -> Dr<Module, ParseError, ParseError> {
+    match source.ty(db) {
+        SourceType::Feather => parse_feather_module(db, source),
+        // There is no tree-sitter grammar for Quill yet,
+        // so we refuse to parse Quill files rather than parsing them with the wrong grammar.
+        SourceType::Quill => Dr::new_err(ParseError::UnsupportedSourceType {
+            src: source.data(db),
+            extension: SourceType::Quill.extension(),
+        }),
+    }
+}
+
+/// The names of the definitions in the given source file, in the order they were written,
+/// each with the span of the name.
+///
+/// Unlike [`parse_module`], this does not process the types or bodies of definitions,
+/// so it is cheap, and lists the names of definitions that contain syntax errors or fail to parse.
+/// Syntax errors are not reported, except for those that prevent the file from being parsed at all.
+#[salsa::tracked(return_ref)]
+pub fn module_outline(db: &dyn Db, source: Source) -> ParseDr<Vec<WithProvenance<Str>>> {
+    match source.ty(db) {
+        SourceType::Feather => parse_feather_tree(db, source).map(|(code, tree)| {
+            let root_node = tree.root_node();
+            root_node
+                .children_by_field_name("definition", &mut root_node.walk())
+                .filter_map(|node| node.child_by_field_name("name"))
+                .filter(|name| !name.is_missing())
+                .map(|name| process_identifier(db, source, &code, name))
+                .collect()
+        }),
+        SourceType::Quill => Dr::new_err(ParseError::UnsupportedSourceType {
+            src: source.data(db),
+            extension: SourceType::Quill.extension(),
+        }),
+    }
+}
+
+/// The definitions of the given source file, keyed by name, so that they can be looked up quickly.
+/// If a name is defined more than once, the first definition is used.
+#[salsa::tracked(return_ref)]
+pub fn module_definition_map(
+    db: &dyn Db,
+    source: Source,
+) -> Dr<HashMap<Str, Definition>, ParseError, ParseError> {
+    parse_module(db, source).map(|module| {
+        let mut map = HashMap::new();
+        for definition in module.definitions {
+            map.entry(definition.contents.name.contents)
+                .or_insert(definition.contents);
+        }
+        map
+    })
+}
+
+/// The span of source code that an expression was parsed from.
+/// These are accumulated while running [`parse_module`], and collected by [`expression_spans`].
+#[salsa::accumulator]
+pub struct ExpressionSpan((Expression, Span));
+
+/// The spans of source code that each expression in the given source file was parsed from.
+/// Since expressions are interned, an expression that was written several times has several spans.
+/// The spans are listed in the order that they were parsed, so sub-expressions come before the expressions containing them.
+#[salsa::tracked(return_ref)]
+pub fn expression_spans(db: &dyn Db, source: Source) -> HashMap<Expression, Vec<Span>> {
+    let mut spans = HashMap::<_, Vec<_>>::new();
+    for (expr, span) in parse_module::accumulated::<ExpressionSpan>(db, source) {
+        spans.entry(expr).or_default().push(span);
+    }
+    spans
+}
+
+fn parse_feather_module(db: &dyn Db, source: Source) -> ParseDr<Module> {
+    parse_feather_tree(db, source)
+        .bind(|(code, tree)| process_module(db, source, &code, tree.root_node()))
+}
+
+/// Parses the given Feather source file into a syntax tree whose root is a `source_file` node,
+/// returning the tree along with the code it was parsed from.
+fn parse_feather_tree(db: &dyn Db, source: Source) -> ParseDr<(Arc<String>, Tree)> {
     files::source(db, source)
-        .map_err(|_| todo!())
-        .map_errs(|_| todo!())
+        .map_err(|err| ParseError::ModuleNotFound {
+            src: source.data(db),
+            module: source.path(db).display(db),
+            message: err.to_string(),
+        })
+        .map_errs(|never| match never {})
         .bind(|code| {
             let mut parser = tree_sitter::Parser::new();
             parser
                 .set_language(tree_sitter_feather::language())
                 .expect("Error loading feather grammar");
-            let tree = parser.parse(&*code, None).unwrap();
+            let tree = match db.tree_cache().parse(&mut parser, source, &code) {
+                Some(tree) => tree,
+                None => {
+                    return Dr::new_err(ParseError::ParseFailed {
+                        src: source.data(db),
+                        label_span: (0..code.len()).into(),
+                    })
+                }
+            };
 
+            // If the file is badly malformed, the root node itself may be an error node,
+            // so we report syntax errors before checking the kind of the root node.
             if tree.root_node().kind() != "source_file" {
-                return Dr::new_err(ParseError::parser_bug(
-                    db,
-                    source,
-                    "root node was not `source_file`",
-                ));
+                return check_syntax(db, source, tree.root_node()).bind(|()| {
+                    Dr::new_err(ParseError::parser_bug(
+                        db,
+                        source,
+                        "root node was not `source_file`",
+                    ))
+                });
             }
 
-            let mut errors = Vec::new();
-            check_errors(db, source, &mut tree.root_node().walk(), &mut errors);
-            if !errors.is_empty() {
-                return Dr::new_err_many(errors);
-            }
-
-            process_module(db, source, &code, tree.root_node())
+            Dr::new((code, tree))
         })
 }
 
-/// Search through the node tree given by `cursor` for any error notes, and add them to `errors`.
-/// This function provides pretty poor error messages, but it's good enough for now.
-/// Later, we can use contextual information (such as where an error node is positioned in the tree)
-/// to give better diagnostics, and provide suggestions.
+/// Search through the node tree given by `cursor` for any error nodes or missing nodes, and add them to `errors`.
+///
+/// Tree-sitter recovers from syntax errors either by inserting a zero-width *missing* node
+/// where a single token was expected, or by wrapping the text it could not parse in an error node.
+/// In both cases, we describe the error using the node's position in the tree:
+/// the last node that was parsed successfully before it, or failing that, its parent.
 fn check_errors(
     db: &dyn Db,
     source: Source,
     cursor: &mut TreeCursor,
     errors: &mut Vec<ParseError>,
 ) {
-    if cursor.node().is_error() {
-        errors.push(ParseError::ParseError {
+    let node = cursor.node();
+    let context = || match previous_sibling(node) {
+        Some(previous) => format!("after {}", describe_node(previous)),
+        None => match node.parent() {
+            Some(parent) => format!("in {}", describe_node(parent)),
+            None => "at the start of the file".to_owned(),
+        },
+    };
+
+    if node.is_missing() {
+        errors.push(ParseError::MissingSyntax {
             src: source.data(db),
-            label_span: cursor.node().byte_range().into(),
+            message: format!("expected {} {}", describe_node(node), context()),
+            label_span: node.byte_range().into(),
         });
-    } else if cursor.goto_first_child() {
+    } else if node.is_error() {
+        let unexpected = match node.child(0) {
+            Some(child) => describe_node(child),
+            None => "syntax".to_owned(),
+        };
+        errors.push(ParseError::UnexpectedSyntax {
+            src: source.data(db),
+            message: format!("unexpected {unexpected} {}", context()),
+            label_span: node.byte_range().into(),
+        });
+    } else if node.has_error() && cursor.goto_first_child() {
         loop {
             check_errors(db, source, cursor, errors);
             if !cursor.goto_next_sibling() {
@@ -81,14 +204,131 @@ fn check_errors(
     }
 }
 
+/// Reports all of the syntax errors in the subtree rooted at `node`, failing if there are any.
+fn check_syntax(db: &dyn Db, source: Source, node: Node) -> ParseDr<()> {
+    let mut errors = Vec::new();
+    check_errors(db, source, &mut node.walk(), &mut errors);
+    if errors.is_empty() {
+        Dr::new(())
+    } else {
+        Dr::new_err_many(errors)
+    }
+}
+
+/// Returns the closest sibling before `node` that is not a comment, if there is one.
+fn previous_sibling(node: Node) -> Option<Node> {
+    let mut previous = node.prev_sibling();
+    while let Some(sibling) = previous {
+        if !sibling.is_extra() {
+            break;
+        }
+        previous = sibling.prev_sibling();
+    }
+    previous
+}
+
+/// Describes a node in the syntax tree for use in an error message, such as `` `->` `` or `binder`.
+fn describe_node(node: Node) -> String {
+    if !node.is_named() {
+        return format!("`{}`", node.kind());
+    }
+    match node.kind() {
+        "ERROR" => "invalid syntax".to_owned(),
+        "source_file" => "module".to_owned(),
+        "module" => "module declaration".to_owned(),
+        "explicit" | "implicit" | "weak" => "binder".to_owned(),
+        "app" => "function application".to_owned(),
+        "paren" => "parenthesised expression".to_owned(),
+        "local" => "local variable".to_owned(),
+        "sort" => "`Sort` expression".to_owned(),
+        "deref" => "`*` expression".to_owned(),
+        "match_body" => "match cases".to_owned(),
+        "match_variant" => "match case".to_owned(),
+        "take_proofs" => "proofs".to_owned(),
+        "universe_params" => "universe parameters".to_owned(),
+        "universe_args" => "universe levels".to_owned(),
+        "take_proof" => "proof".to_owned(),
+        "line_comment" => "comment".to_owned(),
+        kind @ ("fun" | "for" | "let" | "inst" | "intro" | "match" | "fix" | "ref" | "loan"
+        | "take" | "in") => format!("`{kind}` expression"),
+        kind => kind.replace('_', " "),
+    }
+}
+
+/// Returns the child of `node` in the field with the given name.
+/// In a tree without syntax errors, the grammar guarantees that every required field is present,
+/// so a missing field means that the grammar and this parser disagree.
+/// This is reported as a [`ParseError::ParserBug`] rather than a panic.
+fn require_field<'tree>(
+    db: &dyn Db,
+    source: Source,
+    node: Node<'tree>,
+    name: &str,
+) -> ParseDr<Node<'tree>> {
+    match node.child_by_field_name(name) {
+        Some(child) => Dr::new(child),
+        None => Dr::new_err(ParseError::ParserBug {
+            src: source.data(db),
+            message: format!("`{}` node has no `{name}` field", node.kind()),
+            label_message: format!("expected a `{name}` field in this `{}`", node.kind()),
+            label_span: node.byte_range().into(),
+        }),
+    }
+}
+
+fn require_child<'tree>(
+    db: &dyn Db,
+    source: Source,
+    node: Node<'tree>,
+    index: usize,
+) -> ParseDr<Node<'tree>> {
+    match node.child(index) {
+        Some(child) => Dr::new(child),
+        None => Dr::new_err(ParseError::ParserBug {
+            src: source.data(db),
+            message: format!("`{}` node has no child at index {index}", node.kind()),
+            label_message: format!(
+                "expected a child at index {index} in this `{}`",
+                node.kind()
+            ),
+            label_span: node.byte_range().into(),
+        }),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Module {
     pub path: WithProvenance<Path>,
     pub definitions: Vec<WithProvenance<Definition>>,
 }
 
+impl Module {
+    /// The path of this module, as written in its module declaration.
+    pub fn path(&self) -> &WithProvenance<Path> {
+        &self.path
+    }
+
+    /// The definitions in this module, in the order they were written.
+    pub fn definitions(&self) -> &[WithProvenance<Definition>] {
+        &self.definitions
+    }
+
+    /// Finds the definition with the given name.
+    /// If a name is defined more than once, the first definition is returned, like [`module_definition_map`].
+    pub fn definition_named(&self, name: Str) -> Option<&Definition> {
+        self.definitions
+            .iter()
+            .find(|def| def.contents.name.contents == name)
+            .map(|def| &def.contents)
+    }
+}
+
 /// Converts a parsed node into a [`Module`].
-/// We assume that there were no syntax errors.
+///
+/// A syntax error in the module declaration makes the whole module fail to parse.
+/// A syntax error inside a definition only causes that definition to be skipped,
+/// and syntax errors between definitions are reported without affecting any definition,
+/// so that the rest of a file that is being edited can still be checked.
 fn process_module(
     db: &dyn Db,
     source: Source,
@@ -97,25 +337,32 @@ fn process_module(
 ) -> ParseDr<Module> {
     assert_eq!(root_node.kind(), "source_file");
     // Process the module's name.
-    let path = process_path(
-        db,
-        source,
-        code,
+    let path = require_field(db, source, root_node, "module")
+        .bind(|module| check_syntax(db, source, module).map(|()| module))
+        .bind(|module| require_field(db, source, module, "path"))
+        .bind(|path| process_path(db, source, code, path));
+
+    // Report any syntax errors that are not part of the module declaration or a definition.
+    // Error nodes are never processed as definitions, even if tree-sitter placed them in the `definition` field.
+    let stray_errors = Dr::sequence_unfail(
         root_node
-            .child_by_field_name("module")
-            .unwrap()
-            .child_by_field_name("path")
-            .unwrap(),
+            .children(&mut root_node.walk())
+            .filter(|node| node.is_error() || node.is_missing())
+            .map(|node| check_syntax(db, source, node)),
     );
 
     // Process all of the definitions.
     let definitions = Dr::sequence_unfail(
         root_node
             .children_by_field_name("definition", &mut root_node.walk())
-            .map(|node| process_definition(db, source, code, node)),
+            .filter(|node| !node.is_error() && !node.is_missing())
+            .map(|node| {
+                check_syntax(db, source, node).bind(|()| process_definition(db, source, code, node))
+            }),
     );
 
-    definitions.map(|definitions| Module { path, definitions })
+    path.zip(stray_errors.zip(definitions))
+        .map(|(path, (_, definitions))| Module { path, definitions })
 }
 
 fn process_definition(
@@ -125,27 +372,41 @@ fn process_definition(
     node: Node,
 ) -> ParseDr<WithProvenance<Definition>> {
     assert_eq!(node.kind(), "definition");
-    let name = node.child_by_field_name("name").unwrap();
     let erased = node.child_by_field_name("usage").is_some();
-    let ty = node.child_by_field_name("ty").unwrap();
-    let body = node.child_by_field_name("body").unwrap();
-    process_expr(db, source, code, ty, &[]).bind(|ty| {
-        process_expr(db, source, code, body, &[]).map(|body| {
-            WithProvenance::new(
-                Some(SourceSpan::new(source, node.byte_range().into())),
-                Definition {
-                    name: process_identifier(db, source, code, name),
-                    usage: if erased {
-                        Usage::Erased
-                    } else {
-                        Usage::Present
-                    },
-                    ty,
-                    body: Some(body),
-                },
-            )
+    let universe_params = match node.child_by_field_name("universe_params") {
+        Some(params) => params
+            .children_by_field_name("param", &mut params.walk())
+            .map(|param| UniverseVar(process_identifier(db, source, code, param).contents))
+            .collect(),
+        None => Vec::new(),
+    };
+    require_field(db, source, node, "name")
+        .zip(require_field(db, source, node, "ty"))
+        .bind(|(name, ty)| {
+            process_expr(db, source, code, ty, &[]).bind(|ty| {
+                // A definition without a body is an axiom.
+                let body = match node.child_by_field_name("body") {
+                    Some(body) => process_expr(db, source, code, body, &[]).map(Some),
+                    None => Dr::new(None),
+                };
+                body.map(|body| {
+                    WithProvenance::new(
+                        Some(SourceSpan::new(source, node.byte_range().into())),
+                        Definition::new(
+                            process_identifier(db, source, code, name),
+                            universe_params,
+                            if erased {
+                                Usage::Erased
+                            } else {
+                                Usage::Present
+                            },
+                            ty,
+                            body,
+                        ),
+                    )
+                })
+            })
         })
-    })
 }
 
 fn process_path(
@@ -153,16 +414,18 @@ fn process_path(
     source: Source,
     code: &Arc<String>,
     node: Node,
-) -> WithProvenance<Path> {
-    let segments = node
-        .children_by_field_name("first", &mut node.walk())
-        .chain(std::iter::once(node.child_by_field_name("last").unwrap()))
-        .map(|node| Str::new(db, node.utf8_text(code.as_bytes()).unwrap().to_owned()))
-        .collect::<Vec<_>>();
-    WithProvenance::new(
-        Some(SourceSpan::new(source, node.byte_range().into())),
-        Path::new(db, segments),
-    )
+) -> ParseDr<WithProvenance<Path>> {
+    require_field(db, source, node, "last").map(|last| {
+        let segments = node
+            .children_by_field_name("first", &mut node.walk())
+            .chain(std::iter::once(last))
+            .map(|node| Str::new(db, node.utf8_text(code.as_bytes()).unwrap().to_owned()))
+            .collect::<Vec<_>>();
+        WithProvenance::new(
+            Some(SourceSpan::new(source, node.byte_range().into())),
+            Path::new(db, segments),
+        )
+    })
 }
 
 fn process_identifier(
@@ -178,17 +441,99 @@ fn process_identifier(
     )
 }
 
-fn process_universe(source: Source, code: &Arc<String>, node: Node) -> WithProvenance<Universe> {
+fn process_universe(
+    db: &dyn Db,
+    source: Source,
+    code: &Arc<String>,
+    node: Node,
+) -> ParseDr<WithProvenance<Universe>> {
     assert_eq!(node.kind(), "universe");
-    WithProvenance::new(
-        Some(SourceSpan::new(source, node.byte_range().into())),
-        Universe(
-            node.utf8_text(code.as_bytes())
-                .unwrap()
-                .parse()
-                .expect("did not fit into a u32"),
-        ),
-    )
+    let universe = if let Some(level) = node.child_by_field_name("level") {
+        process_universe_level(db, source, code, level)
+    } else if let Some(kind) = node.child_by_field_name("kind") {
+        let left = require_field(db, source, node, "left")
+            .bind(|left| process_universe(db, source, code, left));
+        let right = require_field(db, source, node, "right")
+            .bind(|right| process_universe(db, source, code, right));
+        left.bind(|left| {
+            right.map(|right| match kind.kind() {
+                "max" => left.contents.max(right.contents),
+                _ => left.contents.imax(right.contents),
+            })
+        })
+    } else {
+        let inner = require_field(db, source, node, "inner")
+            .bind(|inner| process_universe(db, source, code, inner));
+        require_child(db, source, node, 0).bind(|first| {
+            let is_succ = first.kind() == "succ";
+            inner.map(|inner| {
+                if is_succ {
+                    inner.contents.succ()
+                } else {
+                    inner.contents
+                }
+            })
+        })
+    };
+    universe.map(|universe| {
+        WithProvenance::new(
+            Some(SourceSpan::new(source, node.byte_range().into())),
+            universe,
+        )
+    })
+}
+
+/// Parses a universe level that was written as a single identifier: `0`, `u`, or `u+1`.
+fn process_universe_level(
+    db: &dyn Db,
+    source: Source,
+    code: &Arc<String>,
+    node: Node,
+) -> ParseDr<Universe> {
+    let text = node.utf8_text(code.as_bytes()).unwrap();
+    let is_number = |text: &str| !text.is_empty() && text.chars().all(|c| c.is_ascii_digit());
+    let invalid = || {
+        Dr::new_err(ParseError::InvalidUniverse {
+            src: source.data(db),
+            label_span: node.byte_range().into(),
+        })
+    };
+    let too_large = || {
+        Dr::new_err(ParseError::UniverseTooLarge {
+            src: source.data(db),
+            literal: text.to_owned(),
+            max: u32::MAX,
+            label_span: node.byte_range().into(),
+        })
+    };
+
+    let (base, offset) = match text.split_once('+') {
+        Some((base, offset)) => (base, offset),
+        None => (text, "0"),
+    };
+    if base.is_empty() || !is_number(offset) {
+        return invalid();
+    }
+    // Since the base and offset are strings of digits, parsing can only fail if the number is too large.
+    let Ok(offset) = offset.parse::<u32>() else {
+        return too_large();
+    };
+
+    if is_number(base) {
+        match base
+            .parse::<u32>()
+            .ok()
+            .and_then(|base| base.checked_add(offset))
+        {
+            Some(level) => Dr::new(Universe::Const(level)),
+            None => too_large(),
+        }
+    } else {
+        Dr::new((0..offset).fold(
+            Universe::Var(UniverseVar(Str::new(db, base.to_owned()))),
+            |universe, _| universe.succ(),
+        ))
+    }
 }
 
 fn process_expr(
@@ -198,21 +543,16 @@ fn process_expr(
     node: Node,
     locals: &[Str],
 ) -> ParseDr<Expression> {
-    match node.kind() {
-        "paren" => process_expr(
-            db,
-            source,
-            code,
-            node.child_by_field_name("inner").unwrap(),
-            locals,
-        ),
+    let expr = match node.kind() {
+        "paren" => require_field(db, source, node, "inner")
+            .bind(|inner| process_expr(db, source, code, inner, locals)),
         "local" => process_local(db, source, code, node, locals),
         "app" => process_app(db, source, code, node, locals),
         "for" => process_for(db, source, code, node, locals),
         "fun" => process_fun(db, source, code, node, locals),
         "let" => process_let(db, source, code, node, locals),
-        "sort" => Dr::new(process_sort(db, source, code, node)),
-        "inst" => Dr::new(process_inst(db, source, code, node)),
+        "sort" => process_sort(db, source, code, node),
+        "inst" => process_inst(db, source, code, node),
         "intro" => process_intro(db, source, code, node, locals),
         "match" => process_match(db, source, code, node, locals),
         "fix" => process_fix(db, source, code, node, locals),
@@ -221,8 +561,17 @@ fn process_expr(
         "loan" => process_loan(db, source, code, node, locals),
         "take" => process_take(db, source, code, node, locals),
         "in" => process_in(db, source, code, node, locals),
-        value => todo!("{value}"),
-    }
+        "hole" => Dr::new(process_hole(db, node)),
+        value => Dr::new_err(ParseError::parser_bug(
+            db,
+            source,
+            format!("unexpected expression node `{value}`"),
+        )),
+    };
+    expr.map(|expr| {
+        ExpressionSpan::push(db, (expr, node.byte_range().into()));
+        expr
+    })
 }
 
 fn process_de_bruijn_index(
@@ -255,6 +604,27 @@ fn process_local(
         .map(|index| Expression::new_local(db, index))
 }
 
+/// Creates a hole for the elaborator to fill.
+/// Holes are identified by their position in the source file, so reparsing a file gives the same holes.
+/// The type of the hole is another hole, whose type is a sort in a universe variable named after that hole.
+/// Since `?` is not an identifier character, this universe variable cannot clash with one written by the user.
+fn process_hole(db: &dyn Db, node: Node) -> Expression {
+    assert_eq!(node.kind(), "hole");
+    // Hole ids must be unique across every source file, since definitions from different files
+    // are type checked together, so they are not derived from the position of the hole.
+    let id = HoleId::fresh();
+    let ty_id = HoleId::fresh();
+    let universe = Universe::Var(UniverseVar(Str::new(db, ty_id.to_string())));
+    let ty = Expression::new_hole(
+        db,
+        Hole {
+            id: ty_id,
+            ty: Expression::new_sort(db, universe),
+        },
+    );
+    Expression::new_hole(db, Hole { id, ty })
+}
+
 fn process_app(
     db: &dyn Db,
     source: Source,
@@ -263,23 +633,13 @@ fn process_app(
     locals: &[Str],
 ) -> ParseDr<Expression> {
     assert_eq!(node.kind(), "app");
-    process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("left").unwrap(),
-        locals,
-    )
-    .bind(|left| {
-        process_expr(
-            db,
-            source,
-            code,
-            node.child_by_field_name("right").unwrap(),
-            locals,
-        )
-        .map(|right| Expression::new_apply(db, left, right))
-    })
+    require_field(db, source, node, "left")
+        .zip(require_field(db, source, node, "right"))
+        .bind(|(left, right)| {
+            process_expr(db, source, code, left, locals)
+                .zip(process_expr(db, source, code, right, locals))
+        })
+        .map(|(left, right)| Expression::new_apply(db, left, right))
 }
 
 fn process_binder_structure(
@@ -290,27 +650,30 @@ fn process_binder_structure(
     locals: &[Str],
     invocation_style: InvocationStyle,
 ) -> ParseDr<BinderStructure> {
-    let name = process_identifier(db, source, code, node.child_by_field_name("name").unwrap());
     let erased = node.child_by_field_name("usage").is_some();
-    let ty = node.child_by_field_name("ty").unwrap();
-    process_expr(db, source, code, ty, locals).map(|ty| BinderStructure {
-        bound: BoundVariable {
-            name: name.contents,
-            ty,
-            usage: if erased {
-                Usage::Erased
-            } else {
-                Usage::Present
-            },
-        },
-        argument_style: match node.kind() {
-            "explicit" => ArgumentStyle::Explicit,
-            "implicit" => ArgumentStyle::ImplicitEager,
-            "weak" => ArgumentStyle::ImplicitWeak,
-            _ => unreachable!(),
-        },
-        invocation_style,
-    })
+    require_field(db, source, node, "name")
+        .zip(require_field(db, source, node, "ty"))
+        .bind(|(name, ty)| {
+            let name = process_identifier(db, source, code, name);
+            process_expr(db, source, code, ty, locals).map(|ty| BinderStructure {
+                bound: BoundVariable {
+                    name: name.contents,
+                    ty,
+                    usage: if erased {
+                        Usage::Erased
+                    } else {
+                        Usage::Present
+                    },
+                },
+                argument_style: match node.kind() {
+                    "explicit" => ArgumentStyle::Explicit,
+                    "implicit" => ArgumentStyle::ImplicitEager,
+                    "weak" => ArgumentStyle::ImplicitWeak,
+                    _ => unreachable!(),
+                },
+                invocation_style,
+            })
+        })
 }
 
 fn process_binder(
@@ -320,27 +683,32 @@ fn process_binder(
     node: Node,
     locals: &[Str],
 ) -> ParseDr<Binder> {
-    let binder_structure = node.child_by_field_name("binder_structure").unwrap();
-    let arrow = node.child_by_field_name("arrow").unwrap();
-    let body = node.child_by_field_name("body").unwrap();
-    process_binder_structure(
-        db,
-        source,
-        code,
-        binder_structure,
-        locals,
-        match arrow.utf8_text(code.as_bytes()).unwrap() {
-            "->" => InvocationStyle::Once,
-            "=>" => InvocationStyle::Many,
-            _ => unreachable!(),
-        },
-    )
-    .bind(|structure| {
-        let new_locals = std::iter::once(structure.bound.name)
-            .chain(locals.iter().copied())
-            .collect::<Vec<_>>();
-        process_expr(db, source, code, body, &new_locals).map(|body| Binder { structure, body })
-    })
+    require_field(db, source, node, "binder_structure")
+        .zip(require_field(db, source, node, "arrow"))
+        .zip(require_field(db, source, node, "body"))
+        .bind(|((binder_structure, arrow), body)| {
+            require_field(db, source, binder_structure, "name").bind(|name| {
+                let name = process_identifier(db, source, code, name);
+                let new_locals = std::iter::once(name.contents)
+                    .chain(locals.iter().copied())
+                    .collect::<Vec<_>>();
+                let body = process_expr(db, source, code, body, &new_locals);
+                process_binder_structure(
+                    db,
+                    source,
+                    code,
+                    binder_structure,
+                    locals,
+                    match arrow.utf8_text(code.as_bytes()).unwrap() {
+                        "->" => InvocationStyle::Once,
+                        "=>" => InvocationStyle::Many,
+                        _ => unreachable!(),
+                    },
+                )
+                .zip(body)
+            })
+        })
+        .map(|(structure, body)| Binder { structure, body })
 }
 
 fn process_for(
@@ -373,40 +741,53 @@ fn process_let(
     locals: &[Str],
 ) -> ParseDr<Expression> {
     assert_eq!(node.kind(), "let");
-    let name = process_identifier(db, source, code, node.child_by_field_name("name").unwrap());
-    let to_assign = process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("to_assign").unwrap(),
-        locals,
-    );
-    let mut locals = locals.to_vec();
-    locals.insert(0, name.contents);
-    let body = process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("body").unwrap(),
-        &locals,
-    );
-    to_assign
-        .bind(|to_assign| body.map(|body| Expression::new_let(db, name.contents, to_assign, body)))
+    require_field(db, source, node, "name")
+        .zip(require_field(db, source, node, "to_assign"))
+        .zip(require_field(db, source, node, "body"))
+        .bind(|((name, to_assign), body)| {
+            let name = process_identifier(db, source, code, name);
+            let to_assign = process_expr(db, source, code, to_assign, locals);
+            let mut locals = locals.to_vec();
+            locals.insert(0, name.contents);
+            let body = process_expr(db, source, code, body, &locals);
+            to_assign
+                .zip(body)
+                .map(|(to_assign, body)| Expression::new_let(db, name.contents, to_assign, body))
+        })
 }
 
-fn process_sort(db: &dyn Db, source: Source, code: &Arc<String>, node: Node) -> Expression {
-    Expression::new_sort(
-        db,
-        process_universe(source, code, node.child_by_field_name("universe").unwrap()).contents,
-    )
+fn process_sort(
+    db: &dyn Db,
+    source: Source,
+    code: &Arc<String>,
+    node: Node,
+) -> ParseDr<Expression> {
+    require_field(db, source, node, "universe")
+        .bind(|universe| process_universe(db, source, code, universe))
+        .map(|universe| Expression::new_sort(db, universe.contents))
 }
 
-fn process_inst(db: &dyn Db, source: Source, code: &Arc<String>, node: Node) -> Expression {
+fn process_inst(
+    db: &dyn Db,
+    source: Source,
+    code: &Arc<String>,
+    node: Node,
+) -> ParseDr<Expression> {
     assert_eq!(node.kind(), "inst");
-    Expression::new_inst(
-        db,
-        process_path(db, source, code, node.child_by_field_name("path").unwrap()).contents,
-    )
+    let path =
+        require_field(db, source, node, "path").bind(|path| process_path(db, source, code, path));
+    let universes = match node.child_by_field_name("universes") {
+        Some(args) => Dr::sequence(
+            args.children_by_field_name("universe", &mut args.walk())
+                .map(|universe| {
+                    process_universe(db, source, code, universe).map(|universe| universe.contents)
+                })
+                .collect::<Vec<_>>(),
+        ),
+        None => Dr::new(Vec::new()),
+    };
+    path.zip(universes)
+        .map(|(path, universes)| Expression::new_inst_with_universes(db, path.contents, universes))
 }
 
 fn process_intro(
@@ -417,44 +798,34 @@ fn process_intro(
     locals: &[Str],
 ) -> ParseDr<Expression> {
     assert_eq!(node.kind(), "intro");
-    let path = process_path(db, source, code, node.child_by_field_name("path").unwrap());
+    let path =
+        require_field(db, source, node, "path").bind(|path| process_path(db, source, code, path));
     let parameters = Dr::sequence_unfail(
         node.children_by_field_name("param", &mut node.walk())
             .map(|param| process_expr(db, source, code, param, locals)),
     );
 
-    let variant = process_identifier(
-        db,
-        source,
-        code,
-        node.child_by_field_name("variant").unwrap(),
-    );
+    let variant = require_field(db, source, node, "variant")
+        .map(|variant| process_identifier(db, source, code, variant));
 
     let fields = Dr::sequence_unfail(node.children_by_field_name("field", &mut node.walk()).map(
         |field| {
-            assert_eq!(node.kind(), "intro_field");
-            let name =
-                process_identifier(db, source, code, field.child_by_field_name("name").unwrap());
-            process_expr(
-                db,
-                source,
-                code,
-                node.child_by_field_name("value").unwrap(),
-                locals,
-            )
-            .map(|value| (name.contents, value))
+            assert_eq!(field.kind(), "intro_field");
+            require_field(db, source, field, "name")
+                .zip(require_field(db, source, field, "value"))
+                .bind(|(name, value)| {
+                    let name = process_identifier(db, source, code, name);
+                    process_expr(db, source, code, value, locals).map(|value| (name, value))
+                })
         },
-    ));
-
-    parameters.bind(|parameters| {
-        fields.map(|fields| {
-            Expression::new_intro(
-                db,
-                path.contents,
-                parameters,
-                variant.contents,
-                fields.into(),
-            )
+    ))
+    .bind(|fields| process_named_entries(db, source, fields));
+
+    path.zip(variant).bind(|(path, variant)| {
+        parameters.bind(|parameters| {
+            fields.map(|fields| {
+                Expression::new_intro(db, path.contents, parameters, variant.contents, fields)
+            })
         })
     })
 }
@@ -468,50 +839,63 @@ fn process_match(
 ) -> ParseDr<Expression> {
     assert_eq!(node.kind(), "match");
 
-    let subject = process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("subject").unwrap(),
-        locals,
-    );
+    let subject = require_field(db, source, node, "subject")
+        .bind(|subject| process_expr(db, source, code, subject, locals));
 
-    let return_ty = process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("return").unwrap(),
-        locals,
-    );
+    let return_ty = require_field(db, source, node, "return")
+        .bind(|return_ty| process_expr(db, source, code, return_ty, locals));
 
-    let body = node.child_by_field_name("body").unwrap();
-    let cases = Dr::sequence_unfail(
-        body.children_by_field_name("variant", &mut body.walk())
-            .map(|variant| {
-                let name = process_identifier(
-                    db,
-                    source,
-                    code,
-                    variant.child_by_field_name("name").unwrap(),
-                );
-                process_expr(
-                    db,
-                    source,
-                    code,
-                    variant.child_by_field_name("value").unwrap(),
-                    locals,
-                )
-                .map(|value| (name.contents, value))
-            }),
-    );
+    let cases = require_field(db, source, node, "body")
+        .bind(|body| {
+            Dr::sequence_unfail(
+                body.children_by_field_name("variant", &mut body.walk())
+                    .map(|variant| {
+                        require_field(db, source, variant, "name")
+                            .zip(require_field(db, source, variant, "value"))
+                            .bind(|(name, value)| {
+                                let name = process_identifier(db, source, code, name);
+                                process_expr(db, source, code, value, locals)
+                                    .map(|value| (name, value))
+                            })
+                    }),
+            )
+        })
+        .bind(|cases| process_named_entries(db, source, cases));
 
     subject.bind(|subject| {
         return_ty.bind(|return_ty| {
-            cases.map(|cases| Expression::new_match(db, subject, return_ty, cases.into()))
+            cases.map(|cases| Expression::new_match(db, subject, return_ty, cases))
         })
     })
 }
 
+/// Converts a list of named entries, such as the fields of an `intro` expression
+/// or the cases of a `match` expression, into a [`VecMap`].
+/// If a name appears more than once, an error is emitted at its second occurrence.
+fn process_named_entries(
+    db: &dyn Db,
+    source: Source,
+    entries: Vec<(WithProvenance<Str>, Expression)>,
+) -> ParseDr<VecMap<Str, Expression>> {
+    let spans = entries
+        .iter()
+        .map(|(name, _)| name.provenance.map(|provenance| provenance.span))
+        .collect::<Vec<_>>();
+    Dr::from_result(
+        VecMap::try_from_vec(
+            entries
+                .into_iter()
+                .map(|(name, value)| (name.contents, value))
+                .collect(),
+        )
+        .map_err(|duplicate| ParseError::DuplicateField {
+            src: source.data(db),
+            label_span: spans[duplicate.second].unwrap_or_default(),
+            first_span: spans[duplicate.first].unwrap_or_default(),
+        }),
+    )
+}
+
 fn process_fix(
     db: &dyn Db,
     source: Source,
@@ -521,55 +905,38 @@ fn process_fix(
 ) -> ParseDr<Expression> {
     assert_eq!(node.kind(), "fix");
 
-    let binder_structure = process_binder_structure(
-        db,
-        source,
-        code,
-        node.child_by_field_name("binder_structure").unwrap(),
-        locals,
-        InvocationStyle::Many,
-    );
+    let binder_structure = require_field(db, source, node, "binder_structure").bind(|structure| {
+        process_binder_structure(db, source, code, structure, locals, InvocationStyle::Many)
+    });
+    let fields = require_field(db, source, node, "return")
+        .zip(require_field(db, source, node, "rec_name"))
+        .zip(require_field(db, source, node, "body"));
 
-    binder_structure.bind(|binder_structure| {
-        let mut locals = locals.to_vec();
-        locals.insert(0, binder_structure.bound.name);
-        let return_ty = process_expr(
-            db,
-            source,
-            code,
-            node.child_by_field_name("return").unwrap(),
-            &locals,
-        );
+    binder_structure
+        .zip(fields)
+        .bind(|(binder_structure, ((return_ty, rec_name), body))| {
+            let mut locals = locals.to_vec();
+            locals.insert(0, binder_structure.bound.name);
+            let return_ty = process_expr(db, source, code, return_ty, &locals);
 
-        let rec_name = process_identifier(
-            db,
-            source,
-            code,
-            node.child_by_field_name("rec_name").unwrap(),
-        );
-        locals.insert(0, rec_name.contents);
-        let body = process_expr(
-            db,
-            source,
-            code,
-            node.child_by_field_name("body").unwrap(),
-            &locals,
-        );
+            let rec_name = process_identifier(db, source, code, rec_name);
+            locals.insert(0, rec_name.contents);
+            let body = process_expr(db, source, code, body, &locals);
 
-        return_ty.bind(|return_ty| {
-            body.map(|body| {
-                Expression::new_fix(
-                    db,
-                    Binder {
-                        structure: binder_structure,
-                        body: return_ty,
-                    },
-                    rec_name.contents,
-                    body,
-                )
+            return_ty.bind(|return_ty| {
+                body.map(|body| {
+                    Expression::new_fix(
+                        db,
+                        Binder {
+                            structure: binder_structure,
+                            body: return_ty,
+                        },
+                        rec_name.contents,
+                        body,
+                    )
+                })
             })
         })
-    })
 }
 
 fn process_ref(
@@ -579,14 +946,9 @@ fn process_ref(
     node: Node,
     locals: &[Str],
 ) -> ParseDr<Expression> {
-    process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("ty").unwrap(),
-        locals,
-    )
-    .map(|ty| Expression::new_ref(db, ty))
+    require_field(db, source, node, "ty")
+        .bind(|ty| process_expr(db, source, code, ty, locals))
+        .map(|ty| Expression::new_ref(db, ty))
 }
 
 fn process_deref(
@@ -596,14 +958,9 @@ fn process_deref(
     node: Node,
     locals: &[Str],
 ) -> ParseDr<Expression> {
-    process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("value").unwrap(),
-        locals,
-    )
-    .map(|ty| Expression::new_deref(db, ty))
+    require_field(db, source, node, "value")
+        .bind(|value| process_expr(db, source, code, value, locals))
+        .map(|value| Expression::new_deref(db, value))
 }
 
 fn process_loan(
@@ -615,30 +972,26 @@ fn process_loan(
 ) -> ParseDr<Expression> {
     assert_eq!(node.kind(), "loan");
 
-    let local = process_de_bruijn_index(
-        db,
-        source,
-        code,
-        node.child_by_field_name("ident").unwrap(),
-        locals,
-    );
-    let loan_as = process_identifier(db, source, code, node.child_by_field_name("as").unwrap());
-    let with = process_identifier(db, source, code, node.child_by_field_name("with").unwrap());
+    require_field(db, source, node, "ident")
+        .zip(require_field(db, source, node, "as"))
+        .zip(require_field(db, source, node, "with"))
+        .zip(require_field(db, source, node, "body"))
+        .bind(|(((local, loan_as), with), body)| {
+            let local = process_de_bruijn_index(db, source, code, local, locals);
+            let loan_as = process_identifier(db, source, code, loan_as);
+            let with = process_identifier(db, source, code, with);
 
-    let mut locals = locals.to_vec();
-    locals.insert(0, loan_as.contents);
-    locals.insert(0, with.contents);
-    let body = process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("body").unwrap(),
-        &locals,
-    );
+            let mut locals = locals.to_vec();
+            locals.insert(0, loan_as.contents);
+            locals.insert(0, with.contents);
+            let body = process_expr(db, source, code, body, &locals);
 
-    local.bind(|local| {
-        body.map(|body| Expression::new_loan(db, local, loan_as.contents, with.contents, body))
-    })
+            local.bind(|local| {
+                body.map(|body| {
+                    Expression::new_loan(db, local, loan_as.contents, with.contents, body)
+                })
+            })
+        })
 }
 
 fn process_take(
@@ -650,42 +1003,25 @@ fn process_take(
 ) -> ParseDr<Expression> {
     assert_eq!(node.kind(), "take");
 
-    let local = process_de_bruijn_index(
-        db,
-        source,
-        code,
-        node.child_by_field_name("ident").unwrap(),
-        locals,
-    );
-    let proofs = node.child_by_field_name("proofs").unwrap();
-    let proofs = Dr::sequence_unfail(
-        proofs
-            .children_by_field_name("proof", &mut proofs.walk())
-            .map(|proof| {
-                let local = process_de_bruijn_index(
-                    db,
-                    source,
-                    code,
-                    proof.child_by_field_name("local").unwrap(),
-                    locals,
-                );
-                let proof_term = process_expr(
-                    db,
-                    source,
-                    code,
-                    proof.child_by_field_name("proof").unwrap(),
-                    locals,
-                );
-                local.bind(|local| proof_term.map(|proof_term| (local, proof_term)))
-            }),
-    );
-    let body = process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("body").unwrap(),
-        locals,
-    );
+    let local = require_field(db, source, node, "ident")
+        .bind(|local| process_de_bruijn_index(db, source, code, local, locals));
+    let proofs = require_field(db, source, node, "proofs").bind(|proofs| {
+        Dr::sequence_unfail(
+            proofs
+                .children_by_field_name("proof", &mut proofs.walk())
+                .map(|proof| {
+                    require_field(db, source, proof, "local")
+                        .zip(require_field(db, source, proof, "proof"))
+                        .bind(|(local, proof_term)| {
+                            let local = process_de_bruijn_index(db, source, code, local, locals);
+                            let proof_term = process_expr(db, source, code, proof_term, locals);
+                            local.bind(|local| proof_term.map(|proof_term| (local, proof_term)))
+                        })
+                }),
+        )
+    });
+    let body = require_field(db, source, node, "body")
+        .bind(|body| process_expr(db, source, code, body, locals));
 
     local.bind(|local| {
         proofs.bind(|proofs| body.map(|body| Expression::new_take(db, local, proofs.into(), body)))
@@ -700,23 +1036,14 @@ fn process_in(
     locals: &[Str],
 ) -> ParseDr<Expression> {
     assert_eq!(node.kind(), "in");
-    process_expr(
-        db,
-        source,
-        code,
-        node.child_by_field_name("reference").unwrap(),
-        locals,
-    )
-    .bind(|reference| {
-        process_expr(
-            db,
-            source,
-            code,
-            node.child_by_field_name("target").unwrap(),
-            locals,
-        )
-        .map(|target| Expression::new_in(db, reference, target))
-    })
+    require_field(db, source, node, "reference")
+        .zip(require_field(db, source, node, "target"))
+        .bind(|(reference, target)| {
+            process_expr(db, source, code, reference, locals).bind(|reference| {
+                process_expr(db, source, code, target, locals)
+                    .map(|target| Expression::new_in(db, reference, target))
+            })
+        })
 }
 
 #[derive(Error, Diagnostic, Debug, Clone, PartialEq, Eq, Hash)]
@@ -731,10 +1058,60 @@ pub enum ParseError {
         #[label("{label_message}")]
         label_span: Span,
     },
-    #[error("syntax error")]
-    ParseError {
+    #[error("failed to parse file")]
+    #[diagnostic(help = "the file may be too large, or parsing may have been cancelled")]
+    ParseFailed {
+        #[source_code]
+        src: SourceData,
+        #[label("while parsing this file")]
+        label_span: Span,
+    },
+    #[error("{message}")]
+    MissingSyntax {
         #[source_code]
         src: SourceData,
+        message: String,
+        #[label("expected here")]
+        label_span: Span,
+    },
+    #[error("{message}")]
+    UnexpectedSyntax {
+        #[source_code]
+        src: SourceData,
+        message: String,
+        #[label("could not parse this")]
+        label_span: Span,
+    },
+    #[error("could not find module {module}")]
+    #[diagnostic(help = "{message}")]
+    ModuleNotFound {
+        #[source_code]
+        src: SourceData,
+        module: String,
+        message: String,
+    },
+    #[error("cannot parse .{extension} files")]
+    #[diagnostic(help = "only feather source files are currently supported")]
+    UnsupportedSourceType {
+        #[source_code]
+        src: SourceData,
+        extension: &'static str,
+    },
+    #[error("invalid universe level")]
+    #[diagnostic(help = "universe levels are written as `0`, `u`, `u+1`, `max u v` or `imax u v`")]
+    InvalidUniverse {
+        #[source_code]
+        src: SourceData,
+        #[label("error occurred here")]
+        label_span: Span,
+    },
+    #[error("universe level {literal} is too large")]
+    #[diagnostic(help = "the largest supported universe level is {max}")]
+    UniverseTooLarge {
+        #[source_code]
+        src: SourceData,
+        literal: String,
+        max: u32,
         #[label("error occurred here")]
         label_span: Span,
     },
@@ -745,6 +1122,15 @@ pub enum ParseError {
         #[label("error occurred here")]
         label_span: Span,
     },
+    #[error("this name was given more than once")]
+    DuplicateField {
+        #[source_code]
+        src: SourceData,
+        #[label("error occurred here")]
+        label_span: Span,
+        #[label("first given here")]
+        first_span: Span,
+    },
 }
 
 impl ParseError {
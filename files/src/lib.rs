@@ -1,20 +1,32 @@
-use std::{fmt::Debug, path::PathBuf, sync::Arc};
+use std::{
+    fmt::{Debug, Display},
+    path::PathBuf,
+    sync::Arc,
+};
 
 use diagnostic::{miette, Dr};
 use miette::Diagnostic;
 use thiserror::Error;
 
+mod line_index;
+
+pub use line_index::*;
+
 #[salsa::jar(db = Db)]
 pub struct Jar(Str, Path, InputFile, Source, source);
 
 pub trait Db: std::fmt::Debug + salsa::DbWithJar<Jar> {
     /// Loads source code from a file.
     /// This is performed lazily when needed.
+    /// If the file does not exist, this returns an input whose contents are `None`,
+    /// so that queries which read it are invalidated if the file is created later.
     fn input_file(&self, path: std::path::PathBuf) -> std::io::Result<InputFile>;
 }
 
 /// A span of code in a given source file.
-/// Represented by a range of UTF-8 characters.
+/// Represented by a range of byte offsets into the UTF-8 source code,
+/// matching the offsets used by tree-sitter and [`miette`].
+/// Use [`LineIndex`] to convert these offsets into line and column numbers.
 /// See also [`SourceSpan`].
 ///
 /// The default span is `0..0`.
@@ -26,6 +38,34 @@ pub struct Span {
     pub end: usize,
 }
 
+impl Span {
+    /// Returns the smallest span that contains both `self` and `other`.
+    /// For example, the union of `3..5` and `8..10` is `3..10`.
+    pub const fn union(self, other: Span) -> Span {
+        Span {
+            start: if self.start < other.start {
+                self.start
+            } else {
+                other.start
+            },
+            end: if self.end > other.end {
+                self.end
+            } else {
+                other.end
+            },
+        }
+    }
+
+    /// Returns true if `other` lies entirely inside this span.
+    pub const fn contains(&self, other: &Span) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+}
+
 impl Debug for Span {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}..{}", self.start, self.end)
@@ -74,6 +114,36 @@ pub struct Str {
     pub text: String,
 }
 
+/// Words that are reserved by the Feather grammar, and so cannot be used as identifiers.
+const FEATHER_KEYWORDS: &[&str] = &[
+    "0", "=", "=>", "Sort", "as", "def", "fix", "for", "fun", "imax", "in", "inst", "intro", "let",
+    "loan", "match", "max", "module", "ref", "return", "succ", "take", "with",
+];
+
+impl Str {
+    /// Returns true if this string can be written as an identifier in Feather code.
+    ///
+    /// The Feather grammar allows identifiers made of Unicode letters, numbers and symbols, excluding keywords.
+    /// The standard library cannot distinguish Unicode symbols from punctuation,
+    /// so any non-ASCII character that is not whitespace or a control character is accepted.
+    pub fn is_valid_identifier(self, db: &dyn Db) -> bool {
+        let text = self.text(db);
+        !text.is_empty()
+            && !FEATHER_KEYWORDS.contains(&text.as_str())
+            && text.chars().all(|c| {
+                c.is_alphanumeric()
+                    || "$+<=>^`|~".contains(c)
+                    || !(c.is_ascii() || c.is_whitespace() || c.is_control())
+            })
+    }
+
+    /// Appends the given suffix to this string.
+    /// There is no separator before the suffix, since `_` is not valid in a Feather identifier.
+    pub fn with_suffix(self, db: &dyn Db, suffix: impl Display) -> Str {
+        Str::new(db, format!("{}{suffix}", self.text(db)))
+    }
+}
+
 /// Generates a sequence of distinct strings with a given prefix.
 pub struct StrGenerator<'a> {
     db: &'a dyn Db,
@@ -91,14 +161,12 @@ impl<'a> StrGenerator<'a> {
     }
 
     pub fn generate(&mut self) -> Str {
-        let result = Str::new(
-            self.db,
-            if self.counter == 0 {
-                self.prefix.clone()
-            } else {
-                format!("{}_{}", self.prefix, self.counter)
-            },
-        );
+        let prefix = Str::new(self.db, self.prefix.clone());
+        let result = if self.counter == 0 {
+            prefix
+        } else {
+            prefix.with_suffix(self.db, self.counter)
+        };
         self.counter += 1;
         result
     }
@@ -114,6 +182,23 @@ pub struct Path {
 }
 
 impl Path {
+    /// Parses a path written with `::` between its segments, such as `std::nat::zero`.
+    /// This is the inverse of [`Path::display`].
+    pub fn parse(db: &dyn Db, text: &str) -> Result<Path, PathParseError> {
+        text.split("::")
+            .map(|segment| {
+                if segment.is_empty() {
+                    Err(PathParseError {
+                        path: text.to_owned(),
+                    })
+                } else {
+                    Ok(Str::new(db, segment.to_owned()))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|segments| Path::new(db, segments))
+    }
+
     pub fn display(self, db: &dyn Db) -> String {
         self.segments(db)
             .iter()
@@ -135,6 +220,20 @@ impl Path {
         (Path::new(db, Vec::from(source_file)), *last_element)
     }
 
+    /// Returns this path without its last segment, or [`None`] if this path has no segments.
+    /// If a path was `[a, b, c]`, this function returns `[a, b]`.
+    pub fn parent(self, db: &dyn Db) -> Option<Path> {
+        self.segments(db)
+            .split_last()
+            .map(|(_, parent)| Path::new(db, parent.to_vec()))
+    }
+
+    /// Returns true if the segments of this path are the first segments of `other`.
+    /// For example, `a::b` is a prefix of `a::b::c`, and every path is a prefix of itself.
+    pub fn is_prefix_of(self, other: Path, db: &dyn Db) -> bool {
+        other.segments(db).starts_with(self.segments(db))
+    }
+
     pub fn with(self, db: &dyn Db, segment: Str) -> Path {
         let mut segments = self.segments(db).clone();
         segments.push(segment);
@@ -160,6 +259,14 @@ impl Path {
     }
 }
 
+/// The error returned by [`Path::parse`].
+#[derive(Error, Diagnostic, Debug, Clone, Eq, PartialEq)]
+#[error("{path} is not a valid path")]
+#[diagnostic(help = "paths are written as identifiers separated by `::`, such as `std::nat::zero`")]
+pub struct PathParseError {
+    path: String,
+}
+
 /// Uniquely identifies a source file.
 #[salsa::interned]
 pub struct Source {
@@ -301,7 +408,10 @@ where
 #[salsa::input]
 pub struct InputFile {
     pub path: PathBuf,
-    pub contents: Arc<String>,
+    /// The contents of the file, or `None` if it does not exist.
+    /// Queries that find that a file is missing still depend on this input,
+    /// so they run again if the file is created.
+    pub contents: Option<Arc<String>>,
 }
 
 #[tracing::instrument(level = "debug")]
@@ -311,13 +421,15 @@ pub fn source(db: &dyn Db, source: Source) -> Dr<Arc<String>, SourceError> {
         .path(db)
         .to_path_buf(db)
         .with_extension(source.ty(db).extension());
-    match db.input_file(path_buf) {
-        Ok(value) => Dr::new(value.contents(db)),
-        Err(err) => Dr::new_err(SourceError {
-            src: source.path(db).to_path_buf(db),
-            message: err.to_string(),
-        }),
-    }
+    let contents = db.input_file(path_buf).and_then(|file| {
+        file.contents(db).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "the file does not exist")
+        })
+    });
+    Dr::from_result(contents.map_err(|err| SourceError {
+        src: source.path(db).to_path_buf(db),
+        message: err.to_string(),
+    }))
 }
 
 #[derive(Error, Diagnostic, Debug, Clone, Eq, PartialEq)]
@@ -326,3 +438,76 @@ pub struct SourceError {
     src: PathBuf,
     message: String,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A database in which every file is missing.
+    #[salsa::db(crate::Jar)]
+    #[derive(Default)]
+    struct TestDatabase {
+        storage: salsa::Storage<Self>,
+    }
+
+    impl Debug for TestDatabase {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "<db>")
+        }
+    }
+
+    impl salsa::Database for TestDatabase {}
+
+    impl Db for TestDatabase {
+        fn input_file(&self, path: PathBuf) -> std::io::Result<InputFile> {
+            Ok(InputFile::new(self, path, None))
+        }
+    }
+
+    #[test]
+    fn paths_parse() {
+        let db = TestDatabase::default();
+        let path = Path::parse(&db, "std::nat::zero").unwrap();
+        assert_eq!(path.segments(&db).len(), 3);
+        assert_eq!(path.display(&db), "std::nat::zero");
+        assert!(Path::parse(&db, "std::::zero").is_err());
+        assert!(Path::parse(&db, "").is_err());
+    }
+
+    #[test]
+    fn path_navigation() {
+        let db = TestDatabase::default();
+        let path = |text| Path::parse(&db, text).unwrap();
+        assert_eq!(path("a::b::c").parent(&db), Some(path("a::b")));
+        assert_eq!(Path::new(&db, Vec::new()).parent(&db), None);
+        assert!(path("a::b").is_prefix_of(path("a::b::c"), &db));
+        assert!(path("a::b").is_prefix_of(path("a::b"), &db));
+        assert!(!path("a::b::c").is_prefix_of(path("a::b"), &db));
+        assert!(!path("a::c").is_prefix_of(path("a::b::c"), &db));
+    }
+
+    #[test]
+    fn identifiers() {
+        let db = TestDatabase::default();
+        let valid = |text: &str| Str::new(&db, text.to_owned()).is_valid_identifier(&db);
+        assert!(valid("x"));
+        assert!(valid("λx"));
+        assert!(valid("u+1"));
+        assert!(!valid(""));
+        assert!(!valid("fun"));
+        assert!(!valid("a_b"));
+        assert!(!valid("a::b"));
+
+        let x = Str::new(&db, "x".to_owned());
+        assert_eq!(x.with_suffix(&db, 1).text(&db), "x1");
+        assert!(x.with_suffix(&db, 1).is_valid_identifier(&db));
+    }
+
+    #[test]
+    fn missing_files_are_not_found() {
+        let db = TestDatabase::default();
+        let path = Path::parse(&db, "missing").unwrap();
+        let missing = Source::new(&db, path, SourceType::Feather);
+        assert!(source(&db, missing).value().is_none());
+    }
+}
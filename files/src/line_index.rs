@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+/// Converts byte offsets into a source file into line and column numbers.
+/// Offsets are measured in bytes, matching [`crate::Span`],
+/// but columns are measured in UTF-8 characters, as expected by editors and terminals.
+///
+/// Building the index takes linear time in the length of the file.
+/// Each lookup then takes logarithmic time in the number of lines,
+/// plus linear time in the length of the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    text: Arc<String>,
+    /// The byte offset of the start of each line.
+    /// The first entry is always zero.
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &Arc<String>) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        Self {
+            text: Arc::clone(text),
+            line_starts,
+        }
+    }
+
+    /// Returns the 1-based line and column of the character at the given byte offset.
+    /// Offsets past the end of the file are clamped to the end of the file,
+    /// and offsets inside a multi-byte character are treated as the start of that character.
+    pub fn line_col(&self, offset: usize) -> (u32, u32) {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        // The index of the last line that starts at or before `offset`.
+        let line = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        let column = self.text[self.line_starts[line]..offset].chars().count();
+        (line as u32 + 1, column as u32 + 1)
+    }
+
+    /// Returns the byte offset of the character at the given 1-based line and column.
+    /// This is the inverse of [`LineIndex::line_col`].
+    /// If the line does not exist, or the column is past the end of the line, return [`None`].
+    pub fn offset(&self, line: u32, column: u32) -> Option<usize> {
+        let line = (line as usize).checked_sub(1)?;
+        let column = (column as usize).checked_sub(1)?;
+        let start = *self.line_starts.get(line)?;
+        // The end of the line, including its newline character if it has one.
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .copied()
+            .unwrap_or(self.text.len());
+        self.text[start..end]
+            .char_indices()
+            .map(|(offset, _)| start + offset)
+            // The end of the last line is a valid position, since it may be followed by more text.
+            .chain((end == self.text.len()).then_some(end))
+            .nth(column)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LineIndex;
+    use std::sync::Arc;
+
+    #[test]
+    fn line_col_round_trip() {
+        let index = LineIndex::new(&Arc::new("ab\nλx\n\nz".to_owned()));
+        assert_eq!(index.line_col(0), (1, 1));
+        assert_eq!(index.line_col(2), (1, 3));
+        assert_eq!(index.line_col(3), (2, 1));
+        assert_eq!(index.line_col(4), (2, 1));
+        assert_eq!(index.line_col(5), (2, 2));
+        assert_eq!(index.line_col(7), (3, 1));
+        assert_eq!(index.line_col(8), (4, 1));
+        for offset in [0, 1, 2, 3, 5, 6, 7, 8, 9] {
+            let (line, column) = index.line_col(offset);
+            assert_eq!(index.offset(line, column), Some(offset));
+        }
+        assert_eq!(index.offset(1, 4), None);
+        assert_eq!(index.offset(5, 1), None);
+    }
+}
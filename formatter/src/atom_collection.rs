@@ -9,12 +9,49 @@ use tree_sitter_facade::Node;
 
 use crate::{Atom, FormatterError, FormatterResult, ScopeCondition};
 
+/// The capture names that may be used in a query file.
+/// Each is handled by [`AtomCollection::resolve_capture`], except for `do_nothing`,
+/// which is handled when the query is applied.
+pub const CAPTURE_NAMES: &[&str] = &[
+    "allow_blank_line_before",
+    "append_antispace",
+    "append_blankline",
+    "append_delimiter",
+    "append_empty_scoped_softline",
+    "append_empty_softline",
+    "append_hardline",
+    "append_indent_end",
+    "append_indent_start",
+    "append_input_softline",
+    "append_space",
+    "append_spaced_scoped_softline",
+    "append_spaced_softline",
+    "begin_scope",
+    "delete",
+    "do_nothing",
+    "end_scope",
+    "leaf",
+    "prepend_antispace",
+    "prepend_blankline",
+    "prepend_delimiter",
+    "prepend_empty_scoped_softline",
+    "prepend_empty_softline",
+    "prepend_hardline",
+    "prepend_indent_end",
+    "prepend_indent_start",
+    "prepend_input_softline",
+    "prepend_space",
+    "prepend_spaced_scoped_softline",
+    "prepend_spaced_softline",
+    "single_line_no_indent",
+];
+
 struct NodesWithLinebreaks {
     before: HashSet<usize>,
     after: HashSet<usize>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct AtomCollection {
     atoms: Vec<Atom>,
     prepend: HashMap<usize, Vec<Atom>>,
@@ -31,16 +68,29 @@ pub struct AtomCollection {
     // is lost at post-processing time.
     scope_begin: HashMap<usize, (u32, Vec<String>)>,
     scope_end: HashMap<usize, (u32, Vec<String>)>,
+    /// If true, softlines in multi-line nodes and scopes become breakpoints rather than hardlines,
+    /// so they are only broken if the construct does not fit on one line.
+    reflow: bool,
     /// Used to generate unique IDs
     counter: usize,
 }
 
 impl AtomCollection {
+    /// Creates an `AtomCollection` from atoms that were built directly,
+    /// rather than collected from a syntax tree.
+    pub fn from_atoms(atoms: Vec<Atom>) -> Self {
+        Self {
+            atoms,
+            ..Default::default()
+        }
+    }
+
     /// Use this to create an initial `AtomCollection`
     pub fn collect_leafs(
         root: &Node,
         source: &[u8],
         specified_leaf_nodes: HashSet<usize>,
+        reflow: bool,
     ) -> FormatterResult<Self> {
         // Flatten the tree, from the root node, in a depth-first traversal
         let dfs_nodes = dfs_flatten(root);
@@ -62,6 +112,7 @@ impl AtomCollection {
             line_break_after: line_break_nodes.after,
             scope_begin: HashMap::new(),
             scope_end: HashMap::new(),
+            reflow,
             counter: 0,
         };
 
@@ -138,9 +189,14 @@ impl AtomCollection {
         match name {
             "allow_blank_line_before" => {
                 if self.blank_lines_before.contains(&node.id()) {
-                    self.prepend(Atom::Blankline, node, predicates);
+                    self.prepend(Atom::Blankline(1), node, predicates);
                 }
             }
+            "append_blankline" => self.append(
+                Atom::Blankline(predicates.blank_lines.unwrap_or(1)),
+                node,
+                predicates,
+            ),
             "append_delimiter" => self.append(
                 Atom::Literal(requires_delimiter()?.to_string()),
                 node,
@@ -156,7 +212,10 @@ impl AtomCollection {
                 let space = if self.line_break_after.contains(&node.id()) {
                     Atom::Hardline
                 } else {
-                    Atom::Space
+                    Atom::Breakpoint {
+                        spaced: true,
+                        group: self.next_id(),
+                    }
                 };
 
                 self.append(space, node, predicates);
@@ -166,6 +225,11 @@ impl AtomCollection {
             "append_spaced_softline" => {
                 self.append(Atom::Softline { spaced: true }, node, predicates);
             }
+            "prepend_blankline" => self.prepend(
+                Atom::Blankline(predicates.blank_lines.unwrap_or(1)),
+                node,
+                predicates,
+            ),
             "prepend_delimiter" => self.prepend(
                 Atom::Literal(requires_delimiter()?.to_string()),
                 node,
@@ -180,8 +244,15 @@ impl AtomCollection {
             "prepend_input_softline" => {
                 let space = if self.line_break_before.contains(&node.id()) {
                     Atom::Hardline
-                } else {
+                } else if node.is_extra() {
+                    // Comments at the end of a line must stay on that line,
+                    // so they are never moved by line breaking.
                     Atom::Space
+                } else {
+                    Atom::Breakpoint {
+                        spaced: true,
+                        group: self.next_id(),
+                    }
                 };
 
                 self.prepend(space, node, predicates);
@@ -270,7 +341,10 @@ impl AtomCollection {
             // Return a query parsing error on unknown capture names
             unknown => {
                 return Err(FormatterError::Query(
-                    format!("@{unknown} is not a valid capture name"),
+                    format!(
+                        "@{unknown} is not a valid capture name. Valid capture names are: @{}",
+                        CAPTURE_NAMES.join(", @")
+                    ),
                     None,
                 ))
             }
@@ -409,7 +483,7 @@ impl AtomCollection {
             if let Some(parent) = node.parent() {
                 let parent_id = parent.id();
 
-                if self.multi_line_nodes.contains(&parent_id) {
+                if self.multi_line_nodes.contains(&parent_id) && !self.reflow {
                     tracing::debug!(
                         "Expanding softline to hardline in node {:?} with parent {}: {:?}",
                         node,
@@ -417,16 +491,18 @@ impl AtomCollection {
                         parent
                     );
                     Atom::Hardline
-                } else if spaced {
+                } else {
                     tracing::debug!(
-                        "Expanding softline to space in node {:?} with parent {}: {:?}",
+                        "Expanding softline to breakpoint in node {:?} with parent {}: {:?}",
                         node,
                         parent_id,
                         parent
                     );
-                    Atom::Space
-                } else {
-                    Atom::Empty
+                    // All softlines with the same parent are broken together.
+                    Atom::Breakpoint {
+                        spaced,
+                        group: parent_id,
+                    }
                 }
             } else {
                 Atom::Empty
@@ -441,7 +517,9 @@ impl AtomCollection {
     // It does two passes over the atom collection: the first one associates each `ScopedSoftline`
     // to its scope, and decides what to replace them with when the scope ends.
     // The second pass applies the modifications to the atoms.
-    fn post_process_scopes(&mut self) {
+    // If the scopes are unbalanced, or a scoped atom is found outside of its scope,
+    // we return an error naming the offending scope.
+    fn post_process_scopes(&mut self) -> FormatterResult<()> {
         type ScopeId = String;
         type LineIndex = u32;
         type ScopedNodeId = usize;
@@ -455,15 +533,10 @@ impl AtomCollection {
         // atom to each `ScopedSoftline` atom (identified by their `id` field), then apply
         // the modifications in a second pass over the atoms.
         let mut modifications: HashMap<ScopedNodeId, Atom> = HashMap::new();
-        // `force_apply_modifications` keeps track of whether something has gone wrong in the
-        // post-processing (e.g. closing an unopened scope, finding a scoped atom outside
-        // of its scope). If we detect any error, we don't skip the "Apply modifications" part
-        // of the processing, even if the `modifications` map is empty. This is to ensure we will
-        // get rid of misplaced scoped atoms.
-        let mut force_apply_modifications = false;
 
         for atom in &self.atoms {
             if let Atom::Leaf { id, .. } = atom {
+                let scope_end = *id;
                 // Begin a new scope
                 if let Some((line_start, scope_ids)) = self.scope_begin.get(id) {
                     for scope_id in scope_ids {
@@ -483,12 +556,16 @@ impl AtomCollection {
                             let multiline = line_start != *line_end;
                             for atom in atoms {
                                 if let Atom::ScopedSoftline { id, spaced, .. } = atom {
-                                    let new_atom = if multiline {
+                                    // When reflowing, a multi-line scope is only broken if it does not fit,
+                                    // but scoped conditionals still follow the layout of the input.
+                                    let new_atom = if multiline && !self.reflow {
                                         Atom::Hardline
-                                    } else if *spaced {
-                                        Atom::Space
                                     } else {
-                                        Atom::Empty
+                                        // All softlines in this scope are broken together.
+                                        Atom::Breakpoint {
+                                            spaced: *spaced,
+                                            group: scope_end,
+                                        }
                                     };
                                     modifications.insert(*id, new_atom);
                                 } else if let Atom::ScopedConditional {
@@ -509,8 +586,10 @@ impl AtomCollection {
                                 }
                             }
                         } else {
-                            tracing::warn!("Closing unopened scope {scope_id:?}");
-                            force_apply_modifications = true;
+                            return Err(FormatterError::Query(
+                                format!("The scope {scope_id:?} was closed by @end_scope without a matching @begin_scope"),
+                                None,
+                            ));
                         }
                     }
                 }
@@ -520,8 +599,10 @@ impl AtomCollection {
                 {
                     vec.push(atom);
                 } else {
-                    tracing::warn!("Found scoped softline {:?} outside of its scope", atom);
-                    force_apply_modifications = true;
+                    return Err(FormatterError::Query(
+                        format!("Found a scoped softline outside of its scope {scope_id:?}"),
+                        None,
+                    ));
                 }
             // Register the ScopedConditional in the correct scope
             } else if let Atom::ScopedConditional { scope_id, .. } = atom {
@@ -529,23 +610,31 @@ impl AtomCollection {
                 {
                     vec.push(atom);
                 } else {
-                    tracing::warn!("Found scoped conditional {:?} outside of its scope", atom);
-                    force_apply_modifications = true;
+                    return Err(FormatterError::Query(
+                        format!("Found a scoped conditional outside of its scope {scope_id:?}"),
+                        None,
+                    ));
                 }
             }
         }
-        let still_opened: Vec<&String> = opened_scopes
+        let mut still_opened: Vec<&String> = opened_scopes
             .into_iter()
             .filter_map(|(scope_id, vec)| if vec.is_empty() { None } else { Some(scope_id) })
             .collect();
-        if !still_opened.is_empty() {
-            tracing::warn!("Some scopes have been left opened: {:?}", still_opened);
-            force_apply_modifications = true;
+        // Sort the scopes so that the error message is deterministic.
+        still_opened.sort();
+        if let Some(scope_id) = still_opened.first() {
+            return Err(FormatterError::Query(
+                format!(
+                    "The scope {scope_id:?} was opened by @begin_scope without a matching @end_scope"
+                ),
+                None,
+            ));
         }
 
         // Apply modifications.
         // For performance reasons, skip this step if there are no modifications to make
-        if !modifications.is_empty() || force_apply_modifications {
+        if !modifications.is_empty() {
             for atom in &mut self.atoms {
                 if let Atom::ScopedSoftline { id, .. } = atom {
                     if let Some(replacement) = modifications.remove(id) {
@@ -570,6 +659,8 @@ impl AtomCollection {
                 }
             }
         }
+
+        Ok(())
     }
 
     // Separate post_processing of Delete sections, to avoid interference with whitespace logic
@@ -601,8 +692,8 @@ impl AtomCollection {
     // If there are several tokens of different kind one after the other,
     // the blank line is kept over the new line which itself is kept over the space.
     // Furthermore, this function put the indentation delimiters before any space/line atom.
-    pub fn post_process(&mut self) {
-        self.post_process_scopes();
+    pub fn post_process(&mut self) -> FormatterResult<()> {
+        self.post_process_scopes()?;
         self.post_process_deletes();
         let mut prev: Option<&mut Atom> = None;
         for next in &mut self.atoms {
@@ -617,19 +708,30 @@ impl AtomCollection {
                             Atom::Space | Atom::Antispace => {
                                 *next = Atom::Empty;
                             }
+                            Atom::Breakpoint { spaced, .. } => *spaced = false,
                             _ => {}
                         }
                     }
 
                     // If the last atom is a space/line
-                    Atom::Empty | Atom::Space | Atom::Hardline | Atom::Blankline => {
+                    Atom::Empty
+                    | Atom::Space
+                    | Atom::Breakpoint { .. }
+                    | Atom::Hardline
+                    | Atom::Blankline(_) => {
                         match next {
                             // And the next one is also a space/line
-                            Atom::Empty | Atom::Space | Atom::Hardline | Atom::Blankline => {
+                            Atom::Empty
+                            | Atom::Space
+                            | Atom::Breakpoint { .. }
+                            | Atom::Hardline
+                            | Atom::Blankline(_) => {
                                 // Set the non-dominant one to empty.
                                 if is_dominant(next, prev) {
+                                    absorb_space(next, prev);
                                     **prev = Atom::Empty;
                                 } else {
+                                    absorb_space(prev, next);
                                     *next = Atom::Empty;
                                 }
                             }
@@ -654,9 +756,10 @@ impl AtomCollection {
                 match next {
                     Atom::Empty
                     | Atom::Space
+                    | Atom::Breakpoint { .. }
                     | Atom::Antispace
                     | Atom::Hardline
-                    | Atom::Blankline => {
+                    | Atom::Blankline(_) => {
                         *next = Atom::Empty;
                     }
                     _ => {}
@@ -674,6 +777,8 @@ impl AtomCollection {
         collapse_spaces_before_antispace(&mut self.atoms);
 
         tracing::debug!("List of atoms after post-processing: {:?}", self.atoms);
+
+        Ok(())
     }
 
     fn next_id(&mut self) -> usize {
@@ -724,6 +829,7 @@ impl AtomCollection {
 pub struct QueryPredicates {
     pub delimiter: Option<String>,
     pub scope_id: Option<String>,
+    pub blank_lines: Option<usize>,
     pub single_line_only: bool,
     pub multi_line_only: bool,
     pub single_line_scope_only: Option<String>,
@@ -739,6 +845,8 @@ fn collapse_spaces_before_antispace(v: &mut [Atom]) {
             antispace_mode = true;
         } else if *a == Atom::Space && antispace_mode {
             *a = Atom::Empty;
+        } else if let (Atom::Breakpoint { spaced, .. }, true) = (&mut *a, antispace_mode) {
+            *spaced = false;
         } else {
             antispace_mode = false;
         }
@@ -746,17 +854,32 @@ fn collapse_spaces_before_antispace(v: &mut [Atom]) {
 }
 
 // This function is only expected to take spaces and newlines as argument.
-// It defines the order Blankline > Hardline > Space > Empty.
+// It defines the order Blankline > Hardline > Breakpoint > Space > Empty,
+// where a Blankline with more blank lines dominates one with fewer.
 fn is_dominant(next: &Atom, prev: &Atom) -> bool {
     match next {
         Atom::Empty => false,
         Atom::Space => *prev == Atom::Empty,
-        Atom::Hardline => *prev == Atom::Space || *prev == Atom::Empty,
-        Atom::Blankline => *prev != Atom::Blankline,
+        Atom::Breakpoint { .. } => matches!(prev, Atom::Space | Atom::Empty),
+        Atom::Hardline => matches!(prev, Atom::Breakpoint { .. } | Atom::Space | Atom::Empty),
+        Atom::Blankline(count) => match prev {
+            Atom::Blankline(prev_count) => count > prev_count,
+            _ => true,
+        },
         _ => panic!("Unexpected character in is_dominant"),
     }
 }
 
+// When a breakpoint is kept over a space, it must still render the space
+// if the line is not broken.
+fn absorb_space(dominant: &mut Atom, other: &Atom) {
+    if let Atom::Breakpoint { spaced, .. } = dominant {
+        if matches!(other, Atom::Space | Atom::Breakpoint { spaced: true, .. }) {
+            *spaced = true;
+        }
+    }
+}
+
 fn dfs_flatten<'tree>(node: &Node<'tree>) -> Vec<Node<'tree>> {
     // Flatten the tree, depth-first, into a vector of nodes
     let mut walker = node.walk();
@@ -1,13 +1,56 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, path::Path};
 
-use crate::{language::Language, FormatterError, FormatterResult};
+use serde::Deserialize;
 
+use crate::{
+    language::{Language, SupportedLanguage},
+    FormatterError, FormatterResult, IoError,
+};
+
+#[derive(Deserialize)]
 pub struct Configuration {
     pub language: Vec<Language>,
 }
 
 impl Configuration {
-    // TODO: Should be able to take a filepath.
+    /// Reads a configuration from the TOML file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be read, or it is not a valid configuration, a `FormatterError` will be returned.
+    pub fn parse_file(path: &Path) -> FormatterResult<Self> {
+        let toml = std::fs::read_to_string(path).map_err(|e| {
+            FormatterError::Io(IoError::Filesystem(
+                format!("Could not read configuration file '{}'", path.display()),
+                e,
+            ))
+        })?;
+        Self::parse_str(&toml).map_err(|err| match err {
+            FormatterError::Configuration(message, source) => {
+                FormatterError::Configuration(format!("{message} in '{}'", path.display()), source)
+            }
+            err => err,
+        })
+    }
+
+    /// Parses a configuration from TOML, such as
+    ///
+    /// ```toml
+    /// [[language]]
+    /// name = "feather"
+    /// extensions = ["ftr"]
+    /// indent = "    "
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the input is not a valid configuration, a `FormatterError` will be returned.
+    pub fn parse_str(toml: &str) -> FormatterResult<Self> {
+        toml::from_str(toml).map_err(|e| {
+            FormatterError::Configuration("Could not parse configuration".into(), Some(e))
+        })
+    }
+
     // TODO: Should return a FormatterResult rather than panicking.
     #[must_use]
     pub fn parse_default_config() -> Self {
@@ -24,6 +67,7 @@ impl Configuration {
                     set
                 },
                 indent: Some("    ".to_owned()),
+                grammar: None,
             }],
         }
     }
@@ -39,6 +83,31 @@ impl Configuration {
         res
     }
 
+    /// The supported languages that this configuration has an entry for.
+    /// Unlike [`Configuration::get_language`], this does not require knowing the name of a language in advance,
+    /// so it can be used to list the languages and their extensions.
+    #[must_use]
+    pub fn supported_languages(&self) -> Vec<&'static SupportedLanguage> {
+        SupportedLanguage::ALL
+            .iter()
+            .filter(|supported| {
+                self.language
+                    .iter()
+                    .any(|lang| lang.name == supported.name())
+            })
+            .collect()
+    }
+
+    /// Finds the language of the file at the given path from its extension.
+    /// Returns [`None`] if the path has no extension, or no language is configured with its extension.
+    #[must_use]
+    pub fn language_for_path(&self, path: &Path) -> Option<&Language> {
+        let extension = path.extension()?.to_str()?;
+        self.language
+            .iter()
+            .find(|lang| lang.extensions.contains(extension))
+    }
+
     pub fn get_language<T: AsRef<str>>(&self, name: T) -> FormatterResult<&Language> {
         for lang in &self.language {
             if lang.name == name.as_ref() {
@@ -50,3 +119,60 @@ impl Configuration {
         ));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::Configuration;
+    use crate::{FormatterError, SupportedLanguage};
+
+    #[test]
+    fn parse_languages() {
+        let config = Configuration::parse_str(
+            r#"
+            [[language]]
+            name = "quill"
+            extensions = ["quill", "ql"]
+            indent = "\t"
+            grammar = "feather"
+            "#,
+        )
+        .unwrap();
+        let language = config.get_language("quill").unwrap();
+        assert_eq!(language.indent.as_deref(), Some("\t"));
+        assert_eq!(language.grammar.as_deref(), Some("feather"));
+        assert!(language.extensions.contains("ql"));
+        assert_eq!(language.grammars().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn supported_languages() {
+        let config = Configuration::parse_default_config();
+        let languages = config.supported_languages();
+        assert_eq!(languages.len(), 1);
+        assert!(matches!(languages[0], SupportedLanguage::Feather));
+        assert!(languages[0].extensions(&config).contains("ftr"));
+
+        // Languages without an entry in the configuration are not listed.
+        let config = Configuration::parse_str("language = []").unwrap();
+        assert!(config.supported_languages().is_empty());
+    }
+
+    #[test]
+    fn language_for_path() {
+        let config = Configuration::parse_default_config();
+        let language = config.language_for_path(Path::new("src/test.ftr")).unwrap();
+        assert_eq!(language.name, "feather");
+        assert!(config.language_for_path(Path::new("test.rs")).is_none());
+        assert!(config.language_for_path(Path::new("ftr")).is_none());
+    }
+
+    #[test]
+    fn malformed_configuration() {
+        assert!(matches!(
+            Configuration::parse_str("[[language]]\nname = 3\n"),
+            Err(FormatterError::Configuration(_, Some(_)))
+        ));
+    }
+}
@@ -11,7 +11,9 @@ pub enum FormatterError {
     /// The input produced output that isn't idempotent, i.e. formatting the
     /// output again made further changes. If this happened using our provided
     /// query files, it is a bug. Please log an issue.
-    Idempotence,
+    /// We record the first line (counting from 1) at which the two outputs differ,
+    /// and a unified diff of the region that changed.
+    Idempotence { line: usize, diff: String },
 
     /// An internal error occurred. This is a bug. Please log an issue.
     Internal(String, Option<Box<dyn Error>>),
@@ -32,6 +34,9 @@ pub enum FormatterError {
     /// provided query files, it is a bug. Please log an issue.
     Query(String, Option<tree_sitter_facade::QueryError>),
 
+    /// There was an error in the configuration file.
+    Configuration(String, Option<toml::de::Error>),
+
     /// Could not detect the input language from the (filename, Option<extension>)
     LanguageDetection(PathBuf, Option<String>),
 
@@ -55,10 +60,10 @@ impl fmt::Display for FormatterError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let please_log_message = "It would be helpful if you logged this error at https://github.com/tweag/topiary/issues/new?assignees=&labels=type%3A+bug&template=bug_report.md";
         match self {
-            Self::Idempotence => {
+            Self::Idempotence { line, diff } => {
                 write!(
                     f,
-                    "The formatter did not produce the same result when invoked twice (idempotence check).\n{please_log_message}"
+                    "The formatter did not produce the same result when invoked twice (idempotence check).\nThe results first differ at line {line}:\n{diff}\n{please_log_message}"
                 )
             }
 
@@ -103,6 +108,7 @@ impl fmt::Display for FormatterError {
 
             Self::Internal(message, _)
             | Self::Query(message, _)
+            | Self::Configuration(message, _)
             | Self::Io(IoError::Filesystem(message, _) | IoError::Generic(message, _)) => {
                 write!(f, "{message}")
             }
@@ -117,7 +123,7 @@ impl fmt::Display for FormatterError {
 impl Error for FormatterError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
-            Self::Idempotence
+            Self::Idempotence { .. }
             | Self::Parsing { .. }
             | Self::PatternDoesNotMatch(_)
             | Self::LanguageDetection(_, _)
@@ -125,6 +131,7 @@ impl Error for FormatterError {
             | Self::UnsupportedLanguage(_) => None,
             Self::Internal(_, source) => source.as_ref().map(Deref::deref),
             Self::Query(_, source) => source.as_ref().map(|e| e as &dyn Error),
+            Self::Configuration(_, source) => source.as_ref().map(|e| e as &dyn Error),
             Self::Io(IoError::Filesystem(_, source)) => Some(source),
             Self::Io(IoError::Generic(_, Some(source))) => Some(source.as_ref()),
             Self::Formatting(err) => Some(err),
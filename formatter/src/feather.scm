@@ -39,6 +39,9 @@
   "for"
   "let"
   "Sort"
+  "max"
+  "imax"
+  "succ"
   "inst"
   "intro"
   "match"
@@ -56,6 +59,7 @@
   "{"
   "}"
   "0"
+  "_"
 ] @prepend_space @append_space
 
 ; Input softlines before and after all comments. This means that the input
@@ -65,6 +69,8 @@
     (line_comment)
 ] @prepend_input_softline
 
+(line_comment) @append_hardline
+
 [
   ":"
   ","
@@ -87,6 +93,17 @@
 
 ";" @append_spaced_softline
 
+(universe left: _ @append_space)
+
+; Universe parameters and arguments are written without spaces inside their braces,
+; as in `def id.{u, v}` and `inst id.{0, u}`.
+".{" @append_indent_start
+
+[
+  (universe_params "}" @prepend_antispace)
+  (universe_args "}" @prepend_antispace)
+]
+
 [
   "->"
   "=>"
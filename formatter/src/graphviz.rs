@@ -1,6 +1,7 @@
 /// GraphViz visualisation for our SyntaxTree representation
-/// Named syntax nodes are elliptical; anonymous are rectangular
-use std::{borrow::Cow, fmt, io};
+/// Named syntax nodes are elliptical; anonymous are dashed rectangles.
+/// Labels can optionally include each node's field name and byte range, as in `body: inst [20..26]`
+use std::{borrow::Cow, io};
 
 use crate::{tree_sitter::SyntaxNode, FormatterResult};
 
@@ -62,29 +63,46 @@ fn escape(input: &str) -> Cow<str> {
     }
 }
 
-impl fmt::Display for SyntaxNode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let shape = if self.is_named { "ellipse" } else { "box" };
-
-        writeln!(
-            f,
-            "  {} [label=\"{}\", shape={shape}];",
-            self.id,
-            escape(&self.kind)
-        )?;
+fn write_node(
+    output: &mut dyn io::Write,
+    node: &SyntaxNode,
+    byte_ranges: bool,
+    field_names: bool,
+) -> io::Result<()> {
+    let style = if node.is_named {
+        "shape=ellipse"
+    } else {
+        "shape=box, style=dashed"
+    };
 
-        for child in &self.children {
-            writeln!(f, "  {} -- {};", self.id, child.id)?;
-            write!(f, "{child}")?;
+    write!(output, "  {} [label=\"", node.id)?;
+    if field_names {
+        if let Some(field_name) = &node.field_name {
+            write!(output, "{}: ", escape(field_name))?;
         }
+    }
+    write!(output, "{}", escape(&node.kind))?;
+    if byte_ranges {
+        write!(output, " [{}..{}]", node.start_byte, node.end_byte)?;
+    }
+    writeln!(output, "\", {style}];")?;
 
-        Ok(())
+    for child in &node.children {
+        writeln!(output, "  {} -- {};", node.id, child.id)?;
+        write_node(output, child, byte_ranges, field_names)?;
     }
+
+    Ok(())
 }
 
-pub fn write(output: &mut dyn io::Write, root: &SyntaxNode) -> FormatterResult<()> {
+pub fn write(
+    output: &mut dyn io::Write,
+    root: &SyntaxNode,
+    byte_ranges: bool,
+    field_names: bool,
+) -> FormatterResult<()> {
     writeln!(output, "graph {{")?;
-    write!(output, "{root}")?;
+    write_node(output, root, byte_ranges, field_names)?;
     writeln!(output, "}}")?;
 
     Ok(())
@@ -92,7 +110,8 @@ pub fn write(output: &mut dyn io::Write, root: &SyntaxNode) -> FormatterResult<(
 
 #[cfg(test)]
 mod test {
-    use super::escape;
+    use super::{escape, write};
+    use crate::{tree_sitter, Configuration, SyntaxNode};
     use std::borrow::Cow;
 
     #[test]
@@ -123,4 +142,20 @@ mod test {
             _ => panic!("Expected an owned, escaped string"),
         }
     }
+
+    #[test]
+    fn detailed_labels() {
+        let config = Configuration::parse_default_config();
+        let grammars = config.get_language("feather").unwrap().grammars().unwrap();
+        let (tree, _) = tree_sitter::parse("module test\n", &grammars).unwrap();
+        let root: SyntaxNode = tree.root_node().into();
+
+        let mut output = Vec::new();
+        write(&mut output, &root, true, true).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("[label=\"source_file [0..12]\", shape=ellipse];"));
+        assert!(output.contains("[label=\"path: path [7..11]\", shape=ellipse];"));
+        assert!(output.contains("[label=\"module [0..6]\", shape=box, style=dashed];"));
+    }
 }
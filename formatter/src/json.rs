@@ -0,0 +1,147 @@
+/// JSON visualisation for our SyntaxTree representation
+/// The output is pretty-printed with a fixed key order, so that it diffs cleanly
+use std::{borrow::Cow, io};
+
+use crate::{tree_sitter::SyntaxNode, FormatterResult};
+
+/// Escapes a string so that it can be placed between quotes in a JSON document
+fn escape(input: &str) -> Cow<str> {
+    if !input
+        .chars()
+        .any(|c| matches!(c, '"' | '\\') || c.is_control())
+    {
+        return input.into();
+    }
+
+    let mut buffer = String::with_capacity(input.len() + 2);
+    for current in input.chars() {
+        match current {
+            '"' => buffer += r#"\""#,
+            '\\' => buffer += r#"\\"#,
+            '\n' => buffer += r#"\n"#,
+            '\r' => buffer += r#"\r"#,
+            '\t' => buffer += r#"\t"#,
+            c if c.is_control() => buffer += &format!("\\u{:04x}", c as u32),
+            c => buffer.push(c),
+        }
+    }
+    buffer.into()
+}
+
+fn write_node(output: &mut dyn io::Write, node: &SyntaxNode, depth: usize) -> io::Result<()> {
+    let indent = "  ".repeat(depth + 1);
+
+    writeln!(output, "{{")?;
+    writeln!(output, "{indent}\"kind\": \"{}\",", escape(&node.kind))?;
+    writeln!(output, "{indent}\"named\": {},", node.is_named)?;
+    match &node.field_name {
+        Some(field_name) => writeln!(output, "{indent}\"field\": \"{}\",", escape(field_name))?,
+        None => writeln!(output, "{indent}\"field\": null,")?,
+    }
+    writeln!(output, "{indent}\"start_byte\": {},", node.start_byte)?;
+    writeln!(output, "{indent}\"end_byte\": {},", node.end_byte)?;
+
+    if node.children.is_empty() {
+        writeln!(output, "{indent}\"children\": []")?;
+    } else {
+        writeln!(output, "{indent}\"children\": [")?;
+        for (idx, child) in node.children.iter().enumerate() {
+            write!(output, "{indent}  ")?;
+            write_node(output, child, depth + 2)?;
+            if idx + 1 < node.children.len() {
+                writeln!(output, ",")?;
+            } else {
+                writeln!(output)?;
+            }
+        }
+        writeln!(output, "{indent}]")?;
+    }
+
+    write!(output, "{}}}", "  ".repeat(depth))
+}
+
+pub fn write(output: &mut dyn io::Write, root: &SyntaxNode) -> FormatterResult<()> {
+    write_node(output, root, 0)?;
+    writeln!(output)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{escape, write};
+    use crate::tree_sitter::SyntaxNode;
+
+    #[test]
+    fn escape_special_characters() {
+        assert_eq!(escape("foo"), "foo");
+        assert_eq!(escape("\""), r#"\""#);
+        assert_eq!(escape("a\\b"), r#"a\\b"#);
+        assert_eq!(escape("\n\t"), r#"\n\t"#);
+        assert_eq!(escape("\u{1}"), r#"\u0001"#);
+    }
+
+    #[test]
+    fn write_tree() {
+        let root = SyntaxNode {
+            id: 0,
+            kind: "app".into(),
+            is_named: true,
+            field_name: None,
+            start_byte: 0,
+            end_byte: 3,
+            children: vec![
+                SyntaxNode {
+                    id: 1,
+                    kind: "identifier".into(),
+                    is_named: true,
+                    field_name: Some("left".into()),
+                    start_byte: 0,
+                    end_byte: 1,
+                    children: Vec::new(),
+                },
+                SyntaxNode {
+                    id: 2,
+                    kind: "\"".into(),
+                    is_named: false,
+                    field_name: None,
+                    start_byte: 2,
+                    end_byte: 3,
+                    children: Vec::new(),
+                },
+            ],
+        };
+
+        let mut output = Vec::new();
+        write(&mut output, &root).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"{
+  "kind": "app",
+  "named": true,
+  "field": null,
+  "start_byte": 0,
+  "end_byte": 3,
+  "children": [
+    {
+      "kind": "identifier",
+      "named": true,
+      "field": "left",
+      "start_byte": 0,
+      "end_byte": 1,
+      "children": []
+    },
+    {
+      "kind": "\"",
+      "named": false,
+      "field": null,
+      "start_byte": 2,
+      "end_byte": 3,
+      "children": []
+    }
+  ]
+}
+"#
+        );
+    }
+}
@@ -2,14 +2,19 @@ use std::collections::HashSet;
 use std::io;
 use std::path::{Path, PathBuf};
 
+use serde::Deserialize;
+
 use crate::{Configuration, FormatterError, FormatterResult, IoError};
 
 /// The languages that we support with query files.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
 pub struct Language {
     pub name: String,
     pub extensions: HashSet<String>,
     pub indent: Option<String>,
+    /// The name of the Tree-sitter grammar used to parse this language.
+    /// If this is not given, the name of the language is used.
+    pub grammar: Option<String>,
 }
 
 impl Language {
@@ -19,19 +24,14 @@ impl Language {
     ///
     /// If the file extension is not supported, a `FormatterError` will be returned.
     pub fn detect<P: AsRef<Path>>(path: P, config: &Configuration) -> FormatterResult<&Self> {
-        let pb = &path.as_ref().to_path_buf();
-        if let Some(extension) = pb.extension().map(|ext| ext.to_string_lossy()) {
-            for lang in &config.language {
-                if lang.extensions.contains::<String>(&extension.to_string()) {
-                    return Ok(lang);
-                }
-            }
-            return Err(FormatterError::LanguageDetection(
-                pb.clone(),
-                Some(extension.to_string()),
-            ));
-        }
-        Err(FormatterError::LanguageDetection(pb.clone(), None))
+        let path = path.as_ref();
+        config.language_for_path(path).ok_or_else(|| {
+            FormatterError::LanguageDetection(
+                path.to_path_buf(),
+                path.extension()
+                    .map(|extension| extension.to_string_lossy().into_owned()),
+            )
+        })
     }
 
     /// Convenience alias to return the query file path for the Language.
@@ -49,7 +49,7 @@ impl Language {
     /// If the language is not supported, a `FormatterError` will be returned.
     #[cfg(not(target_arch = "wasm32"))]
     pub fn grammars(&self) -> FormatterResult<Vec<tree_sitter_facade::Language>> {
-        Ok(match self.name.as_str() {
+        Ok(match self.grammar.as_deref().unwrap_or(&self.name) {
             // "bash" => vec![tree_sitter_bash::language()],
             // "json" => vec![tree_sitter_json::language()],
             // "nickel" => vec![tree_sitter_nickel::language()],
@@ -137,6 +137,9 @@ pub enum SupportedLanguage {
 }
 
 impl SupportedLanguage {
+    /// Every supported language, in the order they should be listed to users.
+    pub const ALL: &'static [SupportedLanguage] = &[SupportedLanguage::Feather];
+
     pub fn to_language<'config>(&self, configuration: &'config Configuration) -> &'config Language {
         let name = self.name();
 
@@ -151,6 +154,14 @@ impl SupportedLanguage {
         unreachable!()
     }
 
+    /// The file extensions of this language, such as `ftr` for Feather, without a leading dot.
+    pub fn extensions<'config>(
+        &self,
+        configuration: &'config Configuration,
+    ) -> &'config HashSet<String> {
+        &self.to_language(configuration).extensions
+    }
+
     pub fn name(&self) -> &str {
         match self {
             // SupportedLanguage::Json => "json",
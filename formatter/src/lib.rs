@@ -10,21 +10,31 @@
 //! More details can be found on
 //! [GitHub](https://github.com/tweag/topiary).
 
-use std::io;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use rayon::prelude::*;
+
+use crate::atom_collection::AtomCollection;
 
 pub use crate::{
     configuration::Configuration,
     error::{FormatterError, IoError},
     language::{Language, SupportedLanguage},
-    tree_sitter::{apply_query, SyntaxNode, Visualisation},
+    tree_sitter::{apply_query, CompiledQuery, SyntaxNode, Visualisation},
 };
 
 mod atom_collection;
 mod configuration;
 mod error;
 mod graphviz;
+mod json;
 mod language;
 mod pretty;
+mod sexp;
 mod tree_sitter;
 
 /// An atom represents a small piece of the output. We turn Tree-sitter nodes
@@ -32,9 +42,10 @@ mod tree_sitter;
 /// of atoms is rendered to the output.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub enum Atom {
-    /// We don't allow consecutive `Hardline`, but a `Blankline` will render two
-    /// newlines to produce a blank line.
-    Blankline,
+    /// We don't allow consecutive `Hardline`, but a `Blankline` will render
+    /// enough newlines to produce the given number of blank lines.
+    /// When several blank lines are adjacent, only the largest is kept.
+    Blankline(usize),
     /// A "no-op" atom that will not produce any output.
     #[default]
     Empty,
@@ -58,10 +69,18 @@ pub enum Atom {
     /// Represents a literal string, such as a semicolon.
     Literal(String),
     /// Represents a softline. It will be turned into a hardline for multi-line
-    /// constructs, and either a space or nothing for single-line constructs.
+    /// constructs, and a `Breakpoint` for single-line constructs.
     Softline {
         spaced: bool,
     },
+    /// Represents a point at which a line may be broken if it would otherwise exceed
+    /// the maximum line width. If the line is not broken, this renders as a space or nothing.
+    /// All breakpoints with the same `group` are broken together, so that a construct
+    /// is either entirely on one line or entirely broken.
+    Breakpoint {
+        spaced: bool,
+        group: usize,
+    },
     /// Represents a space. Consecutive spaces are reduced to one before rendering.
     Space,
     /// Represents the destruction of errant spaces. Adjacent consecutive spaces are
@@ -103,11 +122,116 @@ pub enum ScopeCondition {
 /// A convenience wrapper around `std::result::Result<T, FormatterError>`.
 pub type FormatterResult<T> = std::result::Result<T, FormatterError>;
 
+/// The maximum line width used when none is specified.
+pub const DEFAULT_MAX_WIDTH: usize = 80;
+
+/// The unit of indentation used when formatting.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IndentStyle {
+    /// Indent with a single tab character per level.
+    Tabs,
+    /// Indent with the given number of spaces per level.
+    Spaces(usize),
+}
+
+impl IndentStyle {
+    /// The string emitted for each level of indentation.
+    #[must_use]
+    pub fn unit(self) -> String {
+        match self {
+            IndentStyle::Tabs => "\t".to_owned(),
+            IndentStyle::Spaces(width) => " ".repeat(width),
+        }
+    }
+}
+
+/// Detects the unit of indentation used by the given input, by sampling the leading whitespace of its indented lines.
+///
+/// Returns `None` if no line is indented, or if the indentation is ambiguous:
+/// that is, if both tabs and spaces are used, or if the indentation widths are not all multiples of the smallest one.
+#[must_use]
+pub fn detect_indent(content: &str) -> Option<IndentStyle> {
+    let mut tabs = false;
+    let mut widths = Vec::new();
+    for line in content.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with('\t') {
+            tabs = true;
+        } else {
+            let width = line.len() - line.trim_start_matches(' ').len();
+            if width > 0 {
+                widths.push(width);
+            }
+        }
+    }
+
+    match (tabs, widths.iter().min()) {
+        (true, None) => Some(IndentStyle::Tabs),
+        (false, Some(&unit)) if widths.iter().all(|width| width % unit == 0) => {
+            Some(IndentStyle::Spaces(unit))
+        }
+        _ => None,
+    }
+}
+
+/// The line endings written when formatting.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LineEnding {
+    /// Use the line ending of the first line of the input, or `\n` if it has only one line.
+    #[default]
+    Detect,
+    /// Always end lines with `\n`.
+    Lf,
+    /// Always end lines with `\r\n`.
+    CrLf,
+}
+
+impl LineEnding {
+    /// The string written at the end of each line of the given input.
+    #[must_use]
+    pub fn resolve(self, input: &str) -> &'static str {
+        match self {
+            LineEnding::Detect => match input.find('\n') {
+                Some(i) if input[..i].ends_with('\r') => "\r\n",
+                _ => "\n",
+            },
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
 /// Operations that can be performed by the formatter.
 #[derive(Clone, Copy, Debug)]
 pub enum Operation {
-    Format { skip_idempotence: bool },
-    Visualise { output_format: Visualisation },
+    /// Formats the input.
+    /// If `indent` is given, it overrides the indentation specified by the language.
+    /// Otherwise, if `detect_indent` is true, the indentation already used by the input is kept,
+    /// falling back to the language's indentation if it cannot be detected; see [`detect_indent`].
+    ///
+    /// If `reflow` is false, a construct that spans several lines of the input is kept on several lines.
+    /// If it is true, such a construct is joined onto a single line if it fits within `max_width`.
+    Format {
+        skip_idempotence: bool,
+        indent: Option<IndentStyle>,
+        detect_indent: bool,
+        max_width: usize,
+        line_ending: LineEnding,
+        reflow: bool,
+    },
+    /// Formats only the top-level nodes of the input that intersect the byte range `start..end`,
+    /// using the default indentation and line width, and the line endings of the input.
+    /// The rest of the input is left unchanged.
+    FormatRange {
+        start: usize,
+        end: usize,
+        skip_idempotence: bool,
+    },
+    Visualise {
+        output_format: Visualisation,
+    },
 }
 
 /// The function that takes an input and formats, or visualises an output.
@@ -131,28 +255,60 @@ pub fn formatter(
     })?;
 
     match operation {
-        Operation::Format { skip_idempotence } => {
-            // All the work related to tree-sitter and the query is done here
-            tracing::info!("Apply Tree-sitter query");
-            let mut atoms = tree_sitter::apply_query(&content, query, grammars, false)?;
-
-            // Various post-processing of whitespace
-            atoms.post_process();
-
-            // Pretty-print atoms
-            tracing::info!("Pretty-print output");
-            let rendered = pretty::render(
-                &atoms[..],
-                // Default to "  " is the language has no indentation specified
-                language.indent.as_ref().map_or("  ", |v| v.as_str()),
-            )?;
-            let trimmed = trim_whitespace(&rendered);
-
-            if !skip_idempotence {
-                idempotence_check(&trimmed, query, language, grammars)?;
+        Operation::Format {
+            skip_idempotence,
+            indent,
+            detect_indent,
+            max_width,
+            line_ending,
+            reflow,
+        } => {
+            let query = CompiledQuery::new(query, grammars)?;
+            let options = RenderOptions {
+                indent,
+                detect_indent,
+                max_width,
+                line_ending,
+                reflow,
+            };
+            if skip_idempotence {
+                // The output is streamed, rather than being held in memory all at once.
+                let mut output = io::BufWriter::new(output);
+                write_formatted(&content, &query, language, options, &mut output)?;
+                output.flush()?;
+            } else {
+                // The idempotence check needs the whole output, so we must buffer it.
+                let formatted = format_content(&content, &query, language, false, options)?;
+                write!(output, "{formatted}")?;
             }
+        }
 
-            write!(output, "{trimmed}")?;
+        Operation::FormatRange {
+            start,
+            end,
+            skip_idempotence,
+        } => {
+            // The whole input is formatted, since Tree-sitter needs the context of the whole file.
+            let mut formatted = Vec::new();
+            formatter(
+                &mut content.as_bytes(),
+                &mut formatted,
+                query,
+                language,
+                grammars,
+                Operation::Format {
+                    skip_idempotence,
+                    indent: None,
+                    detect_indent: false,
+                    max_width: DEFAULT_MAX_WIDTH,
+                    line_ending: LineEnding::Detect,
+                    reflow: false,
+                },
+            )?;
+            let formatted = String::from_utf8(formatted)?;
+
+            let spliced = splice_range(&content, &formatted, grammars, start, end)?;
+            write!(output, "{spliced}")?;
         }
 
         Operation::Visualise { output_format } => {
@@ -160,7 +316,14 @@ pub fn formatter(
             let root: SyntaxNode = tree.root_node().into();
 
             match output_format {
-                Visualisation::GraphViz => graphviz::write(output, &root)?,
+                Visualisation::GraphViz {
+                    byte_ranges,
+                    field_names,
+                } => graphviz::write(output, &root, byte_ranges, field_names)?,
+                Visualisation::Json => json::write(output, &root)?,
+                Visualisation::SExpression { byte_ranges } => {
+                    sexp::write(output, &root, byte_ranges)?
+                }
             };
         }
     };
@@ -168,89 +331,320 @@ pub fn formatter(
     Ok(())
 }
 
-pub fn format_feather(input: &str) -> Option<String> {
-    let mut output = Vec::new();
-    let query = include_str!("feather.scm");
+/// A language whose configuration, grammars and query have been loaded and compiled.
+struct CachedLanguage {
+    configuration: Configuration,
+    name: String,
+    query: CompiledQuery,
+}
 
-    let config = Configuration::parse_default_config();
-    let language = config.get_language("feather").unwrap();
-    let grammars = language.grammars().expect("grammars");
+impl CachedLanguage {
+    fn language(&self) -> &Language {
+        self.configuration
+            .get_language(&self.name)
+            .expect("the language was found when it was cached")
+    }
+}
+
+/// Returns the query file we include for the given language, if any.
+fn builtin_query(name: &str) -> Option<&'static str> {
+    match name {
+        "feather" => Some(include_str!("feather.scm")),
+        _ => None,
+    }
+}
+
+/// Loads and compiles the configuration, grammars and query of the given language.
+/// The result is cached, so this work is only done once per language.
+fn cached_language(name: &str) -> FormatterResult<Arc<CachedLanguage>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Arc<CachedLanguage>>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(Default::default);
 
-    // TODO: Cache `query`.
-    // TODO: Return more useful errors.
-    match formatter(
-        &mut input.as_bytes(),
-        &mut output,
+    if let Some(cached) = cache.lock().unwrap().get(name) {
+        return Ok(Arc::clone(cached));
+    }
+
+    // Errors are not cached, since `FormatterError` is not `Clone`.
+    // We compile outside the lock, so two threads may race to compile the same language;
+    // this is harmless, since they produce the same result.
+    let configuration = Configuration::parse_default_config();
+    let language = configuration.get_language(name)?;
+    let query_content =
+        builtin_query(name).ok_or_else(|| FormatterError::UnsupportedLanguage(name.to_owned()))?;
+    let query = CompiledQuery::new(query_content, &language.grammars()?)?;
+    let cached = Arc::new(CachedLanguage {
+        configuration,
+        name: name.to_owned(),
         query,
-        language,
-        &grammars,
-        Operation::Format {
-            skip_idempotence: true,
-        },
-    ) {
-        Ok(()) => {
-            let formatted = String::from_utf8(output).expect("valid utf-8");
-            Some(formatted)
+    });
+
+    Ok(Arc::clone(
+        cache
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_insert(cached),
+    ))
+}
+
+/// Formats many Feather source files concurrently.
+/// Each input is paired with a key, such as the source it came from, which is ignored.
+/// The query is compiled once and shared between all of the inputs.
+/// The results are returned in the same order as the inputs.
+pub fn format_many<K: Sync>(inputs: &[(K, String)]) -> Vec<FormatterResult<String>> {
+    match cached_language("feather") {
+        Ok(cached) => inputs
+            .par_iter()
+            .map(|(_, input)| {
+                format_content(
+                    input,
+                    &cached.query,
+                    cached.language(),
+                    true,
+                    RenderOptions::default(),
+                )
+            })
+            .collect(),
+        Err(err) => {
+            // The error is not `Clone`, so we report its message for every input.
+            let message = err.to_string();
+            inputs
+                .iter()
+                .map(|_| Err(FormatterError::Query(message.clone(), None)))
+                .collect()
         }
-        Err(_err) => None,
     }
 }
 
+/// Formats a Feather source file, using the cached query for Feather.
+///
+/// # Errors
+///
+/// If the input could not be parsed or formatted, a `FormatterError` will be returned.
+pub fn format_feather(input: &str) -> FormatterResult<String> {
+    let cached = cached_language("feather")?;
+    format_content(
+        input,
+        &cached.query,
+        cached.language(),
+        true,
+        RenderOptions::default(),
+    )
+}
+
+/// Formats atoms that were built directly, rather than by applying a query to source text.
+/// This lets a caller that already has a syntax tree, such as a kernel expression,
+/// lay it out without writing it as source text and parsing it again.
+///
+/// The atoms are post-processed in the same way as the atoms produced by a query,
+/// so for instance consecutive spaces are merged, and whitespace at the start is removed.
+/// Softlines and scoped atoms depend on the layout of an input, so they must not be used;
+/// use [`Atom::Breakpoint`] and [`Atom::Hardline`] instead.
+///
+/// # Errors
+///
+/// If the atoms could not be rendered, for instance because an indentation block is closed without being opened,
+/// a `FormatterError` will be returned.
+pub fn format_expression_atoms(
+    atoms: &[Atom],
+    indent: IndentStyle,
+    max_width: usize,
+) -> FormatterResult<String> {
+    let mut atoms = AtomCollection::from_atoms(atoms.to_vec());
+    atoms.post_process()?;
+    let mut output = Vec::new();
+    pretty::render(&atoms[..], &indent.unit(), max_width, "\n", &mut output)?;
+    Ok(String::from_utf8(output)?)
+}
+
+/// Checks whether the input is already formatted, without producing the formatted output.
+/// The input is formatted with the default indentation and line width, and the line endings of the input,
+/// and the idempotence check is skipped.
+///
+/// # Errors
+///
+/// If the query could not be compiled, or the input could not be parsed or formatted,
+/// a `FormatterError` will be returned.
+pub fn check_formatted(
+    input: &str,
+    query: &str,
+    language: &Language,
+    grammars: &[tree_sitter_facade::Language],
+) -> FormatterResult<bool> {
+    let query = CompiledQuery::new(query, grammars)?;
+    let formatted = format_content(input, &query, language, true, RenderOptions::default())?;
+    Ok(formatted == input)
+}
+
+/// Replaces the top-level nodes of `content` that intersect the byte range `start..end`
+/// with the corresponding nodes of `formatted`, which must be a formatted version of `content`.
+/// Only whole top-level nodes are replaced, so the indentation and whitespace at the boundaries
+/// of the range are unaffected.
+fn splice_range(
+    content: &str,
+    formatted: &str,
+    grammars: &[tree_sitter_facade::Language],
+    start: usize,
+    end: usize,
+) -> FormatterResult<String> {
+    let (content_tree, _) = tree_sitter::parse(content, grammars)?;
+    let (formatted_tree, _) = tree_sitter::parse(formatted, grammars)?;
+    let content_nodes = top_level_ranges(&content_tree);
+    let formatted_nodes = top_level_ranges(&formatted_tree);
+    if content_nodes.len() != formatted_nodes.len() {
+        return Err(FormatterError::Internal(
+            "Formatting changed the number of top-level nodes".into(),
+            None,
+        ));
+    }
+
+    // An empty range selects the node containing it.
+    let selected = content_nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, range)| range.start < end.max(start + 1) && start < range.end)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    match (selected.first(), selected.last()) {
+        (Some(&first), Some(&last)) => Ok(format!(
+            "{}{}{}",
+            &content[..content_nodes[first].start],
+            &formatted[formatted_nodes[first].start..formatted_nodes[last].end],
+            &content[content_nodes[last].end..]
+        )),
+        _ => Ok(content.to_owned()),
+    }
+}
+
+/// The byte ranges of the children of the root node, excluding extras such as comments.
+fn top_level_ranges(tree: &tree_sitter_facade::Tree) -> Vec<std::ops::Range<usize>> {
+    let root = tree.root_node();
+    let mut walker = root.walk();
+    let ranges = root
+        .children(&mut walker)
+        .filter(|node| !node.is_extra())
+        .map(|node| node.start_byte() as usize..node.end_byte() as usize)
+        .collect();
+    ranges
+}
+
 fn read_input(input: &mut dyn io::Read) -> Result<String, io::Error> {
     let mut content = String::new();
     input.read_to_string(&mut content)?;
     Ok(content)
 }
 
-fn trim_whitespace(s: &str) -> String {
-    // Trim whitespace from the end of each line,
-    // then trim any leading/trailing new lines,
-    // finally reinstate the new line at EOF.
-    format!(
-        "{}\n",
-        s.lines()
-            .map(str::trim_end)
-            .collect::<Vec<_>>()
-            .join("\n")
-            .trim()
+/// The options of [`Operation::Format`] that affect the formatted output.
+#[derive(Clone, Copy, Debug)]
+struct RenderOptions {
+    indent: Option<IndentStyle>,
+    detect_indent: bool,
+    max_width: usize,
+    line_ending: LineEnding,
+    reflow: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            indent: None,
+            detect_indent: false,
+            max_width: DEFAULT_MAX_WIDTH,
+            line_ending: LineEnding::Detect,
+            reflow: false,
+        }
+    }
+}
+
+/// Formats the given content with a compiled query, returning the formatted output.
+fn format_content(
+    content: &str,
+    query: &CompiledQuery,
+    language: &Language,
+    skip_idempotence: bool,
+    options: RenderOptions,
+) -> FormatterResult<String> {
+    let mut formatted = Vec::new();
+    write_formatted(content, query, language, options, &mut formatted)?;
+    let formatted = String::from_utf8(formatted)?;
+
+    if !skip_idempotence {
+        idempotence_check(&formatted, query, language, options)?;
+    }
+
+    Ok(formatted)
+}
+
+/// Formats the given content with a compiled query, writing each line of the formatted output
+/// to `output` as soon as it is rendered.
+fn write_formatted(
+    content: &str,
+    query: &CompiledQuery,
+    language: &Language,
+    options: RenderOptions,
+    output: &mut dyn io::Write,
+) -> FormatterResult<()> {
+    let RenderOptions {
+        indent,
+        detect_indent,
+        max_width,
+        line_ending,
+        reflow,
+    } = options;
+
+    // All the work related to tree-sitter and the query is done here
+    tracing::info!("Apply Tree-sitter query");
+    let mut atoms = query.apply(content, false, reflow)?;
+
+    // Various post-processing of whitespace
+    atoms.post_process()?;
+
+    // Pretty-print atoms
+    tracing::info!("Pretty-print output");
+    let indent = match indent {
+        Some(indent) => Some(indent),
+        None if detect_indent => crate::detect_indent(content),
+        None => None,
+    };
+    let indent_unit = match indent {
+        Some(indent) => indent.unit(),
+        // Default to "  " if the language has no indentation specified
+        None => language.indent.clone().unwrap_or_else(|| "  ".to_owned()),
+    };
+    pretty::render(
+        &atoms[..],
+        &indent_unit,
+        max_width,
+        line_ending.resolve(content),
+        output,
     )
 }
 
 fn idempotence_check(
     content: &str,
-    query: &str,
+    query: &CompiledQuery,
     language: &Language,
-    grammars: &[tree_sitter_facade::Language],
+    options: RenderOptions,
 ) -> FormatterResult<()> {
     tracing::info!("Checking for idempotence ...");
 
-    let mut input = content.as_bytes();
-    let mut output = io::BufWriter::new(Vec::new());
-
-    formatter(
-        &mut input,
-        &mut output,
-        query,
-        language,
-        grammars,
-        Operation::Format {
-            skip_idempotence: true,
-        },
-    )?;
-    let reformatted = String::from_utf8(output.into_inner()?)?;
-    let res = if content == reformatted {
-        Ok(())
-    } else {
-        tracing::error!("Failed idempotence check");
-        tracing::error!("{}\n!=\n{}", content, reformatted);
-        Err(FormatterError::Idempotence)
-    };
+    let res = format_content(content, query, language, true, options).and_then(|reformatted| {
+        if content == reformatted {
+            Ok(())
+        } else {
+            tracing::error!("Failed idempotence check");
+            tracing::error!("{}\n!=\n{}", content, reformatted);
+            let (line, diff) = first_difference(content, &reformatted);
+            Err(FormatterError::Idempotence { line, diff })
+        }
+    });
 
     if let Err(err) = res {
         match err {
             // If topiary ran smoothly on its own output,
             // but produced a different output, it is a Idempotence error.
-            FormatterError::Idempotence => Err(FormatterError::Idempotence),
+            FormatterError::Idempotence { .. } => Err(err),
             // On the other hand, if it failed to run on its output,
             // it means that when formatting the code, topiary somehow broke it.
             // Hence it is a formatting error.
@@ -260,3 +654,414 @@ fn idempotence_check(
         res
     }
 }
+
+/// The number of removed or added lines shown on each side of an idempotence diff.
+const MAX_DIFF_LINES: usize = 5;
+
+/// Finds the first line (counting from 1) at which `before` and `after` differ,
+/// and writes a unified diff of the region between their common prefix and common suffix,
+/// with one line of context before it.
+fn first_difference(before: &str, after: &str) -> (usize, String) {
+    let before = before.lines().collect::<Vec<_>>();
+    let after = after.lines().collect::<Vec<_>>();
+    let prefix = before
+        .iter()
+        .zip(&after)
+        .take_while(|(before, after)| before == after)
+        .count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(before, after)| before == after)
+        .count();
+    let removed = &before[prefix..before.len() - suffix];
+    let added = &after[prefix..after.len() - suffix];
+
+    let context = prefix.saturating_sub(1);
+    let mut diff = format!(
+        "@@ -{},{} +{},{} @@",
+        context + 1,
+        prefix - context + removed.len(),
+        context + 1,
+        prefix - context + added.len()
+    );
+    for line in &before[context..prefix] {
+        diff.push_str(&format!("\n {line}"));
+    }
+    for (sign, lines) in [('-', removed), ('+', added)] {
+        for line in lines.iter().take(MAX_DIFF_LINES) {
+            diff.push_str(&format!("\n{sign}{line}"));
+        }
+        if lines.len() > MAX_DIFF_LINES {
+            diff.push_str(&format!(
+                "\n{sign}... ({} more lines)",
+                lines.len() - MAX_DIFF_LINES
+            ));
+        }
+    }
+    (prefix + 1, diff)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        detect_indent, first_difference, formatter, Configuration, FormatterError, IndentStyle,
+        LineEnding, Operation, DEFAULT_MAX_WIDTH,
+    };
+
+    fn format(input: &str, indent: IndentStyle, max_width: usize) -> String {
+        format_with_line_ending(input, indent, max_width, LineEnding::Detect)
+    }
+
+    fn format_with_line_ending(
+        input: &str,
+        indent: IndentStyle,
+        max_width: usize,
+        line_ending: LineEnding,
+    ) -> String {
+        let config = Configuration::parse_default_config();
+        let language = config.get_language("feather").unwrap();
+        let grammars = language.grammars().unwrap();
+        let mut output = Vec::new();
+        formatter(
+            &mut input.as_bytes(),
+            &mut output,
+            include_str!("feather.scm"),
+            language,
+            &grammars,
+            Operation::Format {
+                skip_idempotence: false,
+                indent: Some(indent),
+                detect_indent: false,
+                max_width,
+                line_ending,
+                reflow: false,
+            },
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn indent_styles() {
+        let input = "module test\n\ndef foo: inst Bool =\n  inst True\n";
+        let tabs = format(input, IndentStyle::Tabs, DEFAULT_MAX_WIDTH);
+        let spaces = format(input, IndentStyle::Spaces(4), DEFAULT_MAX_WIDTH);
+        assert!(tabs.contains("\n\tinst True"));
+        assert!(spaces.contains("\n    inst True"));
+        assert_eq!(tabs.replace('\t', "    "), spaces);
+    }
+
+    #[test]
+    fn indent_detection() {
+        let tabs = "module test\n\ndef foo: inst Bool =\n\tinst True\n";
+        let spaces = "module test\n\ndef foo: inst Bool =\n    inst True\n\n    \n";
+        assert_eq!(detect_indent(tabs), Some(IndentStyle::Tabs));
+        assert_eq!(detect_indent(spaces), Some(IndentStyle::Spaces(4)));
+        assert_eq!(
+            detect_indent("a\n    b\n        c\n"),
+            Some(IndentStyle::Spaces(4))
+        );
+        assert_eq!(detect_indent("a\n\tb\n    c\n"), None);
+        assert_eq!(detect_indent("a\n    b\n      c\n"), None);
+        assert_eq!(detect_indent("a\nb\n"), None);
+
+        let format = |input: &str| {
+            let config = Configuration::parse_default_config();
+            let language = config.get_language("feather").unwrap();
+            let grammars = language.grammars().unwrap();
+            let mut output = Vec::new();
+            formatter(
+                &mut input.as_bytes(),
+                &mut output,
+                include_str!("feather.scm"),
+                language,
+                &grammars,
+                Operation::Format {
+                    skip_idempotence: false,
+                    indent: None,
+                    detect_indent: true,
+                    max_width: DEFAULT_MAX_WIDTH,
+                    line_ending: LineEnding::Detect,
+                    reflow: false,
+                },
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(format(tabs), tabs);
+        assert_eq!(
+            format(spaces),
+            "module test\n\ndef foo: inst Bool =\n    inst True\n"
+        );
+        // Mixed indentation is ambiguous, so the language's indentation is used.
+        assert_eq!(
+            format("module test\n\ndef foo: inst Bool =\n\tinst True\n\ndef bar: inst Bool =\n  inst True\n"),
+            "module test\n\ndef foo: inst Bool =\n    inst True\n\ndef bar: inst Bool =\n    inst True\n"
+        );
+    }
+
+    #[test]
+    fn max_width() {
+        let input = "module test\n\n\
+            def id: for (x: inst Bool) -> for (y: inst Bool) -> inst Bool =\n    \
+            fun (x: inst Bool) -> fun (y: inst Bool) -> x\n";
+
+        let wide = format(input, IndentStyle::Spaces(4), 80);
+        assert!(wide.contains("def id: for (x: inst Bool) -> for (y: inst Bool) -> inst Bool =\n"));
+        assert!(wide.contains("    fun (x: inst Bool) -> fun (y: inst Bool) -> x\n"));
+
+        let narrow = format(input, IndentStyle::Spaces(4), 40);
+        assert!(narrow.lines().all(|line| line.chars().count() <= 40));
+        assert!(narrow.lines().count() > wide.lines().count());
+    }
+
+    #[test]
+    fn check_formatted() {
+        let config = Configuration::parse_default_config();
+        let language = config.get_language("feather").unwrap();
+        let grammars = language.grammars().unwrap();
+        let check = |input: &str| {
+            crate::check_formatted(input, include_str!("feather.scm"), language, &grammars).unwrap()
+        };
+        assert!(check("module test\n\ndef a: inst Bool =\n    inst True\n"));
+        assert!(!check(
+            "module test\n\ndef a: inst   Bool =\n    inst True\n"
+        ));
+    }
+
+    #[test]
+    fn idempotence_diff() {
+        let (line, diff) = first_difference("a\nb\nc\nd\n", "a\nb\nx\ny\nd\n");
+        assert_eq!(line, 3);
+        assert_eq!(diff, "@@ -2,2 +2,3 @@\n b\n-c\n+x\n+y");
+    }
+
+    #[test]
+    fn reflow() {
+        let config = Configuration::parse_default_config();
+        let language = config.get_language("feather").unwrap();
+        let grammars = language.grammars().unwrap();
+        let format = |input: &str, max_width: usize, reflow: bool| {
+            let mut output = Vec::new();
+            formatter(
+                &mut input.as_bytes(),
+                &mut output,
+                include_str!("feather.scm"),
+                language,
+                &grammars,
+                Operation::Format {
+                    skip_idempotence: false,
+                    indent: None,
+                    detect_indent: false,
+                    max_width,
+                    line_ending: LineEnding::Detect,
+                    reflow,
+                },
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let input = "module test\n\ndef a: inst Bool =\n    let x = inst True;\n    x\n";
+        assert_eq!(format(input, DEFAULT_MAX_WIDTH, false), input);
+        assert_eq!(
+            format(input, DEFAULT_MAX_WIDTH, true),
+            "module test\n\ndef a: inst Bool =\n    let x = inst True; x\n"
+        );
+        // The `let` expression does not fit on one line, so it is not joined.
+        assert_eq!(format(input, 20, true), input);
+    }
+
+    #[test]
+    fn format_range() {
+        let config = Configuration::parse_default_config();
+        let language = config.get_language("feather").unwrap();
+        let grammars = language.grammars().unwrap();
+        let input = "module test\n\n\
+            def a: inst   Bool =\n    inst   True\n\n\
+            def b: inst   Bool =\n    inst   True\n";
+        let start = input.find("def b").unwrap();
+        let mut output = Vec::new();
+        formatter(
+            &mut input.as_bytes(),
+            &mut output,
+            include_str!("feather.scm"),
+            language,
+            &grammars,
+            Operation::FormatRange {
+                start,
+                end: start + 1,
+                skip_idempotence: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "module test\n\n\
+            def a: inst   Bool =\n    inst   True\n\n\
+            def b: inst Bool =\n    inst True\n"
+        );
+    }
+
+    #[test]
+    fn comments_are_preserved() {
+        let input = "module test\n\n\
+            // The constant function.\n\
+            def a: inst Bool = // Always true.\n    inst True\n";
+        assert_eq!(
+            format(input, IndentStyle::Spaces(4), DEFAULT_MAX_WIDTH),
+            input
+        );
+    }
+
+    #[test]
+    fn blank_line_count() {
+        let config = Configuration::parse_default_config();
+        let language = config.get_language("feather").unwrap();
+        let grammars = language.grammars().unwrap();
+        let query = format!(
+            "{}\n((definition) @prepend_blankline (#blank_lines! \"2\"))\n",
+            include_str!("feather.scm")
+        );
+        let mut output = Vec::new();
+        formatter(
+            &mut "module test\ndef a: inst Bool =\n    inst True\n\ndef b: inst Bool =\n    inst True\n"
+                .as_bytes(),
+            &mut output,
+            &query,
+            language,
+            &grammars,
+            Operation::Format {
+                skip_idempotence: false,
+                indent: None,
+                detect_indent: false,
+                max_width: DEFAULT_MAX_WIDTH,
+                line_ending: LineEnding::Detect,
+                reflow: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "module test\n\n\n\
+            def a: inst Bool =\n    inst True\n\n\n\
+            def b: inst Bool =\n    inst True\n"
+        );
+    }
+
+    #[test]
+    fn unknown_capture_name() {
+        let config = Configuration::parse_default_config();
+        let language = config.get_language("feather").unwrap();
+        let grammars = language.grammars().unwrap();
+        let result = formatter(
+            &mut "module test\n".as_bytes(),
+            &mut Vec::new(),
+            "(identifier) @leaf\n(definition) @append_hardlin\n",
+            language,
+            &grammars,
+            Operation::Format {
+                skip_idempotence: true,
+                indent: None,
+                detect_indent: false,
+                max_width: DEFAULT_MAX_WIDTH,
+                line_ending: LineEnding::Detect,
+                reflow: false,
+            },
+        );
+        match result {
+            Err(FormatterError::Query(message, _)) => {
+                assert!(
+                    message.starts_with("@append_hardlin at line 2, column 14 of the query file")
+                );
+                assert!(message.contains("@append_hardline"));
+            }
+            _ => panic!("expected an error for the unknown capture name"),
+        }
+    }
+
+    #[test]
+    fn unbalanced_scope() {
+        let config = Configuration::parse_default_config();
+        let language = config.get_language("feather").unwrap();
+        let grammars = language.grammars().unwrap();
+        let result = formatter(
+            &mut "module test\n\ndef a: inst Bool =\n    inst True\n".as_bytes(),
+            &mut Vec::new(),
+            "(identifier) @leaf\n((definition) @begin_scope (#scope_id! \"definition\"))\n",
+            language,
+            &grammars,
+            Operation::Format {
+                skip_idempotence: true,
+                indent: None,
+                detect_indent: false,
+                max_width: DEFAULT_MAX_WIDTH,
+                line_ending: LineEnding::Detect,
+                reflow: false,
+            },
+        );
+        match result {
+            Err(FormatterError::Query(message, _)) => {
+                assert!(message.contains("\"definition\""));
+                assert!(message.contains("without a matching @end_scope"));
+            }
+            _ => panic!("expected an error for the unbalanced scope"),
+        }
+    }
+
+    #[test]
+    fn format_many_preserves_order() {
+        let inputs = (0..20)
+            .map(|i| {
+                (
+                    i,
+                    format!("module test\n\ndef a{i}: inst   Bool =\n    inst True\n"),
+                )
+            })
+            .collect::<Vec<_>>();
+        let outputs = crate::format_many(&inputs);
+        assert_eq!(outputs.len(), inputs.len());
+        for (i, output) in outputs.into_iter().enumerate() {
+            assert_eq!(
+                output.unwrap(),
+                format!("module test\n\ndef a{i}: inst Bool =\n    inst True\n")
+            );
+        }
+    }
+
+    #[test]
+    fn query_is_compiled_once() {
+        let first = crate::cached_language("feather").unwrap();
+        let second = crate::cached_language("feather").unwrap();
+        assert!(std::sync::Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn format_feather_reports_errors() {
+        assert!(matches!(
+            crate::format_feather("module test\n\ndef a: = =\n"),
+            Err(FormatterError::Parsing { .. })
+        ));
+    }
+
+    #[test]
+    fn line_endings() {
+        let lf = "module test\n\ndef foo: inst Bool =\n    inst True\n";
+        let crlf = lf.replace('\n', "\r\n");
+        let format = |input: &str, line_ending| {
+            format_with_line_ending(
+                input,
+                IndentStyle::Spaces(4),
+                DEFAULT_MAX_WIDTH,
+                line_ending,
+            )
+        };
+
+        assert_eq!(format(&crlf, LineEnding::Detect), crlf);
+        assert_eq!(format(lf, LineEnding::Detect), lf);
+        assert_eq!(format(&crlf, LineEnding::Lf), lf);
+        assert_eq!(format(lf, LineEnding::CrLf), crlf);
+    }
+}
@@ -1,13 +1,29 @@
-use formatter::{formatter, Configuration, FormatterError, Operation};
+use std::path::Path;
+
+use formatter::{
+    formatter, Configuration, FormatterError, LineEnding, Operation, DEFAULT_MAX_WIDTH,
+};
 
 fn main() {
-    let input = std::fs::read_to_string("test/test.ftr").unwrap();
+    let path = Path::new("test/test.ftr");
+    let input = std::fs::read_to_string(path).unwrap();
     let mut input = input.as_bytes();
     let mut output = Vec::new();
     let query = include_str!("feather.scm");
 
     let config = Configuration::parse_default_config();
-    let language = config.get_language("feather").unwrap();
+    let language = match config.language_for_path(path) {
+        Some(language) => language,
+        None => {
+            let mut extensions = config.known_extensions().into_iter().collect::<Vec<_>>();
+            extensions.sort_unstable();
+            panic!(
+                "Could not detect the language of '{}': supported extensions are {}",
+                path.display(),
+                extensions.join(", ")
+            );
+        }
+    };
     let grammars = language.grammars().expect("grammars");
 
     match formatter(
@@ -18,6 +34,11 @@ fn main() {
         &grammars,
         Operation::Format {
             skip_idempotence: true,
+            indent: None,
+            detect_indent: false,
+            max_width: DEFAULT_MAX_WIDTH,
+            line_ending: LineEnding::Detect,
+            reflow: false,
         },
     ) {
         Ok(()) => {
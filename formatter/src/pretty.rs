@@ -1,18 +1,46 @@
-use std::fmt::Write;
+use std::{collections::HashMap, io};
 
 use crate::{Atom, FormatterError, FormatterResult};
 
-pub fn render(atoms: &[Atom], indent: &str) -> FormatterResult<String> {
-    let mut buffer = String::new();
+/// Renders the atoms to `output`, writing each line as soon as it is complete,
+/// so the rendered text is never held in memory all at once.
+///
+/// Trailing whitespace is removed from each line, blank lines at the start and end of the output are removed,
+/// and every line, including the last, is ended with `line_ending`.
+pub fn render(
+    atoms: &[Atom],
+    indent: &str,
+    max_width: usize,
+    line_ending: &str,
+    output: &mut dyn io::Write,
+) -> FormatterResult<()> {
+    let mut lines = LineWriter::new(output, line_ending);
     let mut indent_level: usize = 0;
+    // Whether each group of breakpoints has been broken.
+    // This is decided when the first breakpoint in the group is reached.
+    let mut broken_groups: HashMap<usize, bool> = HashMap::new();
+    // The index of the last breakpoint in each group, found in a single pass
+    // so that deciding whether to break a group does not search the rest of the atoms.
+    let mut last_breakpoints: HashMap<usize, usize> = HashMap::new();
+    for (i, atom) in atoms.iter().enumerate() {
+        if let Atom::Breakpoint { group, .. } = atom {
+            last_breakpoints.insert(*group, i);
+        }
+    }
 
-    for atom in atoms {
+    for (i, atom) in atoms.iter().enumerate() {
         match atom {
-            Atom::Blankline => write!(buffer, "\n\n{}", indent.repeat(indent_level))?,
+            Atom::Blankline(count) => {
+                lines.push(&"\n".repeat(count + 1))?;
+                lines.push(&indent.repeat(indent_level))?;
+            }
 
             Atom::Empty => (),
 
-            Atom::Hardline => write!(buffer, "\n{}", indent.repeat(indent_level))?,
+            Atom::Hardline => {
+                lines.push("\n")?;
+                lines.push(&indent.repeat(indent_level))?;
+            }
 
             Atom::IndentEnd => {
                 if indent_level == 0 {
@@ -35,14 +63,30 @@ pub fn render(atoms: &[Atom], indent: &str) -> FormatterResult<String> {
                 if *single_line_no_indent {
                     // The line break after the content has been previously added
                     // as a `Hardline` in the atom stream.
-                    writeln!(buffer)?;
+                    lines.push("\n")?;
                 }
-                write!(buffer, "{}", content.trim_end_matches('\n'))?;
+                lines.push(content.trim_end_matches('\n'))?;
             }
 
-            Atom::Literal(s) => write!(buffer, "{s}")?,
+            Atom::Literal(s) => lines.push(s)?,
 
-            Atom::Space => write!(buffer, " ")?,
+            Atom::Space => lines.push(" ")?,
+
+            Atom::Breakpoint { spaced, group } => {
+                let broken = *broken_groups.entry(*group).or_insert_with(|| {
+                    let column = lines.column();
+                    // Breaking the line at its indentation would not make it any shorter.
+                    column > indent.len() * indent_level
+                        && group_width(&atoms[i..], last_breakpoints[group] - i)
+                            .map_or(true, |width| column + width > max_width)
+                });
+                if broken {
+                    lines.push("\n")?;
+                    lines.push(&indent.repeat(indent_level))?;
+                } else if *spaced {
+                    lines.push(" ")?;
+                }
+            }
 
             // All other atom kinds should have been post-processed at that point
             other => {
@@ -54,5 +98,130 @@ pub fn render(atoms: &[Atom], indent: &str) -> FormatterResult<String> {
         };
     }
 
-    Ok(buffer)
+    lines.finish()?;
+    Ok(())
+}
+
+/// Whitespace that is trimmed from the ends of lines.
+/// Other whitespace, such as non-breaking spaces, may be significant and is kept.
+fn is_trimmed(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+/// Writes rendered text to an output one line at a time, trimming whitespace as it goes.
+struct LineWriter<'a> {
+    output: &'a mut dyn io::Write,
+    line_ending: &'a str,
+    /// The text of the current line, which has not been written yet.
+    line: String,
+    /// Whether a non-blank line has been written. Blank lines before it are dropped.
+    started: bool,
+    /// The number of blank lines since the last non-blank line.
+    /// They are only written once another non-blank line is written, so blank lines at the end are dropped.
+    blank_lines: usize,
+}
+
+impl<'a> LineWriter<'a> {
+    fn new(output: &'a mut dyn io::Write, line_ending: &'a str) -> Self {
+        Self {
+            output,
+            line_ending,
+            line: String::new(),
+            started: false,
+            blank_lines: 0,
+        }
+    }
+
+    /// The number of characters on the current line.
+    fn column(&self) -> usize {
+        self.line.chars().count()
+    }
+
+    /// Appends text, which may contain line breaks, to the output.
+    fn push(&mut self, text: &str) -> io::Result<()> {
+        let mut parts = text.split('\n');
+        if let Some(first) = parts.next() {
+            self.line.push_str(first);
+        }
+        for part in parts {
+            self.end_line()?;
+            self.line.push_str(part);
+        }
+        Ok(())
+    }
+
+    /// Writes the current line, without its trailing whitespace.
+    fn end_line(&mut self) -> io::Result<()> {
+        let mut line = self.line.trim_end_matches(is_trimmed);
+        if !self.started {
+            line = line.trim_start_matches(is_trimmed);
+        }
+        if line.is_empty() {
+            if self.started {
+                self.blank_lines += 1;
+            }
+        } else {
+            for _ in 0..self.blank_lines {
+                self.output.write_all(self.line_ending.as_bytes())?;
+            }
+            self.output.write_all(line.as_bytes())?;
+            self.output.write_all(self.line_ending.as_bytes())?;
+            self.started = true;
+            self.blank_lines = 0;
+        }
+        self.line.clear();
+        Ok(())
+    }
+
+    /// Writes the last line. An empty output is written as a single line ending.
+    fn finish(mut self) -> io::Result<()> {
+        self.end_line()?;
+        if !self.started {
+            self.output.write_all(self.line_ending.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the width of the given atoms if the group of breakpoints at the start is not broken,
+/// up to the first line break after the last breakpoint in the group, which is at index `last`.
+/// Other groups are assumed not to be broken either.
+/// Returns [`None`] if there is a line break before the last breakpoint in the group,
+/// since the group cannot then fit on one line.
+fn group_width(atoms: &[Atom], last: usize) -> Option<usize> {
+    // Ends the measurement at a line break at index `i`.
+    let line_break = |i: usize, width: usize| if i < last { None } else { Some(width) };
+
+    let mut width = 0;
+    for (i, atom) in atoms.iter().enumerate() {
+        match atom {
+            Atom::Breakpoint { spaced, .. } => {
+                if i > last {
+                    break;
+                }
+                width += usize::from(*spaced);
+            }
+            Atom::Leaf {
+                content,
+                single_line_no_indent,
+                ..
+            } => {
+                if *single_line_no_indent {
+                    return line_break(i, width);
+                }
+                let content = content.trim_end_matches('\n');
+                match content.split_once('\n') {
+                    Some((first_line, _)) => {
+                        return line_break(i, width + first_line.chars().count());
+                    }
+                    None => width += content.chars().count(),
+                }
+            }
+            Atom::Literal(s) => width += s.chars().count(),
+            Atom::Space => width += 1,
+            Atom::Hardline | Atom::Blankline(_) => return line_break(i, width),
+            _ => {}
+        }
+    }
+    Some(width)
 }
@@ -0,0 +1,70 @@
+/// S-expression visualisation for our SyntaxTree representation
+/// This matches the output of Tree-sitter's `Node::to_sexp`: anonymous nodes are omitted,
+/// and children are prefixed with their field names
+use std::io;
+
+use crate::{tree_sitter::SyntaxNode, FormatterResult};
+
+fn write_node(output: &mut dyn io::Write, node: &SyntaxNode, byte_ranges: bool) -> io::Result<()> {
+    write!(output, "({}", node.kind)?;
+    if byte_ranges {
+        write!(output, " {}..{}", node.start_byte, node.end_byte)?;
+    }
+
+    for child in node.children.iter().filter(|child| child.is_named) {
+        write!(output, " ")?;
+        if let Some(field_name) = &child.field_name {
+            write!(output, "{field_name}: ")?;
+        }
+        write_node(output, child, byte_ranges)?;
+    }
+
+    write!(output, ")")
+}
+
+pub fn write(
+    output: &mut dyn io::Write,
+    root: &SyntaxNode,
+    byte_ranges: bool,
+) -> FormatterResult<()> {
+    write_node(output, root, byte_ranges)?;
+    writeln!(output)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::write;
+    use crate::{tree_sitter, Configuration, SyntaxNode};
+
+    fn visualise(input: &str, byte_ranges: bool) -> String {
+        let config = Configuration::parse_default_config();
+        let grammars = config.get_language("feather").unwrap().grammars().unwrap();
+        let (tree, _) = tree_sitter::parse(input, &grammars).unwrap();
+        let root: SyntaxNode = tree.root_node().into();
+
+        let mut output = Vec::new();
+        write(&mut output, &root, byte_ranges).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn feather_snapshot() {
+        assert_eq!(
+            visualise("module test\ndef x: Sort 0 = inst y\n", false),
+            "(source_file module: (module path: (path last: (identifier))) \
+            definition: (definition name: (identifier) \
+            ty: (sort universe: (universe level: (identifier))) \
+            body: (inst path: (path last: (identifier)))))\n"
+        );
+    }
+
+    #[test]
+    fn feather_snapshot_with_byte_ranges() {
+        assert_eq!(
+            visualise("module test\n", true),
+            "(source_file 0..12 module: (module 0..11 path: (path 7..11 last: (identifier 7..11))))\n"
+        );
+    }
+}
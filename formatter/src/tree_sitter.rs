@@ -3,7 +3,7 @@ use std::collections::HashSet;
 use tree_sitter_facade::{Node, Parser, Query, QueryCapture, QueryCursor, QueryPredicate, Tree};
 
 use crate::{
-    atom_collection::{AtomCollection, QueryPredicates},
+    atom_collection::{AtomCollection, QueryPredicates, CAPTURE_NAMES},
     error::FormatterError,
     FormatterResult,
 };
@@ -11,7 +11,18 @@ use crate::{
 /// Supported visualisation formats
 #[derive(Clone, Copy, Debug)]
 pub enum Visualisation {
-    GraphViz,
+    /// A GraphViz graph, optionally labelling each node with its byte range
+    /// and the name of the field of its parent that contains it.
+    GraphViz {
+        byte_ranges: bool,
+        field_names: bool,
+    },
+    Json,
+    /// The parenthesised form produced by Tree-sitter's `Node::to_sexp`,
+    /// optionally annotated with the byte range of each node.
+    SExpression {
+        byte_ranges: bool,
+    },
 }
 
 // Simplified syntactic node struct, for the sake of serialisation.
@@ -20,26 +31,50 @@ pub struct SyntaxNode {
 
     pub kind: String,
     pub is_named: bool,
+    // The name of the field of the parent node that contains this node, if any.
+    pub field_name: Option<String>,
+
+    pub start_byte: usize,
+    pub end_byte: usize,
 
     pub children: Vec<SyntaxNode>,
 }
 
-impl From<Node<'_>> for SyntaxNode {
-    fn from(node: Node) -> Self {
+impl SyntaxNode {
+    fn new(node: Node, field_name: Option<String>) -> Self {
+        let mut children = Vec::new();
         let mut walker = node.walk();
-        let children = node.children(&mut walker).map(Self::from).collect();
+        if walker.goto_first_child() {
+            loop {
+                let field_name = walker.field_name().map(|name| name.to_string());
+                children.push(Self::new(walker.node(), field_name));
+                if !walker.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
 
         Self {
             id: node.id(),
 
             kind: node.kind().into(),
             is_named: node.is_named(),
+            field_name,
+
+            start_byte: node.start_byte() as usize,
+            end_byte: node.end_byte() as usize,
 
             children,
         }
     }
 }
 
+impl From<Node<'_>> for SyntaxNode {
+    fn from(node: Node) -> Self {
+        Self::new(node, None)
+    }
+}
+
 #[derive(Debug)]
 // A struct to statically store the public fields of query match results,
 // to avoid running queries twice.
@@ -48,17 +83,101 @@ struct LocalQueryMatch<'a> {
     captures: Vec<QueryCapture<'a>>,
 }
 
+/// A query file compiled for each of the grammars of a language,
+/// so that it can be applied to many inputs without being parsed again.
+///
+/// Tree-sitter queries and languages are immutable once created, and may be shared between threads.
+/// Parsers are not shared: a new one is created each time the query is applied.
+pub struct CompiledQuery {
+    content: String,
+    grammars: Vec<tree_sitter_facade::Language>,
+    queries: Vec<Query>,
+}
+
+impl CompiledQuery {
+    /// Compiles the given query for each of the given grammars.
+    ///
+    /// # Errors
+    ///
+    /// If the query is invalid, or uses an unknown capture name, a `FormatterError` will be returned.
+    pub fn new(
+        query_content: &str,
+        grammars: &[tree_sitter_facade::Language],
+    ) -> FormatterResult<Self> {
+        let queries = grammars
+            .iter()
+            .map(|grammar| {
+                let query = Query::new(grammar, query_content).map_err(|e| {
+                    FormatterError::Query("Error parsing query file".into(), Some(e))
+                })?;
+                check_capture_names(query_content, &query.capture_names())?;
+                Ok(query)
+            })
+            .collect::<FormatterResult<Vec<_>>>()?;
+
+        Ok(Self {
+            content: query_content.to_owned(),
+            grammars: grammars.to_vec(),
+            queries,
+        })
+    }
+
+    pub fn grammars(&self) -> &[tree_sitter_facade::Language] {
+        &self.grammars
+    }
+
+    /// Parses the input with the first grammar that accepts it,
+    /// and applies the query compiled for that grammar.
+    /// If `reflow` is true, softlines in multi-line nodes may be joined if they fit on a line,
+    /// rather than always being broken.
+    pub fn apply(
+        &self,
+        input_content: &str,
+        should_check_input_exhaustivity: bool,
+        reflow: bool,
+    ) -> FormatterResult<AtomCollection> {
+        let (tree, grammar) = parse(input_content, &self.grammars)?;
+        let index = self
+            .grammars
+            .iter()
+            .position(|other| std::ptr::eq(other, grammar))
+            .expect("the grammar was chosen from this list");
+        apply_compiled_query(
+            input_content,
+            &tree,
+            grammar,
+            &self.queries[index],
+            &self.content,
+            should_check_input_exhaustivity,
+            reflow,
+        )
+    }
+}
+
 pub fn apply_query(
     input_content: &str,
     query_content: &str,
     grammars: &[tree_sitter_facade::Language],
     should_check_input_exhaustivity: bool,
 ) -> FormatterResult<AtomCollection> {
-    let (tree, grammar) = parse(input_content, grammars)?;
+    CompiledQuery::new(query_content, grammars)?.apply(
+        input_content,
+        should_check_input_exhaustivity,
+        false,
+    )
+}
+
+fn apply_compiled_query(
+    input_content: &str,
+    tree: &Tree,
+    grammar: &tree_sitter_facade::Language,
+    query: &Query,
+    query_content: &str,
+    should_check_input_exhaustivity: bool,
+    reflow: bool,
+) -> FormatterResult<AtomCollection> {
     let root = tree.root_node();
     let source = input_content.as_bytes();
-    let query = Query::new(grammar, query_content)
-        .map_err(|e| FormatterError::Query("Error parsing query file".into(), Some(e)))?;
 
     // Match queries
     let mut cursor = QueryCursor::new();
@@ -78,7 +197,7 @@ pub fn apply_query(
         let ref_match_count = matches.len();
         check_input_exhaustivity(
             ref_match_count,
-            &query,
+            query,
             query_content,
             grammar,
             &root,
@@ -91,7 +210,7 @@ pub fn apply_query(
     let specified_leaf_nodes: HashSet<usize> = collect_leaf_ids(&matches, &capture_names);
 
     // The Flattening: collects all terminal nodes of the tree-sitter tree in a Vec
-    let mut atoms = AtomCollection::collect_leafs(&root, source, specified_leaf_nodes)?;
+    let mut atoms = AtomCollection::collect_leafs(&root, source, specified_leaf_nodes, reflow)?;
 
     tracing::debug!("List of atoms before formatting: {atoms:?}");
 
@@ -192,6 +311,43 @@ fn check_for_error_nodes(node: &Node) -> FormatterResult<()> {
     Ok(())
 }
 
+// Checks that every capture name in the query is one that we know how to handle,
+// so that a typo in the query file is reported even if the capture never matches.
+fn check_capture_names(query_content: &str, capture_names: &[String]) -> FormatterResult<()> {
+    for name in capture_names {
+        if CAPTURE_NAMES.contains(&name.as_str()) {
+            continue;
+        }
+
+        let location = find_capture(query_content, name)
+            .map(|(line, column)| format!(" at line {line}, column {column} of the query file"))
+            .unwrap_or_default();
+        return Err(FormatterError::Query(
+            format!(
+                "@{name}{location} is not a valid capture name. Valid capture names are: @{}",
+                CAPTURE_NAMES.join(", @")
+            ),
+            None,
+        ));
+    }
+
+    Ok(())
+}
+
+// Returns the 1-based line and column of the first use of the given capture in the query.
+fn find_capture(query_content: &str, name: &str) -> Option<(usize, usize)> {
+    let capture = format!("@{name}");
+    query_content.lines().enumerate().find_map(|(line, text)| {
+        text.match_indices(&capture)
+            .find(|(start, _)| {
+                // Make sure that we have not matched a prefix of a longer capture name.
+                !text[start + capture.len()..]
+                    .starts_with(|c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+            })
+            .map(|(start, _)| (line + 1, text[..start].chars().count() + 1))
+    })
+}
+
 fn collect_leaf_ids(matches: &[LocalQueryMatch], capture_names: &[String]) -> HashSet<usize> {
     let mut ids = HashSet::new();
 
@@ -228,6 +384,21 @@ fn handle_predicate(
             scope_id: Some(arg),
             ..predicates.clone()
         })
+    } else if "blank_lines!" == operator {
+        let arg =
+            predicate.args().into_iter().next().ok_or_else(|| {
+                FormatterError::Query(format!("{operator} needs an argument"), None)
+            })?;
+        let count = arg.parse().map_err(|_| {
+            FormatterError::Query(
+                format!("{operator} needs a number of blank lines, but got {arg:?}"),
+                None,
+            )
+        })?;
+        Ok(QueryPredicates {
+            blank_lines: Some(count),
+            ..predicates.clone()
+        })
     } else if "single_line_only!" == operator {
         Ok(QueryPredicates {
             single_line_only: true,
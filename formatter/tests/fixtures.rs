@@ -0,0 +1,76 @@
+//! Formats each `.ftr` file in `tests/fixtures`, and compares the result with the `.expected` file beside it.
+//! Each fixture must also be formatted idempotently.
+//!
+//! To accept the current output of the formatter, run the tests with the `UPDATE_EXPECTED` environment variable set,
+//! which writes the `.expected` files instead of comparing against them.
+
+use std::{fs, path::Path};
+
+use formatter::{formatter, Configuration, LineEnding, Operation, DEFAULT_MAX_WIDTH};
+
+fn format(input: &str) -> String {
+    let config = Configuration::parse_default_config();
+    let language = config.get_language("feather").unwrap();
+    let grammars = language.grammars().unwrap();
+    let mut output = Vec::new();
+    formatter(
+        &mut input.as_bytes(),
+        &mut output,
+        include_str!("../src/feather.scm"),
+        language,
+        &grammars,
+        Operation::Format {
+            skip_idempotence: false,
+            indent: None,
+            detect_indent: false,
+            max_width: DEFAULT_MAX_WIDTH,
+            line_ending: LineEnding::Lf,
+            reflow: false,
+        },
+    )
+    .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let update = std::env::var_os("UPDATE_EXPECTED").is_some();
+
+    let mut inputs = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "ftr")
+        })
+        .collect::<Vec<_>>();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no fixtures found in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for input in inputs {
+        let formatted = format(&fs::read_to_string(&input).unwrap());
+        assert_eq!(
+            format(&formatted),
+            formatted,
+            "{} was not formatted idempotently",
+            input.display()
+        );
+
+        let expected = input.with_extension("expected");
+        if update {
+            fs::write(&expected, &formatted).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&expected) {
+            Ok(contents) if contents == formatted => {}
+            Ok(contents) => failures.push(format!(
+                "{}:\n--- expected\n{contents}--- found\n{formatted}",
+                input.display()
+            )),
+            Err(err) => failures.push(format!("{}: {err}", expected.display())),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -3,6 +3,7 @@ use std::{
     ops::{Add, Sub},
 };
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DeBruijnIndex(u32);
 
@@ -30,10 +31,17 @@ impl DeBruijnIndex {
     }
 
     /// The previous (lower) de Bruijn index, or zero if one does not exist.
+    /// Use [`Self::checked_pred`] if the index may be zero and that case must be handled.
     pub fn pred(self) -> DeBruijnIndex {
         Self(self.0.saturating_sub(1))
     }
 
+    /// The previous (lower) de Bruijn index, or [`None`] if this index is zero.
+    pub fn checked_pred(self) -> Option<DeBruijnIndex> {
+        self.0.checked_sub(1).map(Self)
+    }
+
+    /// The number of binders between this variable and the binder it refers to.
     pub fn value(self) -> u32 {
         self.0
     }
@@ -54,11 +62,29 @@ impl DeBruijnOffset {
         Self(self.0 + 1)
     }
 
+    /// Decrease the offset by one, or return zero if the offset is already zero.
+    /// Use [`Self::checked_pred`] if the offset may be zero and that case must be handled.
+    pub fn pred(self) -> DeBruijnOffset {
+        Self(self.0.saturating_sub(1))
+    }
+
+    /// Decrease the offset by one, or return [`None`] if the offset is zero.
+    pub fn checked_pred(self) -> Option<DeBruijnOffset> {
+        self.0.checked_sub(1).map(Self)
+    }
+
+    /// Constructs an offset explicitly.
     pub fn new(offset: u32) -> DeBruijnOffset {
         Self(offset)
     }
+
+    /// The number of binders this offset represents.
+    pub fn value(self) -> u32 {
+        self.0
+    }
 }
 
+/// Adding an offset to an index gives the index of the same variable under that many more binders.
 impl Add<DeBruijnOffset> for DeBruijnIndex {
     type Output = DeBruijnIndex;
 
@@ -67,6 +93,8 @@ impl Add<DeBruijnOffset> for DeBruijnIndex {
     }
 }
 
+/// Subtracting an offset from an index gives the index of the same variable under that many fewer binders.
+/// This saturates at zero.
 impl Sub<DeBruijnOffset> for DeBruijnIndex {
     type Output = DeBruijnIndex;
 
@@ -83,6 +111,7 @@ impl Add for DeBruijnOffset {
     }
 }
 
+/// Offsets are subtracted with saturation at zero.
 impl Sub for DeBruijnOffset {
     type Output = Self;
 
@@ -90,3 +119,38 @@ impl Sub for DeBruijnOffset {
         Self(self.0.saturating_sub(rhs.0))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{DeBruijnIndex, DeBruijnOffset};
+
+    #[test]
+    fn pred_at_zero() {
+        assert_eq!(DeBruijnIndex::zero().pred(), DeBruijnIndex::zero());
+        assert_eq!(DeBruijnIndex::zero().checked_pred(), None);
+        assert_eq!(
+            DeBruijnIndex::zero().succ().checked_pred(),
+            Some(DeBruijnIndex::zero())
+        );
+        assert_eq!(DeBruijnOffset::zero().pred(), DeBruijnOffset::zero());
+        assert_eq!(DeBruijnOffset::zero().checked_pred(), None);
+        assert_eq!(
+            DeBruijnOffset::new(1).checked_pred(),
+            Some(DeBruijnOffset::zero())
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        let index = DeBruijnIndex::new(2);
+        assert_eq!((index + DeBruijnOffset::new(3)).value(), 5);
+        assert_eq!((index - DeBruijnOffset::new(1)).value(), 1);
+        assert_eq!(index - DeBruijnOffset::new(5), DeBruijnIndex::zero());
+        assert_eq!(
+            DeBruijnOffset::new(1) - DeBruijnOffset::new(2),
+            DeBruijnOffset::zero()
+        );
+        assert!(DeBruijnIndex::zero() < index);
+        assert!(DeBruijnOffset::zero() < DeBruijnOffset::new(1));
+    }
+}
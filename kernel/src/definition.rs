@@ -1,12 +1,42 @@
-use crate::expr::{Expression, Usage};
+use crate::expr::{Expression, UniverseVar, Usage};
 
 use files::{Str, WithProvenance};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Definition {
     pub name: WithProvenance<Str>,
+    /// The universe variables that the type and body may use.
+    /// Each `inst` expression referring to this definition gives a universe level for each of them,
+    /// so the definition can be used at many universe levels.
+    pub universe_params: Vec<UniverseVar>,
     pub usage: Usage,
     pub ty: Expression,
     /// Empty if the body contained an error or was not given.
     pub body: Option<Expression>,
 }
+
+impl Definition {
+    /// Creates a definition with the given name, universe parameters, usage, type and body.
+    /// The body should be [`None`] if it contained an error or was not given,
+    /// in which case the definition is an axiom.
+    pub fn new(
+        name: WithProvenance<Str>,
+        universe_params: Vec<UniverseVar>,
+        usage: Usage,
+        ty: Expression,
+        body: Option<Expression>,
+    ) -> Self {
+        Self {
+            name,
+            universe_params,
+            usage,
+            ty,
+            body,
+        }
+    }
+
+    /// Returns true if this definition has no body, so it is assumed rather than defined.
+    pub fn is_axiom(&self) -> bool {
+        self.body.is_none()
+    }
+}
@@ -0,0 +1,321 @@
+//! Random generation of expressions for property tests, enabled by the `test-util` feature.
+//!
+//! Expressions are interned in the database, so they cannot be produced directly by a [`Strategy`].
+//! Instead, we generate an [`ExpressionShape`], and intern it with [`ExpressionShape::build`].
+
+use files::{Path, Str};
+use proptest::{collection::vec, prelude::*};
+
+use crate::{de_bruijn::DeBruijnIndex, expr::*, vec_map::VecMap, Db};
+
+/// The names that may be given to bound variables.
+/// There are few of them, so generated expressions often shadow their own variables.
+const NAMES: [&str; 3] = ["x", "y", "z"];
+
+/// The shape of an expression generated by [`arbitrary_expression`].
+///
+/// Local variables are given as arbitrary numbers, and are reduced modulo the number of variables in scope
+/// when the expression is built, so built expressions are always closed.
+/// Names are given as indices into a fixed list of names.
+#[derive(Debug, Clone)]
+pub enum ExpressionShape {
+    Local(u32),
+    Apply(Box<ExpressionShape>, Box<ExpressionShape>),
+    Lambda(Box<BinderShape>),
+    Pi(Box<BinderShape>),
+    Let {
+        name: usize,
+        to_assign: Box<ExpressionShape>,
+        body: Box<ExpressionShape>,
+    },
+    Sort(u32),
+    Inst,
+    Intro {
+        parameters: Vec<ExpressionShape>,
+        fields: Vec<ExpressionShape>,
+    },
+    Match {
+        subject: Box<ExpressionShape>,
+        return_ty: Box<ExpressionShape>,
+        cases: Vec<ExpressionShape>,
+    },
+    /// The invocation style of the binder is ignored, since `fix` expressions always bind functions
+    /// that can be called many times.
+    Fix {
+        binder: Box<BinderShape>,
+        rec_name: usize,
+        body: Box<ExpressionShape>,
+    },
+    Ref(Box<ExpressionShape>),
+    Deref(Box<ExpressionShape>),
+    /// If there are no local variables in scope, this builds only its body.
+    Loan {
+        local: u32,
+        loan_as: usize,
+        with: usize,
+        body: Box<ExpressionShape>,
+    },
+    /// If there are no local variables in scope, this builds only its body.
+    Take {
+        local: u32,
+        proofs: Vec<(u32, ExpressionShape)>,
+        body: Box<ExpressionShape>,
+    },
+    In {
+        reference: Box<ExpressionShape>,
+        target: Box<ExpressionShape>,
+    },
+}
+
+/// The shape of a [`Binder`].
+#[derive(Debug, Clone)]
+pub struct BinderShape {
+    pub name: usize,
+    pub ty: ExpressionShape,
+    pub usage: Usage,
+    pub argument_style: ArgumentStyle,
+    pub invocation_style: InvocationStyle,
+    pub body: ExpressionShape,
+}
+
+/// A strategy that generates expressions which can be written as Feather code.
+/// Expressions are not necessarily well typed, and never contain holes or local constants.
+pub fn arbitrary_expression() -> impl Strategy<Value = ExpressionShape> {
+    let leaf = prop_oneof![
+        any::<u32>().prop_map(ExpressionShape::Local),
+        (0..3u32).prop_map(ExpressionShape::Sort),
+        Just(ExpressionShape::Inst),
+    ];
+    leaf.prop_recursive(4, 32, 3, |inner| {
+        let name = || 0..NAMES.len();
+        let binder = (
+            name(),
+            inner.clone(),
+            prop_oneof![Just(Usage::Erased), Just(Usage::Present)],
+            prop_oneof![
+                Just(ArgumentStyle::Explicit),
+                Just(ArgumentStyle::ImplicitEager),
+                Just(ArgumentStyle::ImplicitWeak),
+            ],
+            prop_oneof![Just(InvocationStyle::Once), Just(InvocationStyle::Many)],
+            inner.clone(),
+        )
+            .prop_map(
+                |(name, ty, usage, argument_style, invocation_style, body)| {
+                    Box::new(BinderShape {
+                        name,
+                        ty,
+                        usage,
+                        argument_style,
+                        invocation_style,
+                        body,
+                    })
+                },
+            );
+
+        // Expressions that bind new local variables.
+        let binders = prop_oneof![
+            binder.clone().prop_map(ExpressionShape::Lambda),
+            binder.clone().prop_map(ExpressionShape::Pi),
+            (name(), inner.clone(), inner.clone()).prop_map(|(name, to_assign, body)| {
+                ExpressionShape::Let {
+                    name,
+                    to_assign: Box::new(to_assign),
+                    body: Box::new(body),
+                }
+            }),
+            (binder, name(), inner.clone()).prop_map(|(binder, rec_name, body)| {
+                ExpressionShape::Fix {
+                    binder,
+                    rec_name,
+                    body: Box::new(body),
+                }
+            }),
+            (any::<u32>(), name(), name(), inner.clone()).prop_map(
+                |(local, loan_as, with, body)| ExpressionShape::Loan {
+                    local,
+                    loan_as,
+                    with,
+                    body: Box::new(body),
+                }
+            ),
+        ];
+
+        let others = prop_oneof![
+            (inner.clone(), inner.clone())
+                .prop_map(|(left, right)| ExpressionShape::Apply(Box::new(left), Box::new(right))),
+            (vec(inner.clone(), 0..3), vec(inner.clone(), 0..3))
+                .prop_map(|(parameters, fields)| ExpressionShape::Intro { parameters, fields }),
+            (inner.clone(), inner.clone(), vec(inner.clone(), 0..3)).prop_map(
+                |(subject, return_ty, cases)| ExpressionShape::Match {
+                    subject: Box::new(subject),
+                    return_ty: Box::new(return_ty),
+                    cases,
+                }
+            ),
+            inner
+                .clone()
+                .prop_map(|ty| ExpressionShape::Ref(Box::new(ty))),
+            inner
+                .clone()
+                .prop_map(|value| ExpressionShape::Deref(Box::new(value))),
+            (
+                any::<u32>(),
+                vec((any::<u32>(), inner.clone()), 0..3),
+                inner.clone()
+            )
+                .prop_map(|(local, proofs, body)| ExpressionShape::Take {
+                    local,
+                    proofs,
+                    body: Box::new(body),
+                }),
+            (inner.clone(), inner).prop_map(|(reference, target)| ExpressionShape::In {
+                reference: Box::new(reference),
+                target: Box::new(target),
+            }),
+        ];
+
+        prop_oneof![binders, others]
+    })
+}
+
+impl ExpressionShape {
+    /// Interns this expression in the given database.
+    pub fn build(&self, db: &dyn Db) -> Expression {
+        self.build_with_locals(db, 0)
+    }
+
+    /// Interns this expression, which is written underneath `locals` bound variables.
+    fn build_with_locals(&self, db: &dyn Db, locals: u32) -> Expression {
+        let build = |shape: &ExpressionShape, locals| shape.build_with_locals(db, locals);
+        let name = |index: usize| Str::new(db, NAMES[index].to_owned());
+        let path = || Path::parse(db, "test::item").unwrap();
+        let named = |prefix: &str, shapes: &[ExpressionShape]| -> VecMap<Str, Expression> {
+            shapes
+                .iter()
+                .enumerate()
+                .map(|(i, shape)| (Str::new(db, format!("{prefix}{i}")), build(shape, locals)))
+                .collect::<Vec<_>>()
+                .into()
+        };
+
+        match self {
+            ExpressionShape::Local(index) => match index.checked_rem(locals) {
+                Some(index) => Expression::new_local(db, DeBruijnIndex::new(index)),
+                None => Expression::new_sort(db, Universe::zero()),
+            },
+            ExpressionShape::Apply(left, right) => {
+                Expression::new_apply(db, build(left, locals), build(right, locals))
+            }
+            ExpressionShape::Lambda(binder) => {
+                Expression::new_lambda(db, binder.build(db, locals, binder.invocation_style))
+            }
+            ExpressionShape::Pi(binder) => {
+                Expression::new_pi(db, binder.build(db, locals, binder.invocation_style))
+            }
+            ExpressionShape::Let {
+                name: index,
+                to_assign,
+                body,
+            } => Expression::new_let(
+                db,
+                name(*index),
+                build(to_assign, locals),
+                build(body, locals + 1),
+            ),
+            ExpressionShape::Sort(level) => Expression::new_sort(db, Universe::Const(*level)),
+            ExpressionShape::Inst => Expression::new_inst(db, path()),
+            ExpressionShape::Intro { parameters, fields } => Expression::new_intro(
+                db,
+                path(),
+                parameters
+                    .iter()
+                    .map(|parameter| build(parameter, locals))
+                    .collect(),
+                Str::new(db, "variant".to_owned()),
+                named("field", fields),
+            ),
+            ExpressionShape::Match {
+                subject,
+                return_ty,
+                cases,
+            } => Expression::new_match(
+                db,
+                build(subject, locals),
+                build(return_ty, locals),
+                named("case", cases),
+            ),
+            ExpressionShape::Fix {
+                binder,
+                rec_name,
+                body,
+            } => Expression::new_fix(
+                db,
+                binder.build(db, locals, InvocationStyle::Many),
+                name(*rec_name),
+                build(body, locals + 2),
+            ),
+            ExpressionShape::Ref(ty) => Expression::new_ref(db, build(ty, locals)),
+            ExpressionShape::Deref(value) => Expression::new_deref(db, build(value, locals)),
+            ExpressionShape::Loan {
+                local,
+                loan_as,
+                with,
+                body,
+            } => match local.checked_rem(locals) {
+                Some(local) => Expression::new_loan(
+                    db,
+                    DeBruijnIndex::new(local),
+                    name(*loan_as),
+                    name(*with),
+                    build(body, locals + 2),
+                ),
+                None => build(body, locals),
+            },
+            ExpressionShape::Take {
+                local,
+                proofs,
+                body,
+            } => match local.checked_rem(locals) {
+                Some(local) => {
+                    // Each local variable may only be given one proof.
+                    let mut proof_map = VecMap::new();
+                    for (proof_local, proof) in proofs {
+                        proof_map.insert(
+                            DeBruijnIndex::new(proof_local % locals),
+                            build(proof, locals),
+                        );
+                    }
+                    Expression::new_take(
+                        db,
+                        DeBruijnIndex::new(local),
+                        proof_map,
+                        build(body, locals),
+                    )
+                }
+                None => build(body, locals),
+            },
+            ExpressionShape::In { reference, target } => {
+                Expression::new_in(db, build(reference, locals), build(target, locals))
+            }
+        }
+    }
+}
+
+impl BinderShape {
+    /// Interns this binder, which is written underneath `locals` bound variables.
+    fn build(&self, db: &dyn Db, locals: u32, invocation_style: InvocationStyle) -> Binder {
+        Binder {
+            structure: BinderStructure {
+                bound: BoundVariable {
+                    name: Str::new(db, NAMES[self.name].to_owned()),
+                    ty: self.ty.build_with_locals(db, locals),
+                    usage: self.usage,
+                },
+                argument_style: self.argument_style,
+                invocation_style,
+            },
+            body: self.body.build_with_locals(db, locals + 1),
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use files::{Path, Str};
 
@@ -29,7 +32,13 @@ pub enum ExpressionData {
         body: Expression,
     },
     Sort(Universe),
-    Inst(Path),
+    Inst {
+        /// The path of the definition to refer to.
+        path: Path,
+        /// The universe levels to instantiate the definition's universe parameters with,
+        /// in the order that the parameters were declared.
+        universes: Vec<Universe>,
+    },
     Intro {
         /// The path of the datatype or proposition type to introduce.
         path: Path,
@@ -137,9 +146,19 @@ impl Expression {
         Expression::new(db, ExpressionData::Sort(universe))
     }
 
-    /// Creates a new `Inst` expression.
+    /// Creates a new `Inst` expression referring to a definition with no universe parameters.
     pub fn new_inst(db: &dyn Db, path: Path) -> Expression {
-        Expression::new(db, ExpressionData::Inst(path))
+        Expression::new_inst_with_universes(db, path, Vec::new())
+    }
+
+    /// Creates a new `Inst` expression, instantiating the definition's universe parameters
+    /// with the given universe levels.
+    pub fn new_inst_with_universes(
+        db: &dyn Db,
+        path: Path,
+        universes: Vec<Universe>,
+    ) -> Expression {
+        Expression::new(db, ExpressionData::Inst { path, universes })
     }
 
     /// Creates a new `Intro` expression.
@@ -252,6 +271,7 @@ impl Expression {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Usage {
     Erased,
@@ -270,6 +290,7 @@ pub struct BoundVariable {
 }
 
 /// How should the argument to this function be given?
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ArgumentStyle {
     /// The argument is to be given explicitly.
@@ -281,6 +302,7 @@ pub enum ArgumentStyle {
 }
 
 /// How should the function be called?
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum InvocationStyle {
     /// The function is to be called exactly once.
@@ -305,13 +327,11 @@ pub struct Binder {
     pub body: Expression,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Universe(pub u32);
-
 /// An identifier for a local constant.
 /// These are considered unique inside a given query.
 ///
 /// An [`Ord`] implementation is provided to aid with determinism.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct LocalConstantId(pub u32);
 
@@ -327,14 +347,51 @@ pub struct LocalConstant {
     pub structure: BinderStructure,
 }
 
+impl LocalConstant {
+    /// Creates a local constant with an id that is distinct from the id of every other local constant
+    /// created by this function, even across threads and databases.
+    /// Two fresh local constants with the same structure are therefore never equal,
+    /// so they can be safely used with [`Expression::abstract_binder`] and [`Expression::replace_local`].
+    ///
+    /// Ids are allocated from the top of the range of `u32` downwards,
+    /// so they will not collide with small ids chosen manually.
+    pub fn fresh(structure: BinderStructure) -> Self {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(u32::MAX);
+        Self {
+            id: LocalConstantId(NEXT_ID.fetch_sub(1, Ordering::Relaxed)),
+            structure,
+        }
+    }
+}
+
 /// An identifier for a hole.
 /// These are considered unique inside a given query.
 /// When a hole is filled, all holes with this ID are processed in the same way.
 ///
 /// An [`Ord`] implementation is provided to aid with determinism.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct HoleId(pub u32);
 
+impl HoleId {
+    /// Creates a hole id that is distinct from every other hole id created by this function,
+    /// even across threads, source files, and databases.
+    ///
+    /// Like [`LocalConstant::fresh`], ids are allocated from the top of the range of `u32` downwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if every id has already been allocated.
+    pub fn fresh() -> Self {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(u32::MAX);
+        Self(
+            NEXT_ID
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| id.checked_sub(1))
+                .expect("ran out of hole ids"),
+        )
+    }
+}
+
 impl Display for HoleId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "?{}", self.0)
@@ -344,7 +401,7 @@ impl Display for HoleId {
 /// An inference variable.
 /// May have theoretically any type.
 /// Also called a *metavariable*, although this name is sometimes reserved for holes with no arguments.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Hole {
     /// An index for this metavariable.
     /// This is unique inside a particular query.
@@ -353,3 +410,24 @@ pub struct Hole {
     /// The type of this hole.
     pub ty: Expression,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_db::{local_constant, TestDatabase};
+
+    #[test]
+    fn fresh_local_constants_are_distinct() {
+        let db = TestDatabase::default();
+        let structure = local_constant(&db, 0, "x").structure;
+        let first = LocalConstant::fresh(structure);
+        let second = LocalConstant::fresh(structure);
+        assert_ne!(first, second);
+        assert_ne!(first.id, second.id);
+    }
+
+    #[test]
+    fn fresh_hole_ids_are_distinct() {
+        assert_ne!(HoleId::fresh(), HoleId::fresh());
+    }
+}
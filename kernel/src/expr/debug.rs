@@ -0,0 +1,246 @@
+//! Shows the structure of expressions for debugging.
+//!
+//! Sub-expressions are salsa ids, so the derived [`Debug`] implementation of [`Expression`] only shows an opaque id.
+//! The wrappers in this module look up the data of each sub-expression in the database,
+//! so that the whole tree is shown.
+
+use std::fmt::{Debug, Display, Formatter, Result};
+
+use files::Str;
+
+use crate::{de_bruijn::DeBruijnIndex, vec_map::VecMap, Db};
+
+use super::*;
+
+/// Shows the structure of an expression. Created by [`Expression::debug`].
+#[derive(Clone, Copy)]
+pub struct ExpressionDebug<'a> {
+    db: &'a dyn Db,
+    expr: Expression,
+}
+
+impl Expression {
+    /// Returns a value whose [`Debug`] implementation shows the whole tree of this expression,
+    /// which is useful in `tracing` calls and test failures.
+    /// Unlike [`Expression::display`], local variables are shown as their raw de Bruijn indices,
+    /// holes and local constants are shown with their ids, and nothing is elided.
+    pub fn debug(self, db: &dyn Db) -> ExpressionDebug<'_> {
+        ExpressionDebug { db, expr: self }
+    }
+}
+
+impl Debug for ExpressionDebug<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let db = self.db;
+        match self.expr.data(db) {
+            ExpressionData::Local(index) => f.debug_tuple("Local").field(&Verbatim(index)).finish(),
+            ExpressionData::Apply { left, right } => f
+                .debug_struct("Apply")
+                .field("left", &left.debug(db))
+                .field("right", &right.debug(db))
+                .finish(),
+            ExpressionData::Lambda(binder) => f
+                .debug_tuple("Lambda")
+                .field(&BinderDebug { db, binder })
+                .finish(),
+            ExpressionData::Pi(binder) => f
+                .debug_tuple("Pi")
+                .field(&BinderDebug { db, binder })
+                .finish(),
+            ExpressionData::Let {
+                name,
+                to_assign,
+                body,
+            } => f
+                .debug_struct("Let")
+                .field("name", name.text(db))
+                .field("to_assign", &to_assign.debug(db))
+                .field("body", &body.debug(db))
+                .finish(),
+            ExpressionData::Sort(universe) => f
+                .debug_tuple("Sort")
+                .field(&Verbatim(universe.display(db)))
+                .finish(),
+            ExpressionData::Inst { path, universes } => {
+                let mut tuple = f.debug_tuple("Inst");
+                tuple.field(&Verbatim(path.display(db)));
+                for universe in universes {
+                    tuple.field(&Verbatim(universe.display(db)));
+                }
+                tuple.finish()
+            }
+            ExpressionData::Intro {
+                path,
+                parameters,
+                variant,
+                fields,
+            } => f
+                .debug_struct("Intro")
+                .field("path", &Verbatim(path.display(db)))
+                .field(
+                    "parameters",
+                    &parameters
+                        .iter()
+                        .map(|param| param.debug(db))
+                        .collect::<Vec<_>>(),
+                )
+                .field("variant", variant.text(db))
+                .field("fields", &NamedDebug { db, map: &fields })
+                .finish(),
+            ExpressionData::Match {
+                subject,
+                return_ty,
+                cases,
+            } => f
+                .debug_struct("Match")
+                .field("subject", &subject.debug(db))
+                .field("return_ty", &return_ty.debug(db))
+                .field("cases", &NamedDebug { db, map: &cases })
+                .finish(),
+            ExpressionData::Fix {
+                binder,
+                rec_name,
+                body,
+            } => f
+                .debug_struct("Fix")
+                .field("binder", &BinderDebug { db, binder })
+                .field("rec_name", rec_name.text(db))
+                .field("body", &body.debug(db))
+                .finish(),
+            ExpressionData::Ref(ty) => f.debug_tuple("Ref").field(&ty.debug(db)).finish(),
+            ExpressionData::Deref(value) => f.debug_tuple("Deref").field(&value.debug(db)).finish(),
+            ExpressionData::Loan {
+                local,
+                loan_as,
+                with,
+                body,
+            } => f
+                .debug_struct("Loan")
+                .field("local", &Verbatim(local))
+                .field("loan_as", loan_as.text(db))
+                .field("with", with.text(db))
+                .field("body", &body.debug(db))
+                .finish(),
+            ExpressionData::Take {
+                local,
+                proofs,
+                body,
+            } => f
+                .debug_struct("Take")
+                .field("local", &Verbatim(local))
+                .field(
+                    "proofs",
+                    &ProofsDebug {
+                        db,
+                        proofs: &proofs,
+                    },
+                )
+                .field("body", &body.debug(db))
+                .finish(),
+            ExpressionData::In { reference, target } => f
+                .debug_struct("In")
+                .field("reference", &reference.debug(db))
+                .field("target", &target.debug(db))
+                .finish(),
+            ExpressionData::LocalConstant(constant) => f
+                .debug_struct("LocalConstant")
+                .field("id", &constant.id.0)
+                .field(
+                    "structure",
+                    &StructureDebug {
+                        db,
+                        structure: constant.structure,
+                    },
+                )
+                .finish(),
+            ExpressionData::Hole(hole) => f
+                .debug_struct("Hole")
+                .field("id", &Verbatim(hole.id))
+                .field("ty", &hole.ty.debug(db))
+                .finish(),
+        }
+    }
+}
+
+struct BinderDebug<'a> {
+    db: &'a dyn Db,
+    binder: Binder,
+}
+
+impl Debug for BinderDebug<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_struct("Binder")
+            .field(
+                "structure",
+                &StructureDebug {
+                    db: self.db,
+                    structure: self.binder.structure,
+                },
+            )
+            .field("body", &self.binder.body.debug(self.db))
+            .finish()
+    }
+}
+
+struct StructureDebug<'a> {
+    db: &'a dyn Db,
+    structure: BinderStructure,
+}
+
+impl Debug for StructureDebug<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let bound = self.structure.bound;
+        f.debug_struct("BinderStructure")
+            .field("name", bound.name.text(self.db))
+            .field("ty", &bound.ty.debug(self.db))
+            .field("usage", &bound.usage)
+            .field("argument_style", &self.structure.argument_style)
+            .field("invocation_style", &self.structure.invocation_style)
+            .finish()
+    }
+}
+
+/// Shows a map from names to expressions, such as the fields of an `intro` expression.
+struct NamedDebug<'a, 'b> {
+    db: &'a dyn Db,
+    map: &'b VecMap<Str, Expression>,
+}
+
+impl Debug for NamedDebug<'_, '_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_map()
+            .entries(
+                self.map
+                    .iter()
+                    .map(|(name, expr)| (name.text(self.db), expr.debug(self.db))),
+            )
+            .finish()
+    }
+}
+
+/// Shows the proofs of a `take` expression, keyed by the de Bruijn indices of the variables they are about.
+struct ProofsDebug<'a, 'b> {
+    db: &'a dyn Db,
+    proofs: &'b VecMap<DeBruijnIndex, Expression>,
+}
+
+impl Debug for ProofsDebug<'_, '_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_map()
+            .entries(
+                self.proofs
+                    .iter()
+                    .map(|(index, proof)| (Verbatim(*index), proof.debug(self.db))),
+            )
+            .finish()
+    }
+}
+
+/// Shows a value using its [`Display`] implementation, without quotes.
+struct Verbatim<T>(T);
+
+impl<T: Display> Debug for Verbatim<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.0)
+    }
+}
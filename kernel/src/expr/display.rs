@@ -0,0 +1,427 @@
+//! Displays expressions as Feather code without invoking the formatter.
+//!
+//! The output is written on a single line, so it is suitable for logging and diagnostic messages.
+//! For nicely formatted output, use [`crate::Db::format_expression`] instead.
+
+use std::fmt::Write;
+
+use files::Str;
+
+use crate::{de_bruijn::DeBruijnIndex, Db};
+
+use super::*;
+
+/// Options that control how [`Expression::display`] shows an expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayOptions {
+    /// If true, local variables are shown as their raw de Bruijn indices, such as `#0`,
+    /// rather than the names of the binders that introduced them.
+    pub raw_indices: bool,
+    /// If false, the universe levels of `Sort` and `inst` expressions are replaced with `_`.
+    pub show_universes: bool,
+    /// If set, sub-expressions nested more deeply than this are elided and shown as `…`.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            raw_indices: false,
+            show_universes: true,
+            max_depth: None,
+        }
+    }
+}
+
+/// The precedence levels of Feather expressions, from the loosest binding to the tightest.
+/// An expression must be parenthesised if its precedence is lower than that required by its position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
+    /// Expressions like `fun`, `let` and `*` whose bodies extend as far to the right as possible.
+    Binder,
+    /// An `in` expression.
+    In,
+    /// Function application and `ref` expressions.
+    Apply,
+    /// Expressions that cannot be split apart by their surroundings, such as locals, `inst` and `intro`.
+    Atom,
+}
+
+impl Precedence {
+    /// The precedence of the given expression when written as Feather code.
+    pub fn of(db: &dyn Db, expr: Expression) -> Self {
+        match expr.data(db) {
+            ExpressionData::Lambda(_)
+            | ExpressionData::Pi(_)
+            | ExpressionData::Let { .. }
+            | ExpressionData::Fix { .. }
+            | ExpressionData::Loan { .. }
+            | ExpressionData::Take { .. }
+            | ExpressionData::Deref(_) => Precedence::Binder,
+            ExpressionData::In { .. } => Precedence::In,
+            ExpressionData::Apply { .. } | ExpressionData::Ref(_) => Precedence::Apply,
+            ExpressionData::Local(_)
+            | ExpressionData::Sort(_)
+            | ExpressionData::Inst { .. }
+            | ExpressionData::Intro { .. }
+            | ExpressionData::Match { .. }
+            | ExpressionData::LocalConstant(_)
+            | ExpressionData::Hole(_) => Precedence::Atom,
+        }
+    }
+}
+
+/// Returns `name` if it is not used, and otherwise a variant of it that is not used,
+/// so that a new bound variable does not shadow any variable in scope.
+/// Like [`files::StrGenerator`], we append an increasing counter to the name until it is unused.
+pub fn unshadowed_name(db: &dyn Db, name: Str, is_used: impl Fn(&Str) -> bool) -> Str {
+    if !is_used(&name) {
+        return name;
+    }
+    (1..)
+        .map(|counter| name.with_suffix(db, counter))
+        .find(|candidate| !is_used(candidate))
+        .expect("there are infinitely many candidate names")
+}
+
+impl Expression {
+    /// Displays this expression as Feather code on a single line.
+    /// Unlike [`crate::Db::format_expression`], this does not run the formatter, so it cannot fail.
+    ///
+    /// Bound variables whose names would shadow another variable in scope, or a local constant, are renamed
+    /// by appending a number, so that the output is unambiguous.
+    pub fn display(self, db: &dyn Db, options: &DisplayOptions) -> String {
+        let mut reserved = Vec::new();
+        self.for_each_expression(db, |inner, _offset| {
+            if let ExpressionData::LocalConstant(constant) = inner.data(db) {
+                reserved.push(constant.structure.bound.name);
+            }
+        });
+        let mut printer = Printer {
+            db,
+            options,
+            locals: Vec::new(),
+            reserved,
+            depth: 0,
+            output: String::new(),
+        };
+        printer
+            .expression(self, Precedence::Binder)
+            .expect("should not error while writing to a string");
+        printer.output
+    }
+}
+
+struct Printer<'a> {
+    db: &'a dyn Db,
+    options: &'a DisplayOptions,
+    /// The names of the local variables in scope, with the innermost variable first.
+    locals: Vec<Str>,
+    /// The names of the local constants in the expression, which bound variables must not shadow.
+    reserved: Vec<Str>,
+    /// The number of expressions that enclose the expression currently being written.
+    depth: usize,
+    output: String,
+}
+
+impl<'a> Printer<'a> {
+    /// Writes the given expression, wrapping it in parentheses if it binds more loosely than `precedence`.
+    fn expression(&mut self, expr: Expression, precedence: Precedence) -> std::fmt::Result {
+        if self
+            .options
+            .max_depth
+            .map_or(false, |max_depth| self.depth >= max_depth)
+        {
+            return write!(self.output, "…");
+        }
+
+        self.depth += 1;
+        let result = if Precedence::of(self.db, expr) < precedence {
+            write!(self.output, "(")
+                .and_then(|()| self.unparenthesised(expr))
+                .and_then(|()| write!(self.output, ")"))
+        } else {
+            self.unparenthesised(expr)
+        };
+        self.depth -= 1;
+        result
+    }
+
+    /// Returns a name for a new bound variable that does not shadow any variable in scope,
+    /// or any of the `extra` names that will be bound at the same time.
+    fn unshadowed(&self, name: Str, extra: &[Str]) -> Str {
+        unshadowed_name(self.db, name, |candidate| {
+            self.locals.contains(candidate)
+                || self.reserved.contains(candidate)
+                || extra.contains(candidate)
+        })
+    }
+
+    /// Writes `expr` in a context where the given local variables are bound, with the innermost variable last.
+    /// The names should already have been passed through [`Printer::unshadowed`].
+    fn under(&mut self, names: &[Str], expr: Expression) -> std::fmt::Result {
+        for name in names {
+            self.locals.insert(0, *name);
+        }
+        let result = self.expression(expr, Precedence::Binder);
+        self.locals.drain(0..names.len());
+        result
+    }
+
+    /// Writes the name of the given local variable.
+    fn local(&mut self, index: DeBruijnIndex) -> std::fmt::Result {
+        match self.locals.get(index.value() as usize) {
+            Some(name) if !self.options.raw_indices => {
+                write!(self.output, "{}", name.text(self.db))
+            }
+            _ => write!(self.output, "#{}", index.value()),
+        }
+    }
+
+    fn unparenthesised(&mut self, expr: Expression) -> std::fmt::Result {
+        let db = self.db;
+        match expr.data(db) {
+            ExpressionData::Local(index) => self.local(index),
+            ExpressionData::Apply { left, right } => {
+                // Application is left-associative.
+                self.expression(left, Precedence::Apply)?;
+                write!(self.output, " ")?;
+                self.expression(right, Precedence::Atom)
+            }
+            ExpressionData::Lambda(binder) => {
+                write!(self.output, "fun ")?;
+                self.binder(binder).map(|_| ())
+            }
+            ExpressionData::Pi(binder) => {
+                write!(self.output, "for ")?;
+                self.binder(binder).map(|_| ())
+            }
+            ExpressionData::Let {
+                name,
+                to_assign,
+                body,
+            } => {
+                let name = self.unshadowed(name, &[]);
+                write!(self.output, "let {} = ", name.text(db))?;
+                self.expression(to_assign, Precedence::Binder)?;
+                write!(self.output, "; ")?;
+                self.under(&[name], body)
+            }
+            ExpressionData::Sort(universe) => {
+                if self.options.show_universes {
+                    write!(self.output, "Sort {}", universe.display(db))
+                } else {
+                    write!(self.output, "Sort _")
+                }
+            }
+            ExpressionData::Inst { path, universes } => {
+                write!(self.output, "inst {}", path.display(db))?;
+                if !universes.is_empty() {
+                    let universes = universes
+                        .iter()
+                        .map(|universe| {
+                            if self.options.show_universes {
+                                universe.display(db)
+                            } else {
+                                "_".to_owned()
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    write!(self.output, ".{{{}}}", universes.join(", "))?;
+                }
+                Ok(())
+            }
+            ExpressionData::Intro {
+                path,
+                parameters,
+                variant,
+                fields,
+            } => {
+                write!(self.output, "intro {}", path.display(db))?;
+                for param in parameters {
+                    write!(self.output, " ")?;
+                    self.expression(param, Precedence::Atom)?;
+                }
+                write!(self.output, " / {} {{", variant.text(db))?;
+                for (name, field) in fields.iter() {
+                    write!(self.output, " {} = ", name.text(db))?;
+                    self.expression(*field, Precedence::Binder)?;
+                    write!(self.output, ",")?;
+                }
+                write!(self.output, " }}")
+            }
+            ExpressionData::Match {
+                subject,
+                return_ty,
+                cases,
+            } => {
+                write!(self.output, "match ")?;
+                self.expression(subject, Precedence::Binder)?;
+                write!(self.output, " return ")?;
+                self.expression(return_ty, Precedence::Binder)?;
+                write!(self.output, " {{")?;
+                for (name, case) in cases.iter() {
+                    write!(self.output, " {} -> ", name.text(db))?;
+                    self.expression(*case, Precedence::Binder)?;
+                    write!(self.output, ",")?;
+                }
+                write!(self.output, " }}")
+            }
+            ExpressionData::Fix {
+                binder,
+                rec_name,
+                body,
+            } => {
+                write!(self.output, "fix ")?;
+                let name = self.binder(binder)?;
+                let rec_name = self.unshadowed(rec_name, &[name]);
+                write!(self.output, " with {}; ", rec_name.text(db))?;
+                self.under(&[name, rec_name], body)
+            }
+            ExpressionData::Ref(ty) => {
+                write!(self.output, "ref ")?;
+                self.expression(ty, Precedence::Atom)
+            }
+            ExpressionData::Deref(value) => {
+                write!(self.output, "* ")?;
+                self.expression(value, Precedence::Atom)
+            }
+            ExpressionData::Loan {
+                local,
+                loan_as,
+                with,
+                body,
+            } => {
+                write!(self.output, "loan ")?;
+                self.local(local)?;
+                let loan_as = self.unshadowed(loan_as, &[]);
+                let with = self.unshadowed(with, &[loan_as]);
+                write!(
+                    self.output,
+                    " as {} with {}; ",
+                    loan_as.text(db),
+                    with.text(db)
+                )?;
+                self.under(&[loan_as, with], body)
+            }
+            ExpressionData::Take {
+                local,
+                proofs,
+                body,
+            } => {
+                write!(self.output, "take ")?;
+                self.local(local)?;
+                write!(self.output, " {{")?;
+                for (index, proof) in proofs.iter() {
+                    write!(self.output, " ")?;
+                    self.local(*index)?;
+                    write!(self.output, " -> ")?;
+                    self.expression(*proof, Precedence::Binder)?;
+                    write!(self.output, ",")?;
+                }
+                write!(self.output, " }}; ")?;
+                self.expression(body, Precedence::Binder)
+            }
+            ExpressionData::In { reference, target } => {
+                // `in` is left-associative.
+                self.expression(reference, Precedence::In)?;
+                write!(self.output, " in ")?;
+                self.expression(target, Precedence::Apply)
+            }
+            ExpressionData::LocalConstant(constant) => {
+                write!(self.output, "{}", constant.structure.bound.name.text(db))
+            }
+            ExpressionData::Hole(hole) => write!(self.output, "{}", hole.id),
+        }
+    }
+
+    /// Writes a binder, such as `(x : A) -> body`, returning the name that was used for the bound variable.
+    fn binder(&mut self, binder: Binder) -> Result<Str, std::fmt::Error> {
+        let db = self.db;
+        let structure = binder.structure;
+        let name = self.unshadowed(structure.bound.name, &[]);
+        match structure.argument_style {
+            ArgumentStyle::Explicit => write!(self.output, "(")?,
+            ArgumentStyle::ImplicitEager => write!(self.output, "{{")?,
+            ArgumentStyle::ImplicitWeak => write!(self.output, "{{{{")?,
+        }
+        write!(self.output, "{} : ", name.text(db))?;
+        if structure.bound.usage == Usage::Erased {
+            write!(self.output, "0 ")?;
+        }
+        self.expression(structure.bound.ty, Precedence::Binder)?;
+        match structure.argument_style {
+            ArgumentStyle::Explicit => write!(self.output, ")")?,
+            ArgumentStyle::ImplicitEager => write!(self.output, "}}")?,
+            ArgumentStyle::ImplicitWeak => write!(self.output, "}}}}")?,
+        }
+        match structure.invocation_style {
+            InvocationStyle::Once => write!(self.output, " -> ")?,
+            InvocationStyle::Many => write!(self.output, " => ")?,
+        }
+        self.under(&[name], binder.body)?;
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_db::{local_constant, TestDatabase};
+
+    #[test]
+    fn display_expressions() {
+        let db = TestDatabase::default();
+        let a = local_constant(&db, 0, "a");
+        let identity = Expression::new_lambda(
+            &db,
+            Expression::new_local_constant(&db, a).abstract_binder(&db, a),
+        );
+        let display = |options| identity.display(&db, &options);
+
+        assert_eq!(display(DisplayOptions::default()), "fun (a : Sort 0) -> a");
+        assert_eq!(
+            display(DisplayOptions {
+                raw_indices: true,
+                show_universes: false,
+                max_depth: None,
+            }),
+            "fun (a : Sort _) -> #0"
+        );
+        assert_eq!(
+            display(DisplayOptions {
+                max_depth: Some(1),
+                ..Default::default()
+            }),
+            "fun (a : …) -> …"
+        );
+    }
+
+    #[test]
+    fn display_renames_shadowed_binders() {
+        let db = TestDatabase::default();
+        let x = local_constant(&db, 0, "x");
+        let lambda = |body| {
+            Expression::new_lambda(
+                &db,
+                Binder {
+                    structure: x.structure,
+                    body,
+                },
+            )
+        };
+        // `fun x -> fun x -> x`, where the body refers to the outer `x`.
+        let expr = lambda(lambda(Expression::new_local(&db, DeBruijnIndex::new(1))));
+        assert_eq!(
+            expr.display(&db, &DisplayOptions::default()),
+            "fun (x : Sort 0) -> fun (x1 : Sort 0) -> x"
+        );
+
+        // Bound variables must not capture local constants with the same name.
+        let expr = lambda(Expression::new_local_constant(&db, x));
+        assert_eq!(
+            expr.display(&db, &DisplayOptions::default()),
+            "fun (x1 : Sort 0) -> x"
+        );
+    }
+}
@@ -1,7 +1,21 @@
+#[cfg(any(test, feature = "test-util"))]
+mod arbitrary;
 mod basic;
+mod debug;
+mod display;
 mod find_replace;
+#[cfg(feature = "serde")]
+mod serialize;
+mod universe;
 mod util;
 
+#[cfg(any(test, feature = "test-util"))]
+pub use arbitrary::*;
 pub use basic::*;
+pub use debug::*;
+pub use display::*;
 pub use find_replace::*;
+#[cfg(feature = "serde")]
+pub use serialize::*;
+pub use universe::*;
 pub use util::*;
@@ -0,0 +1,482 @@
+//! A serialisable representation of expressions, used for caching elaborated terms to disk.
+//!
+//! Expressions, strings, and paths are interned in the database, so their ids are meaningless
+//! outside of the database that created them.
+//! Instead of serialising them directly, we convert them into owned data with
+//! [`Expression::to_serializable`], and intern them again with [`Expression::from_serializable`].
+
+use files::{Path, Str};
+use serde::{Deserialize, Serialize};
+
+use crate::{de_bruijn::DeBruijnIndex, expr::*, vec_map::VecMap, Db};
+
+/// The serialisable form of an [`ExpressionData`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SerializableExpression {
+    Local(DeBruijnIndex),
+    Apply {
+        left: Box<SerializableExpression>,
+        right: Box<SerializableExpression>,
+    },
+    Lambda(SerializableBinder),
+    Pi(SerializableBinder),
+    Let {
+        name: String,
+        to_assign: Box<SerializableExpression>,
+        body: Box<SerializableExpression>,
+    },
+    Sort(SerializableUniverse),
+    Inst {
+        path: Vec<String>,
+        universes: Vec<SerializableUniverse>,
+    },
+    Intro {
+        path: Vec<String>,
+        parameters: Vec<SerializableExpression>,
+        variant: String,
+        fields: Vec<(String, SerializableExpression)>,
+    },
+    Match {
+        subject: Box<SerializableExpression>,
+        return_ty: Box<SerializableExpression>,
+        cases: Vec<(String, SerializableExpression)>,
+    },
+    Fix {
+        binder: SerializableBinder,
+        rec_name: String,
+        body: Box<SerializableExpression>,
+    },
+    Ref(Box<SerializableExpression>),
+    Deref(Box<SerializableExpression>),
+    Loan {
+        local: DeBruijnIndex,
+        loan_as: String,
+        with: String,
+        body: Box<SerializableExpression>,
+    },
+    Take {
+        local: DeBruijnIndex,
+        proofs: Vec<(DeBruijnIndex, SerializableExpression)>,
+        body: Box<SerializableExpression>,
+    },
+    In {
+        reference: Box<SerializableExpression>,
+        target: Box<SerializableExpression>,
+    },
+    LocalConstant {
+        id: LocalConstantId,
+        structure: SerializableBinderStructure,
+    },
+    Hole {
+        id: HoleId,
+        ty: Box<SerializableExpression>,
+    },
+}
+
+/// The serialisable form of a [`BoundVariable`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SerializableBoundVariable {
+    pub name: String,
+    pub ty: Box<SerializableExpression>,
+    pub usage: Usage,
+}
+
+/// The serialisable form of a [`BinderStructure`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SerializableBinderStructure {
+    pub bound: SerializableBoundVariable,
+    pub argument_style: ArgumentStyle,
+    pub invocation_style: InvocationStyle,
+}
+
+/// The serialisable form of a [`Binder`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SerializableBinder {
+    pub structure: SerializableBinderStructure,
+    pub body: Box<SerializableExpression>,
+}
+
+/// The serialisable form of a [`Universe`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SerializableUniverse {
+    Const(u32),
+    Var(String),
+    Max(Box<SerializableUniverse>, Box<SerializableUniverse>),
+    IMax(Box<SerializableUniverse>, Box<SerializableUniverse>),
+    Succ(Box<SerializableUniverse>),
+}
+
+fn path_to_serializable(db: &dyn Db, path: Path) -> Vec<String> {
+    path.segments(db)
+        .iter()
+        .map(|segment| segment.text(db).clone())
+        .collect()
+}
+
+fn path_from_serializable(db: &dyn Db, path: &[String]) -> Path {
+    Path::new(
+        db,
+        path.iter()
+            .map(|segment| Str::new(db, segment.clone()))
+            .collect(),
+    )
+}
+
+fn map_to_serializable(
+    db: &dyn Db,
+    map: VecMap<Str, Expression>,
+) -> Vec<(String, SerializableExpression)> {
+    map.into_iter()
+        .map(|(k, v)| (k.text(db).clone(), v.to_serializable(db)))
+        .collect()
+}
+
+fn map_from_serializable(
+    db: &dyn Db,
+    map: &[(String, SerializableExpression)],
+) -> VecMap<Str, Expression> {
+    map.iter()
+        .map(|(k, v)| {
+            (
+                Str::new(db, k.clone()),
+                Expression::from_serializable(db, v),
+            )
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+impl Expression {
+    /// Converts this expression into a form that does not depend on the database, so that it can be serialised.
+    pub fn to_serializable(self, db: &dyn Db) -> SerializableExpression {
+        let boxed = |expr: Expression| Box::new(expr.to_serializable(db));
+        match self.data(db) {
+            ExpressionData::Local(index) => SerializableExpression::Local(index),
+            ExpressionData::Apply { left, right } => SerializableExpression::Apply {
+                left: boxed(left),
+                right: boxed(right),
+            },
+            ExpressionData::Lambda(binder) => {
+                SerializableExpression::Lambda(binder.to_serializable(db))
+            }
+            ExpressionData::Pi(binder) => SerializableExpression::Pi(binder.to_serializable(db)),
+            ExpressionData::Let {
+                name,
+                to_assign,
+                body,
+            } => SerializableExpression::Let {
+                name: name.text(db).clone(),
+                to_assign: boxed(to_assign),
+                body: boxed(body),
+            },
+            ExpressionData::Sort(universe) => {
+                SerializableExpression::Sort(universe.to_serializable(db))
+            }
+            ExpressionData::Inst { path, universes } => SerializableExpression::Inst {
+                path: path_to_serializable(db, path),
+                universes: universes
+                    .iter()
+                    .map(|universe| universe.to_serializable(db))
+                    .collect(),
+            },
+            ExpressionData::Intro {
+                path,
+                parameters,
+                variant,
+                fields,
+            } => SerializableExpression::Intro {
+                path: path_to_serializable(db, path),
+                parameters: parameters
+                    .into_iter()
+                    .map(|param| param.to_serializable(db))
+                    .collect(),
+                variant: variant.text(db).clone(),
+                fields: map_to_serializable(db, fields),
+            },
+            ExpressionData::Match {
+                subject,
+                return_ty,
+                cases,
+            } => SerializableExpression::Match {
+                subject: boxed(subject),
+                return_ty: boxed(return_ty),
+                cases: map_to_serializable(db, cases),
+            },
+            ExpressionData::Fix {
+                binder,
+                rec_name,
+                body,
+            } => SerializableExpression::Fix {
+                binder: binder.to_serializable(db),
+                rec_name: rec_name.text(db).clone(),
+                body: boxed(body),
+            },
+            ExpressionData::Ref(ty) => SerializableExpression::Ref(boxed(ty)),
+            ExpressionData::Deref(value) => SerializableExpression::Deref(boxed(value)),
+            ExpressionData::Loan {
+                local,
+                loan_as,
+                with,
+                body,
+            } => SerializableExpression::Loan {
+                local,
+                loan_as: loan_as.text(db).clone(),
+                with: with.text(db).clone(),
+                body: boxed(body),
+            },
+            ExpressionData::Take {
+                local,
+                proofs,
+                body,
+            } => SerializableExpression::Take {
+                local,
+                proofs: proofs
+                    .into_iter()
+                    .map(|(index, proof)| (index, proof.to_serializable(db)))
+                    .collect(),
+                body: boxed(body),
+            },
+            ExpressionData::In { reference, target } => SerializableExpression::In {
+                reference: boxed(reference),
+                target: boxed(target),
+            },
+            ExpressionData::LocalConstant(constant) => SerializableExpression::LocalConstant {
+                id: constant.id,
+                structure: constant.structure.to_serializable(db),
+            },
+            ExpressionData::Hole(hole) => SerializableExpression::Hole {
+                id: hole.id,
+                ty: boxed(hole.ty),
+            },
+        }
+    }
+
+    /// Interns a deserialised expression into the given database.
+    ///
+    /// The ids of local constants are kept as they were serialised, so that deserialising is the inverse of
+    /// [`Expression::to_serializable`]. They are not renumbered, so they may collide with ids issued by
+    /// [`LocalConstant::fresh`] in this process, which does not know which ids were in use when they were serialised.
+    /// Certified definitions contain no local constants, so this only matters for open terms.
+    pub fn from_serializable(db: &dyn Db, expr: &SerializableExpression) -> Self {
+        let unboxed = |expr: &SerializableExpression| Expression::from_serializable(db, expr);
+        let intern = |text: &String| Str::new(db, text.clone());
+        let data = match expr {
+            SerializableExpression::Local(index) => ExpressionData::Local(*index),
+            SerializableExpression::Apply { left, right } => ExpressionData::Apply {
+                left: unboxed(left),
+                right: unboxed(right),
+            },
+            SerializableExpression::Lambda(binder) => {
+                ExpressionData::Lambda(Binder::from_serializable(db, binder))
+            }
+            SerializableExpression::Pi(binder) => {
+                ExpressionData::Pi(Binder::from_serializable(db, binder))
+            }
+            SerializableExpression::Let {
+                name,
+                to_assign,
+                body,
+            } => ExpressionData::Let {
+                name: intern(name),
+                to_assign: unboxed(to_assign),
+                body: unboxed(body),
+            },
+            SerializableExpression::Sort(universe) => {
+                ExpressionData::Sort(Universe::from_serializable(db, universe))
+            }
+            SerializableExpression::Inst { path, universes } => ExpressionData::Inst {
+                path: path_from_serializable(db, path),
+                universes: universes
+                    .iter()
+                    .map(|universe| Universe::from_serializable(db, universe))
+                    .collect(),
+            },
+            SerializableExpression::Intro {
+                path,
+                parameters,
+                variant,
+                fields,
+            } => ExpressionData::Intro {
+                path: path_from_serializable(db, path),
+                parameters: parameters.iter().map(unboxed).collect(),
+                variant: intern(variant),
+                fields: map_from_serializable(db, fields),
+            },
+            SerializableExpression::Match {
+                subject,
+                return_ty,
+                cases,
+            } => ExpressionData::Match {
+                subject: unboxed(subject),
+                return_ty: unboxed(return_ty),
+                cases: map_from_serializable(db, cases),
+            },
+            SerializableExpression::Fix {
+                binder,
+                rec_name,
+                body,
+            } => ExpressionData::Fix {
+                binder: Binder::from_serializable(db, binder),
+                rec_name: intern(rec_name),
+                body: unboxed(body),
+            },
+            SerializableExpression::Ref(ty) => ExpressionData::Ref(unboxed(ty)),
+            SerializableExpression::Deref(value) => ExpressionData::Deref(unboxed(value)),
+            SerializableExpression::Loan {
+                local,
+                loan_as,
+                with,
+                body,
+            } => ExpressionData::Loan {
+                local: *local,
+                loan_as: intern(loan_as),
+                with: intern(with),
+                body: unboxed(body),
+            },
+            SerializableExpression::Take {
+                local,
+                proofs,
+                body,
+            } => ExpressionData::Take {
+                local: *local,
+                proofs: proofs
+                    .iter()
+                    .map(|(index, proof)| (*index, unboxed(proof)))
+                    .collect::<Vec<_>>()
+                    .into(),
+                body: unboxed(body),
+            },
+            SerializableExpression::In { reference, target } => ExpressionData::In {
+                reference: unboxed(reference),
+                target: unboxed(target),
+            },
+            SerializableExpression::LocalConstant { id, structure } => {
+                ExpressionData::LocalConstant(LocalConstant {
+                    id: *id,
+                    structure: BinderStructure::from_serializable(db, structure),
+                })
+            }
+            SerializableExpression::Hole { id, ty } => ExpressionData::Hole(Hole {
+                id: *id,
+                ty: unboxed(ty),
+            }),
+        };
+        Expression::new(db, data)
+    }
+}
+
+impl BoundVariable {
+    pub fn to_serializable(self, db: &dyn Db) -> SerializableBoundVariable {
+        SerializableBoundVariable {
+            name: self.name.text(db).clone(),
+            ty: Box::new(self.ty.to_serializable(db)),
+            usage: self.usage,
+        }
+    }
+
+    pub fn from_serializable(db: &dyn Db, bound: &SerializableBoundVariable) -> Self {
+        Self {
+            name: Str::new(db, bound.name.clone()),
+            ty: Expression::from_serializable(db, &bound.ty),
+            usage: bound.usage,
+        }
+    }
+}
+
+impl BinderStructure {
+    pub fn to_serializable(self, db: &dyn Db) -> SerializableBinderStructure {
+        SerializableBinderStructure {
+            bound: self.bound.to_serializable(db),
+            argument_style: self.argument_style,
+            invocation_style: self.invocation_style,
+        }
+    }
+
+    pub fn from_serializable(db: &dyn Db, structure: &SerializableBinderStructure) -> Self {
+        Self {
+            bound: BoundVariable::from_serializable(db, &structure.bound),
+            argument_style: structure.argument_style,
+            invocation_style: structure.invocation_style,
+        }
+    }
+}
+
+impl Binder {
+    pub fn to_serializable(self, db: &dyn Db) -> SerializableBinder {
+        SerializableBinder {
+            structure: self.structure.to_serializable(db),
+            body: Box::new(self.body.to_serializable(db)),
+        }
+    }
+
+    pub fn from_serializable(db: &dyn Db, binder: &SerializableBinder) -> Self {
+        Self {
+            structure: BinderStructure::from_serializable(db, &binder.structure),
+            body: Expression::from_serializable(db, &binder.body),
+        }
+    }
+}
+
+impl Universe {
+    pub fn to_serializable(&self, db: &dyn Db) -> SerializableUniverse {
+        let boxed = |universe: &Universe| Box::new(universe.to_serializable(db));
+        match self {
+            Universe::Const(level) => SerializableUniverse::Const(*level),
+            Universe::Var(var) => SerializableUniverse::Var(var.0.text(db).clone()),
+            Universe::Max(left, right) => SerializableUniverse::Max(boxed(left), boxed(right)),
+            Universe::IMax(left, right) => SerializableUniverse::IMax(boxed(left), boxed(right)),
+            Universe::Succ(inner) => SerializableUniverse::Succ(boxed(inner)),
+        }
+    }
+
+    pub fn from_serializable(db: &dyn Db, universe: &SerializableUniverse) -> Self {
+        let boxed =
+            |universe: &SerializableUniverse| Box::new(Universe::from_serializable(db, universe));
+        match universe {
+            SerializableUniverse::Const(level) => Universe::Const(*level),
+            SerializableUniverse::Var(name) => {
+                Universe::Var(UniverseVar(Str::new(db, name.clone())))
+            }
+            SerializableUniverse::Max(left, right) => Universe::Max(boxed(left), boxed(right)),
+            SerializableUniverse::IMax(left, right) => Universe::IMax(boxed(left), boxed(right)),
+            SerializableUniverse::Succ(inner) => Universe::Succ(boxed(inner)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::test_db::{local_constant, TestDatabase};
+
+    proptest! {
+        #[test]
+        fn serialized_expressions_deserialize_to_themselves(shape in arbitrary_expression()) {
+            let db = TestDatabase::default();
+            let expr = shape.build(&db);
+            prop_assert_eq!(Expression::from_serializable(&db, &expr.to_serializable(&db)), expr);
+        }
+    }
+
+    #[test]
+    fn serialized_local_constants_keep_their_ids() {
+        let db = TestDatabase::default();
+        let u = UniverseVar(Str::new(&db, "u".to_owned()));
+        let inst = Expression::new_inst_with_universes(
+            &db,
+            Path::parse(&db, "a::b").unwrap(),
+            vec![Universe::Var(u).succ()],
+        );
+        let expr = Expression::new_apply(
+            &db,
+            inst,
+            Expression::new_local_constant(&db, local_constant(&db, 7, "x")),
+        );
+        assert_eq!(
+            Expression::from_serializable(&db, &expr.to_serializable(&db)),
+            expr
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Universe levels, which may contain variables.
+
+use files::Str;
+
+use crate::Db;
+
+/// A variable standing for an arbitrary universe level, such as `u` in `Sort u`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UniverseVar(pub Str);
+
+/// A universe level.
+/// The type of `Sort u` is `Sort (u + 1)`.
+///
+/// `Sort 0` is impredicative, so the universe of a pi type is given by [`Universe::IMax`].
+/// Universes are compared up to the equivalence given by [`Universe::equivalent`],
+/// rather than syntactically.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Universe {
+    /// A constant universe level.
+    Const(u32),
+    /// A universe level variable.
+    Var(UniverseVar),
+    /// The larger of two universe levels.
+    Max(Box<Universe>, Box<Universe>),
+    /// The "impredicative maximum" of two universe levels.
+    /// This is zero if the right hand side is zero, and is the maximum of the two levels otherwise.
+    IMax(Box<Universe>, Box<Universe>),
+    /// The universe level one larger than the given level.
+    Succ(Box<Universe>),
+}
+
+impl Universe {
+    /// The universe level of propositions.
+    pub fn zero() -> Self {
+        Self::Const(0)
+    }
+
+    /// Returns the universe level one larger than this one, simplifying constants where possible.
+    pub fn succ(self) -> Self {
+        match self {
+            Self::Const(level) => match level.checked_add(1) {
+                Some(level) => Self::Const(level),
+                None => Self::Succ(Box::new(Self::Const(level))),
+            },
+            universe => Self::Succ(Box::new(universe)),
+        }
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        Self::Max(Box::new(self), Box::new(other))
+    }
+
+    pub fn imax(self, other: Self) -> Self {
+        Self::IMax(Box::new(self), Box::new(other))
+    }
+
+    /// Returns true if this universe level is nonzero for every assignment of its variables.
+    pub fn is_never_zero(&self) -> bool {
+        match self {
+            Self::Const(level) => *level > 0,
+            Self::Var(_) => false,
+            Self::Max(left, right) => left.is_never_zero() || right.is_never_zero(),
+            Self::IMax(_, right) => right.is_never_zero(),
+            Self::Succ(_) => true,
+        }
+    }
+
+    /// If this universe level has no variables, return its value.
+    pub fn as_const(&self) -> Option<u32> {
+        match self.normalise() {
+            Self::Const(level) => Some(level),
+            _ => None,
+        }
+    }
+
+    /// Replaces every occurrence of the given variable with a replacement level.
+    #[must_use]
+    pub fn instantiate_var(&self, var: UniverseVar, replacement: &Self) -> Self {
+        match self {
+            Self::Const(_) => self.clone(),
+            Self::Var(inner) => {
+                if *inner == var {
+                    replacement.clone()
+                } else {
+                    self.clone()
+                }
+            }
+            Self::Max(left, right) => left
+                .instantiate_var(var, replacement)
+                .max(right.instantiate_var(var, replacement)),
+            Self::IMax(left, right) => left
+                .instantiate_var(var, replacement)
+                .imax(right.instantiate_var(var, replacement)),
+            Self::Succ(inner) => Self::Succ(Box::new(inner.instantiate_var(var, replacement))),
+        }
+    }
+
+    /// Simultaneously replaces each variable in `vars` with the level at the same position in `replacements`.
+    /// Variables that do not appear in `vars`, or have no corresponding replacement, are left unchanged.
+    #[must_use]
+    pub fn instantiate_vars(&self, vars: &[UniverseVar], replacements: &[Self]) -> Self {
+        match self {
+            Self::Const(_) => self.clone(),
+            Self::Var(inner) => {
+                match vars.iter().zip(replacements).find(|(var, _)| *var == inner) {
+                    Some((_, replacement)) => replacement.clone(),
+                    None => self.clone(),
+                }
+            }
+            Self::Max(left, right) => left
+                .instantiate_vars(vars, replacements)
+                .max(right.instantiate_vars(vars, replacements)),
+            Self::IMax(left, right) => left
+                .instantiate_vars(vars, replacements)
+                .imax(right.instantiate_vars(vars, replacements)),
+            Self::Succ(inner) => Self::Succ(Box::new(inner.instantiate_vars(vars, replacements))),
+        }
+    }
+
+    /// Returns the first variable in this level that is not one of the `bound` variables, if there is one.
+    pub fn free_variable(&self, bound: &[UniverseVar]) -> Option<UniverseVar> {
+        match self {
+            Self::Const(_) => None,
+            Self::Var(var) => (!bound.contains(var)).then_some(*var),
+            Self::Max(left, right) | Self::IMax(left, right) => left
+                .free_variable(bound)
+                .or_else(|| right.free_variable(bound)),
+            Self::Succ(inner) => inner.free_variable(bound),
+        }
+    }
+
+    /// Puts this universe level into a normal form.
+    /// A normalised level is a [`Universe::Max`] of terms of the form `n`, `u + n`, and `(imax a u) + n`,
+    /// where each term appears only once, and a constant term only appears if no other term is at least as large.
+    #[must_use]
+    pub fn normalise(&self) -> Self {
+        let terms = self.terms();
+        terms
+            .into_iter()
+            .map(|(base, offset)| match base {
+                Some(base) => (0..offset).fold(base, |level, _| Self::Succ(Box::new(level))),
+                None => Self::Const(offset),
+            })
+            .reduce(Self::max)
+            .unwrap_or_else(Self::zero)
+    }
+
+    /// Returns true if the two universe levels are equal for every assignment of their variables.
+    pub fn equivalent(&self, other: &Self) -> bool {
+        let left = self.terms();
+        let right = other.terms();
+        if left.len() == right.len() && left.iter().all(|term| right.contains(term)) {
+            return true;
+        }
+
+        // The normal form does not simplify `imax a u` where `u` is a variable.
+        // We split into the cases `u = 0` and `u > 0`, in each of which the `imax` can be simplified.
+        match self.imax_variable().or_else(|| other.imax_variable()) {
+            Some(var) => [Self::zero(), Self::Var(var).succ()]
+                .iter()
+                .all(|replacement| {
+                    self.instantiate_var(var, replacement)
+                        .equivalent(&other.instantiate_var(var, replacement))
+                }),
+            None => false,
+        }
+    }
+
+    /// Computes the normal form of this universe level as a list of terms.
+    /// Each term is a base, or [`None`] for the constant term, together with an offset.
+    /// The bases are normalised, and are either variables or `imax` levels.
+    fn terms(&self) -> Vec<(Option<Self>, u32)> {
+        let mut terms = Vec::new();
+        self.collect_terms(0, &mut terms);
+        // A constant term is redundant if another term is at least as large.
+        if let Some(position) = terms.iter().position(|(base, _)| base.is_none()) {
+            let constant = terms[position].1;
+            if constant == 0
+                || terms
+                    .iter()
+                    .any(|(base, offset)| base.is_some() && *offset >= constant)
+            {
+                terms.remove(position);
+            }
+        }
+        terms
+    }
+
+    fn collect_terms(&self, offset: u32, terms: &mut Vec<(Option<Self>, u32)>) {
+        match self {
+            Self::Const(level) => add_term(terms, None, level.saturating_add(offset)),
+            Self::Var(_) => add_term(terms, Some(self.clone()), offset),
+            Self::Max(left, right) => {
+                left.collect_terms(offset, terms);
+                right.collect_terms(offset, terms);
+            }
+            Self::IMax(left, right) => {
+                let right = right.normalise();
+                if right.is_never_zero() {
+                    left.collect_terms(offset, terms);
+                    right.collect_terms(offset, terms);
+                } else if right == Self::zero() {
+                    add_term(terms, None, offset);
+                } else {
+                    add_term(
+                        terms,
+                        Some(Self::IMax(Box::new(left.normalise()), Box::new(right))),
+                        offset,
+                    );
+                }
+            }
+            Self::Succ(inner) => inner.collect_terms(offset.saturating_add(1), terms),
+        }
+    }
+
+    /// Returns a variable `u` such that `imax a u` appears in the normal form of this level.
+    fn imax_variable(&self) -> Option<UniverseVar> {
+        self.terms().into_iter().find_map(|(base, _)| match base {
+            Some(Self::IMax(_, right)) => right.free_variable(&[]),
+            _ => None,
+        })
+    }
+
+    /// Displays this universe level as Feather code.
+    pub fn display(&self, db: &dyn Db) -> String {
+        match self {
+            Self::Const(level) => level.to_string(),
+            Self::Var(var) => var.0.text(db).clone(),
+            Self::Max(left, right) => {
+                format!("max {} {}", left.display_atom(db), right.display_atom(db))
+            }
+            Self::IMax(left, right) => {
+                format!("imax {} {}", left.display_atom(db), right.display_atom(db))
+            }
+            Self::Succ(_) => {
+                let (base, offset) = self.split_succ();
+                match base {
+                    Self::Const(level) => level.saturating_add(offset).to_string(),
+                    Self::Var(var) => format!("{}+{offset}", var.0.text(db)),
+                    _ => (0..offset)
+                        .fold(base.display_atom(db), |result, _| format!("succ {result}")),
+                }
+            }
+        }
+    }
+
+    /// Displays this universe level, wrapping it in parentheses if it is not a single token.
+    fn display_atom(&self, db: &dyn Db) -> String {
+        match self.split_succ().0 {
+            Self::Const(_) | Self::Var(_) => self.display(db),
+            _ => format!("({})", self.display(db)),
+        }
+    }
+
+    /// Removes all [`Universe::Succ`] constructors from the outside of this level,
+    /// returning the remaining level and the number of constructors that were removed.
+    fn split_succ(&self) -> (&Self, u32) {
+        let mut offset = 0;
+        let mut base = self;
+        while let Self::Succ(inner) = base {
+            offset += 1;
+            base = inner;
+        }
+        (base, offset)
+    }
+}
+
+/// Adds a term to a list of terms, keeping only the largest offset for each base.
+fn add_term(terms: &mut Vec<(Option<Universe>, u32)>, base: Option<Universe>, offset: u32) {
+    match terms.iter_mut().find(|(other, _)| *other == base) {
+        Some((_, other_offset)) => *other_offset = std::cmp::max(*other_offset, offset),
+        None => terms.push((base, offset)),
+    }
+}
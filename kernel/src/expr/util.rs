@@ -1,6 +1,14 @@
 //! Utility functions on [`Expression`] using [`Expression::find`] and [`Expression::replace`]
 
-use std::{cell::RefCell, cmp::Ordering};
+use std::{
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use files::Str;
+use miette::Diagnostic;
+use thiserror::Error;
 
 use crate::{
     de_bruijn::{DeBruijnIndex, DeBruijnOffset},
@@ -9,6 +17,57 @@ use crate::{
     Db,
 };
 
+/// Replaces the names of all variables bound in the given expression with `_`,
+/// so that two expressions are alpha-equivalent if and only if their alpha normal forms are equal.
+/// The result can be used as a cache key that does not depend on the names of bound variables.
+///
+/// The names of local constants are not changed, since local constants are compared by their ids.
+#[salsa::tracked]
+pub fn alpha_normal_form(db: &dyn Db, expr: Expression) -> Expression {
+    let erased = Str::new(db, "_".to_owned());
+    let normalise_binder = |mut binder: Binder| {
+        binder.structure.bound.name = erased;
+        binder.structure.bound.ty = alpha_normal_form(db, binder.structure.bound.ty);
+        binder.body = alpha_normal_form(db, binder.body);
+        binder
+    };
+    expr.replace(db, &|inner, _offset| match inner.data(db) {
+        ExpressionData::Lambda(binder) => {
+            ReplaceResult::ReplaceWith(Expression::new_lambda(db, normalise_binder(binder)))
+        }
+        ExpressionData::Pi(binder) => {
+            ReplaceResult::ReplaceWith(Expression::new_pi(db, normalise_binder(binder)))
+        }
+        ExpressionData::Let {
+            to_assign, body, ..
+        } => ReplaceResult::ReplaceWith(Expression::new_let(
+            db,
+            erased,
+            alpha_normal_form(db, to_assign),
+            alpha_normal_form(db, body),
+        )),
+        ExpressionData::Fix { binder, body, .. } => {
+            ReplaceResult::ReplaceWith(Expression::new_fix(
+                db,
+                normalise_binder(binder),
+                erased,
+                alpha_normal_form(db, body),
+            ))
+        }
+        ExpressionData::Loan { local, body, .. } => ReplaceResult::ReplaceWith(
+            Expression::new_loan(db, local, erased, erased, alpha_normal_form(db, body)),
+        ),
+        _ => ReplaceResult::Skip,
+    })
+}
+
+/// The error returned by [`Expression::fill_hole_checked`] when a hole appears in its own replacement.
+#[derive(Error, Diagnostic, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[error("cannot fill hole {hole} with an expression that contains it")]
+pub struct OccursError {
+    pub hole: HoleId,
+}
+
 impl Expression {
     /// Returns the first local constant or hole in the given expression.
     #[must_use]
@@ -34,6 +93,21 @@ impl Expression {
         .is_some()
     }
 
+    /// Returns the IDs of all of the holes that appear in `self`, including in the types of other holes.
+    /// Each hole is listed once, in the order that they are first found in a depth first traversal.
+    #[must_use]
+    pub fn unsolved_holes(self, db: &dyn Db) -> Vec<HoleId> {
+        let mut holes = Vec::new();
+        self.for_each_expression(db, |inner, _offset| {
+            if let ExpressionData::Hole(hole) = inner.data(db) {
+                if !holes.contains(&hole.id) {
+                    holes.push(hole.id);
+                }
+            }
+        });
+        holes
+    }
+
     /// Returns true if the local variable given by `local` appears in `self`.
     #[must_use]
     pub fn local_is_bound(self, db: &dyn Db, local: DeBruijnIndex) -> bool {
@@ -47,6 +121,81 @@ impl Expression {
         .is_some()
     }
 
+    /// Returns the de Bruijn indices of the free variables in this expression, relative to `self`.
+    /// The result is sorted and contains no duplicates.
+    #[must_use]
+    pub fn free_variables(self, db: &dyn Db) -> Vec<DeBruijnIndex> {
+        let mut result = Vec::new();
+        self.for_each_expression(db, |inner, offset| {
+            let mut visit = |index: DeBruijnIndex| {
+                if index >= DeBruijnIndex::zero() + offset {
+                    result.push(index - offset);
+                }
+            };
+            // `loan` and `take` expressions refer to local variables directly, not through a sub-expression.
+            match inner.data(db) {
+                ExpressionData::Local(index) | ExpressionData::Loan { local: index, .. } => {
+                    visit(index)
+                }
+                ExpressionData::Take { local, proofs, .. } => {
+                    visit(local);
+                    proofs.keys().for_each(|index| visit(*index));
+                }
+                _ => {}
+            }
+        });
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    /// Returns true if this expression has no free variables.
+    /// This stops traversing the expression as soon as a free variable is found.
+    #[must_use]
+    pub fn is_closed(self, db: &dyn Db) -> bool {
+        self.find(db, &|inner, offset| {
+            let is_free = |index: DeBruijnIndex| index >= DeBruijnIndex::zero() + offset;
+            match inner.data(db) {
+                ExpressionData::Local(index) | ExpressionData::Loan { local: index, .. } => {
+                    is_free(index)
+                }
+                ExpressionData::Take { local, proofs, .. } => {
+                    is_free(local) || proofs.keys().any(|index| is_free(*index))
+                }
+                _ => false,
+            }
+        })
+        .is_none()
+    }
+
+    /// Returns true if the two expressions are equal up to the names of their bound variables.
+    /// See [`alpha_normal_form`].
+    #[must_use]
+    pub fn alpha_eq(self, other: Self, db: &dyn Db) -> bool {
+        self == other || alpha_normal_form(db, self) == alpha_normal_form(db, other)
+    }
+
+    /// A hash of this expression that ignores the names of bound variables,
+    /// so alpha-equivalent expressions have the same hash.
+    /// Like the ids of expressions, this is only stable within a single database.
+    #[must_use]
+    pub fn alpha_hash(self, db: &dyn Db) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        alpha_normal_form(db, self).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// In debug builds, panics if this expression is not closed.
+    /// This can be used to catch errors in de Bruijn index manipulation early.
+    #[track_caller]
+    pub fn debug_assert_closed(self, db: &dyn Db) {
+        debug_assert!(
+            self.is_closed(db),
+            "expected a closed expression, but found free variables {:?}",
+            self.free_variables(db)
+        );
+    }
+
     /// Traverses the expression tree and calls the given function on each expression.
     /// The tree is traversed depth first.
     pub fn for_each_expression(self, db: &dyn Db, func: impl FnMut(Self, DeBruijnOffset)) {
@@ -57,12 +206,31 @@ impl Expression {
         });
     }
 
+    /// The number of nodes in the expression tree, including this expression itself.
+    #[must_use]
+    pub fn size(self, db: &dyn Db) -> usize {
+        let mut size = 0;
+        self.for_each_expression(db, |_inner, _offset| size += 1);
+        size
+    }
+
+    /// The maximum number of binders that any sub-expression is nested under.
+    /// For example, `fun (x: A) -> fun (y: B) -> y` has depth two.
+    #[must_use]
+    pub fn depth(self, db: &dyn Db) -> usize {
+        let mut depth = 0;
+        self.for_each_expression(db, |_inner, offset| {
+            depth = std::cmp::max(depth, offset.value() as usize);
+        });
+        depth
+    }
+
     /// Gets the maximum height of reducible definitions contained inside this expression.
     #[must_use]
     pub fn get_max_height(self, db: &dyn Db) -> DefinitionHeight {
         let mut height = 0;
         self.for_each_expression(db, |inner, _offset| {
-            if let ExpressionData::Inst(path) = inner.data(db) {
+            if let ExpressionData::Inst { path, .. } = inner.data(db) {
                 if let Some(inner_height) = definition_height(db, path) {
                     height = std::cmp::max(height, inner_height);
                 }
@@ -73,31 +241,44 @@ impl Expression {
 
     /// Instantiate the first bound variable with the given substitution.
     /// This will subtract one from all higher de Bruijn indices.
-    /// TODO: n-ary instantiation operation.
     #[must_use]
     pub fn instantiate(self, db: &dyn Db, substitution: Self) -> Self {
+        self.instantiate_many(db, &[substitution])
+    }
+
+    /// Instantiate the first `n` bound variables with the given substitutions in a single traversal,
+    /// where `n` is the number of substitutions.
+    /// The variable with de Bruijn index `i` is replaced with `substitutions[i]`.
+    /// This will subtract `n` from all higher de Bruijn indices.
+    ///
+    /// The substitutions are performed simultaneously, so free variables in one substitution
+    /// are never captured by another.
+    #[must_use]
+    pub fn instantiate_many(self, db: &dyn Db, substitutions: &[Self]) -> Self {
+        if substitutions.is_empty() {
+            return self;
+        }
+        let count = DeBruijnOffset::new(substitutions.len() as u32);
         self.replace(db, &|e, offset| {
             match e.data(db) {
                 ExpressionData::Local(index) => {
-                    match index.cmp(&(DeBruijnIndex::zero() + offset)) {
-                        Ordering::Less => {
-                            // The variable is bound and has index lower than the offset, so we don't change it.
-                            ReplaceResult::Skip
-                        }
-                        Ordering::Equal => {
-                            // The variable is the smallest free de Bruijn index.
-                            // It is exactly the one we need to substitute.
-                            ReplaceResult::ReplaceWith(substitution.lift_free_vars(
+                    if index < DeBruijnIndex::zero() + offset {
+                        // The variable is bound and has index lower than the offset, so we don't change it.
+                        ReplaceResult::Skip
+                    } else if index < DeBruijnIndex::zero() + offset + count {
+                        // The variable is one of the smallest free de Bruijn indices.
+                        // It is exactly one of the ones we need to substitute.
+                        ReplaceResult::ReplaceWith(
+                            substitutions[(index - offset).value() as usize].lift_free_vars(
                                 db,
                                 DeBruijnOffset::zero(),
                                 offset,
-                            ))
-                        }
-                        Ordering::Greater => {
-                            // This de Bruijn index must be decremented, since we just
-                            // instantiated a variable below it.
-                            ReplaceResult::ReplaceWith(Self::new_local(db, index.pred()))
-                        }
+                            ),
+                        )
+                    } else {
+                        // This de Bruijn index must be decremented, since we just
+                        // instantiated the variables below it.
+                        ReplaceResult::ReplaceWith(Self::new_local(db, index - count))
                     }
                 }
                 _ => ReplaceResult::Skip,
@@ -105,6 +286,60 @@ impl Expression {
         })
     }
 
+    /// Simultaneously replaces each universe variable in `vars` with the level at the same position
+    /// in `replacements`, in every `Sort` and `inst` expression inside this expression.
+    #[must_use]
+    pub fn instantiate_universes(
+        self,
+        db: &dyn Db,
+        vars: &[UniverseVar],
+        replacements: &[Universe],
+    ) -> Self {
+        if vars.is_empty() {
+            return self;
+        }
+        self.replace(db, &|e, _offset| match e.data(db) {
+            ExpressionData::Sort(universe) => ReplaceResult::ReplaceWith(Self::new_sort(
+                db,
+                universe.instantiate_vars(vars, replacements),
+            )),
+            ExpressionData::Inst { path, universes } => {
+                ReplaceResult::ReplaceWith(Self::new_inst_with_universes(
+                    db,
+                    path,
+                    universes
+                        .iter()
+                        .map(|universe| universe.instantiate_vars(vars, replacements))
+                        .collect(),
+                ))
+            }
+            _ => ReplaceResult::Skip,
+        })
+    }
+
+    /// Finds the first `Sort` or `inst` expression inside this expression that uses a universe variable
+    /// which is not one of the `bound` variables, returning the expression along with that variable.
+    pub fn free_universe_variable(
+        self,
+        db: &dyn Db,
+        bound: &[UniverseVar],
+    ) -> Option<(Expression, UniverseVar)> {
+        let mut free = None;
+        self.for_each_expression(db, |inner, _offset| {
+            if free.is_none() {
+                free = match inner.data(db) {
+                    ExpressionData::Sort(universe) => universe.free_variable(bound),
+                    ExpressionData::Inst { universes, .. } => universes
+                        .iter()
+                        .find_map(|universe| universe.free_variable(bound)),
+                    _ => None,
+                }
+                .map(|var| (inner, var));
+            }
+        });
+        free
+    }
+
     /// Increase the de Bruijn indices of free variables by a certain offset.
     /// Before the check, we increase the index of each expression by `bias`.
     #[must_use]
@@ -124,10 +359,45 @@ impl Expression {
         })
     }
 
+    /// Eta-expands this function into `fun x => self x`,
+    /// where `x` is bound with the given structure, which must match the parameter of `self`.
+    #[must_use]
+    pub fn eta_expand(self, db: &dyn Db, structure: BinderStructure) -> Self {
+        Self::new_lambda(
+            db,
+            Binder {
+                structure,
+                body: Self::new_apply(
+                    db,
+                    self.lift_free_vars(db, DeBruijnOffset::zero(), DeBruijnOffset::new(1)),
+                    Self::new_local(db, DeBruijnIndex::zero()),
+                ),
+            },
+        )
+    }
+
+    /// If this expression is a lambda abstraction `fun x => f x` where `x` does not occur in `f`,
+    /// returns `f`. This is the inverse of [`Expression::eta_expand`].
+    #[must_use]
+    pub fn eta_reduce(self, db: &dyn Db) -> Option<Self> {
+        if let ExpressionData::Lambda(binder) = self.data(db) {
+            if let ExpressionData::Apply { left, right } = binder.body.data(db) {
+                if right.data(db) == ExpressionData::Local(DeBruijnIndex::zero())
+                    && !left.local_is_bound(db, DeBruijnIndex::zero())
+                {
+                    // Since `x` does not occur in `f`, instantiating it only lowers the other free variables.
+                    return Some(left.instantiate(db, right));
+                }
+            }
+        }
+        None
+    }
+
     /// Create a lambda or pi binder where the parameter is the given local constant.
     /// Invoke this with a closed expression.
     #[must_use]
     pub fn abstract_binder(self, db: &dyn Db, local: LocalConstant) -> Binder {
+        self.debug_assert_closed(db);
         let return_type = self.replace(db, &|e, offset| match e.data(db) {
             ExpressionData::LocalConstant(inner_local) => {
                 if inner_local == local {
@@ -145,6 +415,34 @@ impl Expression {
         }
     }
 
+    /// Replaces the given local constants with bound variables in a single traversal,
+    /// as if the result were the body of one binder for each local.
+    /// The locals are given from the outermost binder to the innermost,
+    /// so the last local becomes de Bruijn index zero.
+    ///
+    /// Wrapping the result in binders for each local, innermost first,
+    /// gives the same expression as calling [`Expression::abstract_binder`] once per local,
+    /// from the last local to the first.
+    /// Invoke this with a closed expression.
+    #[must_use]
+    pub fn abstract_many(self, db: &dyn Db, locals: &[LocalConstant]) -> Self {
+        self.debug_assert_closed(db);
+        self.replace(db, &|e, offset| match e.data(db) {
+            ExpressionData::LocalConstant(inner_local) => {
+                match locals.iter().rposition(|local| *local == inner_local) {
+                    Some(position) => ReplaceResult::ReplaceWith(Self::new_local(
+                        db,
+                        DeBruijnIndex::zero()
+                            + offset
+                            + DeBruijnOffset::new((locals.len() - 1 - position) as u32),
+                    )),
+                    None => ReplaceResult::Skip,
+                }
+            }
+            _ => ReplaceResult::Skip,
+        })
+    }
+
     /// Replaces every instance of the given hole inside this expression with a replacement.
     #[must_use]
     pub fn fill_hole(self, db: &dyn Db, id: HoleId, replacement: Self) -> Self {
@@ -164,20 +462,45 @@ impl Expression {
         })
     }
 
+    /// Like [`Expression::fill_hole`], but first performs an occurs check.
+    /// If the hole appears in the replacement, filling it would create an infinite expression,
+    /// so we return an error instead.
+    pub fn fill_hole_checked(
+        self,
+        db: &dyn Db,
+        id: HoleId,
+        replacement: Self,
+    ) -> Result<Self, OccursError> {
+        if replacement.hole_occurs(db, id) {
+            Err(OccursError { hole: id })
+        } else {
+            Ok(self.fill_hole(db, id, replacement))
+        }
+    }
+
     /// Replace the given local constant with this expression.
     #[must_use]
     pub fn replace_local(self, db: &dyn Db, local: &LocalConstant, replacement: Self) -> Self {
+        self.replace_locals(db, &[(*local, replacement)])
+    }
+
+    /// Replace each of the given local constants with its corresponding expression in a single traversal.
+    ///
+    /// The substitutions are performed simultaneously, so a local constant appearing in one replacement
+    /// is never replaced by another substitution.
+    #[must_use]
+    pub fn replace_locals(self, db: &dyn Db, substitutions: &[(LocalConstant, Self)]) -> Self {
+        if substitutions.is_empty() {
+            return self;
+        }
         self.replace(db, &|e, offset| {
             if let ExpressionData::LocalConstant(inner) = e.data(db) {
-                if inner.id == local.id {
+                match substitutions.iter().find(|(local, _)| local.id == inner.id) {
                     // We should replace this local variable.
-                    ReplaceResult::ReplaceWith(replacement.lift_free_vars(
-                        db,
-                        DeBruijnOffset::zero(),
-                        offset,
-                    ))
-                } else {
-                    ReplaceResult::Skip
+                    Some((_, replacement)) => ReplaceResult::ReplaceWith(
+                        replacement.lift_free_vars(db, DeBruijnOffset::zero(), offset),
+                    ),
+                    None => ReplaceResult::Skip,
                 }
             } else {
                 ReplaceResult::Skip
@@ -185,3 +508,166 @@ impl Expression {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_db::{local_constant, TestDatabase};
+
+    #[test]
+    fn abstract_many_matches_nested_abstract_binder() {
+        let db = TestDatabase::default();
+        let (a, b) = (local_constant(&db, 0, "a"), local_constant(&db, 1, "b"));
+        let expr = Expression::new_apply(
+            &db,
+            Expression::new_local_constant(&db, a),
+            Expression::new_local_constant(&db, b),
+        );
+
+        let many = expr.abstract_many(&db, &[a, b]);
+        assert_eq!(
+            many,
+            Expression::new_apply(
+                &db,
+                Expression::new_local(&db, DeBruijnIndex::new(1)),
+                Expression::new_local(&db, DeBruijnIndex::zero()),
+            )
+        );
+
+        let inner = Expression::new_pi(&db, expr.abstract_binder(&db, b));
+        let nested = Expression::new_pi(&db, inner.abstract_binder(&db, a));
+        let wrapped = Expression::new_pi(
+            &db,
+            Binder {
+                structure: a.structure,
+                body: Expression::new_pi(
+                    &db,
+                    Binder {
+                        structure: b.structure,
+                        body: many,
+                    },
+                ),
+            },
+        );
+        assert_eq!(nested, wrapped);
+    }
+
+    #[test]
+    fn replace_locals_is_simultaneous() {
+        let db = TestDatabase::default();
+        let (a, b) = (local_constant(&db, 0, "a"), local_constant(&db, 1, "b"));
+        let apply = |left, right| {
+            Expression::new_apply(
+                &db,
+                Expression::new_local_constant(&db, left),
+                Expression::new_local_constant(&db, right),
+            )
+        };
+
+        let swapped = apply(a, b).replace_locals(
+            &db,
+            &[
+                (a, Expression::new_local_constant(&db, b)),
+                (b, Expression::new_local_constant(&db, a)),
+            ],
+        );
+        assert_eq!(swapped, apply(b, a));
+    }
+
+    #[test]
+    fn size_and_depth() {
+        let db = TestDatabase::default();
+        let a = local_constant(&db, 0, "a");
+        // The local constant and its type `Sort 0`.
+        let local = Expression::new_local_constant(&db, a);
+        assert_eq!((local.size(&db), local.depth(&db)), (2, 0));
+
+        let pi = Expression::new_pi(
+            &db,
+            Binder {
+                structure: a.structure,
+                body: Expression::new_local(&db, DeBruijnIndex::zero()),
+            },
+        );
+        assert_eq!((pi.size(&db), pi.depth(&db)), (3, 1));
+    }
+
+    #[test]
+    fn deep_applications_do_not_overflow() {
+        let db = TestDatabase::default();
+        let (a, b) = (local_constant(&db, 0, "a"), local_constant(&db, 1, "b"));
+        let depth = 100_000;
+        let build = |local| {
+            let local = Expression::new_local_constant(&db, local);
+            (0..depth).fold(local, |expr, _| Expression::new_apply(&db, expr, local))
+        };
+
+        let expr = build(a);
+        // Each application contributes itself and a local constant with its type.
+        assert_eq!(expr.size(&db), 2 + 3 * depth);
+        assert_eq!(
+            expr.replace_local(&db, &a, Expression::new_local_constant(&db, b)),
+            build(b)
+        );
+    }
+
+    #[test]
+    fn eta_expansion() {
+        let db = TestDatabase::default();
+        let x = local_constant(&db, 0, "x");
+        // A free variable `f`, which must be lifted under the new binder.
+        let f = Expression::new_local(&db, DeBruijnIndex::zero());
+        let expanded = f.eta_expand(&db, x.structure);
+        assert_eq!(
+            expanded,
+            Expression::new_lambda(
+                &db,
+                Binder {
+                    structure: x.structure,
+                    body: Expression::new_apply(
+                        &db,
+                        Expression::new_local(&db, DeBruijnIndex::new(1)),
+                        Expression::new_local(&db, DeBruijnIndex::zero()),
+                    ),
+                },
+            )
+        );
+        assert_eq!(expanded.eta_reduce(&db), Some(f));
+
+        // `fun x => x x` cannot be eta-reduced.
+        let local = Expression::new_local(&db, DeBruijnIndex::zero());
+        let self_application = Expression::new_lambda(
+            &db,
+            Binder {
+                structure: x.structure,
+                body: Expression::new_apply(&db, local, local),
+            },
+        );
+        assert_eq!(self_application.eta_reduce(&db), None);
+    }
+
+    #[test]
+    fn alpha_equivalence() {
+        let db = TestDatabase::default();
+        let identity = |name: &str| {
+            let local = local_constant(&db, 0, name);
+            Expression::new_lambda(
+                &db,
+                Expression::new_local_constant(&db, local).abstract_binder(&db, local),
+            )
+        };
+        let (x, y) = (identity("x"), identity("y"));
+        assert_ne!(x, y);
+        assert!(x.alpha_eq(y, &db));
+        assert_eq!(x.alpha_hash(&db), y.alpha_hash(&db));
+
+        let constant = Expression::new_lambda(
+            &db,
+            Binder {
+                structure: local_constant(&db, 0, "x").structure,
+                body: Expression::new_sort(&db, Universe::zero()),
+            },
+        );
+        assert!(!x.alpha_eq(constant, &db));
+    }
+}
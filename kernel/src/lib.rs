@@ -6,10 +6,18 @@ pub mod expr;
 pub mod type_check;
 pub mod vec_map;
 
+#[cfg(test)]
+mod test_db;
+
+use std::collections::HashSet;
+
 use definition::Definition;
-use diagnostic::DynDr;
-use files::Path;
-use type_check::{CertifiedDefinition, DefinitionOrigin};
+use diagnostic::{Dr, DynDr, DynamicDiagnostic};
+use expr::{Expression, ExpressionData};
+use files::{Path, Source, SourceSpan};
+use type_check::{
+    CertifiedDefinition, DefinedHere, DefinitionOrigin, InferenceError, LocatedError, TypeError,
+};
 
 pub trait Db: files::Db + salsa::DbWithJar<Jar> {
     fn format_expression(&self, expr: expr::Expression) -> String;
@@ -18,6 +26,15 @@ pub trait Db: files::Db + salsa::DbWithJar<Jar> {
     /// return the parsed and elaborated definition.
     /// This definition will not have been type checked.
     fn get_definition_impl(&self, path: Path) -> DynDr<Definition>;
+
+    /// Returns the span of source code where the given expression was written in the definition with the given path,
+    /// or [`None`] if it was not written there, for example because it was produced by the type checker.
+    fn expression_span(&self, path: Path, expr: expr::Expression) -> Option<SourceSpan>;
+
+    /// Returns true if the source file of the module with the given path exists.
+    /// This is used to distinguish a reference to a module that does not exist
+    /// from a reference to an unknown definition in a module that does.
+    fn module_exists(&self, module: Path) -> bool;
 }
 
 /// Given a fully qualified path of a definition in a either a feather or a quill file,
@@ -40,10 +57,130 @@ pub fn get_definition(db: &dyn Db, path: Path) -> DynDr<Definition> {
 #[salsa::tracked(return_ref)]
 pub fn certify_definition(db: &dyn Db, path: Path) -> DynDr<CertifiedDefinition> {
     get_definition(db, path).clone().bind(|def| {
-        type_check::certify_definition(db, path, &def, DefinitionOrigin::Feather).to_dynamic()
+        // Certifying a definition in a cycle would require its own certification,
+        // so we report the cycle before salsa can detect it.
+        if let Some(cycle) = find_cycle(db, path) {
+            return Dr::new_err(DynamicDiagnostic::new(TypeError::CyclicDefinition {
+                cycle: cycle
+                    .iter()
+                    .map(|path| path.display(db))
+                    .collect::<Vec<_>>()
+                    .join(" -> "),
+            }))
+            .to_dynamic();
+        }
+        type_check::certify_definition(db, path, &def, DefinitionOrigin::Feather)
+            .to_dynamic()
+            .map_err(|err| locate_error(db, path, err))
+            .map_errs(|err| locate_error(db, path, err))
     })
 }
 
+/// If the given error is an [`InferenceError`], reports it at the innermost of its expressions
+/// that was written in the source code of the definition with the given path.
+/// Since expressions are interned, if that expression was written more than once in the definition,
+/// the error is reported at its first occurrence.
+///
+/// The definitions in other source files that the expression refers to are shown alongside the error.
+fn locate_error(db: &dyn Db, path: Path, err: DynamicDiagnostic) -> DynamicDiagnostic {
+    match err.downcast_ref::<InferenceError>() {
+        Some(inference) => inference
+            .exprs
+            .iter()
+            .find_map(|expr| db.expression_span(path, *expr).map(|span| (*expr, span)))
+            .map_or_else(
+                || inference.error.clone(),
+                |(expr, span)| {
+                    DynamicDiagnostic::new(LocatedError {
+                        src: span.source.data(db),
+                        span: span.span,
+                        error: inference.error.clone(),
+                        related: definition_sites(db, expr, span.source),
+                    })
+                },
+            ),
+        None => err,
+    }
+}
+
+/// Returns a [`DefinedHere`] diagnostic for each definition that the given expression refers to,
+/// if that definition was written in a source file other than `source`.
+fn definition_sites(db: &dyn Db, expr: Expression, source: Source) -> Vec<DynamicDiagnostic> {
+    let mut paths = Vec::new();
+    expr.for_each_expression(db, |inner, _offset| {
+        if let ExpressionData::Inst { path, .. } = inner.data(db) {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    });
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let site = get_definition(db, path).value()?.name.provenance?;
+            (site.source != source).then(|| {
+                DynamicDiagnostic::new(DefinedHere {
+                    src: site.source.data(db),
+                    span: site.span,
+                    path: path.display(db),
+                })
+            })
+        })
+        .collect()
+}
+
+/// The paths of the definitions that the definition with the given name refers to,
+/// in its type or its body, in the order they first appear.
+#[salsa::tracked(return_ref)]
+pub fn definition_references(db: &dyn Db, path: Path) -> Vec<Path> {
+    let mut references = Vec::new();
+    if let Some(def) = get_definition(db, path).value() {
+        for expr in std::iter::once(def.ty).chain(def.body) {
+            expr.for_each_expression(db, |inner, _offset| {
+                if let ExpressionData::Inst { path, .. } = inner.data(db) {
+                    if !references.contains(&path) {
+                        references.push(path);
+                    }
+                }
+            });
+        }
+    }
+    references
+}
+
+/// If the definition with the given name refers to itself, directly or through other definitions,
+/// returns the paths in one such cycle, starting and ending with `path`.
+///
+/// Recursion using `fix` is internal to a definition's body and does not refer to the definition by name,
+/// so it is not a cycle.
+pub fn find_cycle(db: &dyn Db, path: Path) -> Option<Vec<Path>> {
+    fn search(
+        db: &dyn Db,
+        target: Path,
+        current: Path,
+        visited: &mut HashSet<Path>,
+        stack: &mut Vec<Path>,
+    ) -> bool {
+        for &next in definition_references(db, current) {
+            if next == target {
+                stack.push(next);
+                return true;
+            }
+            if visited.insert(next) {
+                stack.push(next);
+                if search(db, target, next, visited, stack) {
+                    return true;
+                }
+                stack.pop();
+            }
+        }
+        false
+    }
+
+    let mut stack = vec![path];
+    search(db, path, path, &mut HashSet::new(), &mut stack).then_some(stack)
+}
+
 /// Type checks the definition with the given name, or retrieves it from the database if it was already type checked.
 /// This function returns a [`CertifiedDefinition`], a definition that has been verified by the type checker.
 /// This function will discard any diagnostic messages produced by type checking the definition.
@@ -55,7 +192,10 @@ pub fn get_certified_definition(db: &dyn Db, path: Path) -> Option<CertifiedDefi
 #[salsa::jar(db = Db)]
 pub struct Jar(
     expr::Expression,
+    expr::alpha_normal_form,
     get_definition,
+    definition_references,
     certify_definition,
     get_certified_definition,
+    type_check::cached_whnf,
 );
@@ -0,0 +1,73 @@
+//! An in-memory database for the kernel's unit tests.
+
+use std::path::PathBuf;
+
+use diagnostic::{DynDr, DynamicDiagnostic};
+use files::{InputFile, Path, SourceSpan, Str};
+
+use crate::{
+    definition::Definition,
+    expr::{
+        ArgumentStyle, BinderStructure, BoundVariable, DisplayOptions, Expression, InvocationStyle,
+        LocalConstant, LocalConstantId, Universe, Usage,
+    },
+    type_check::TypeError,
+    Db,
+};
+
+/// A database in which every file is missing, so no module exists and no definition can be found.
+#[salsa::db(files::Jar, crate::Jar)]
+#[derive(Default)]
+pub(crate) struct TestDatabase {
+    storage: salsa::Storage<Self>,
+}
+
+impl std::fmt::Debug for TestDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<db>")
+    }
+}
+
+impl salsa::Database for TestDatabase {}
+
+impl files::Db for TestDatabase {
+    fn input_file(&self, path: PathBuf) -> std::io::Result<InputFile> {
+        Ok(InputFile::new(self, path, None))
+    }
+}
+
+impl Db for TestDatabase {
+    fn format_expression(&self, expr: Expression) -> String {
+        expr.display(self, &DisplayOptions::default())
+    }
+
+    fn get_definition_impl(&self, path: Path) -> DynDr<Definition> {
+        DynDr::new_err(DynamicDiagnostic::new(TypeError::UnknownDefinition {
+            path: path.display(self),
+        }))
+    }
+
+    fn expression_span(&self, _path: Path, _expr: Expression) -> Option<SourceSpan> {
+        None
+    }
+
+    fn module_exists(&self, _module: Path) -> bool {
+        false
+    }
+}
+
+/// A local constant of type `Sort 0`.
+pub(crate) fn local_constant(db: &TestDatabase, id: u32, name: &str) -> LocalConstant {
+    LocalConstant {
+        id: LocalConstantId(id),
+        structure: BinderStructure {
+            bound: BoundVariable {
+                name: Str::new(db, name.to_owned()),
+                ty: Expression::new_sort(db, Universe::zero()),
+                usage: Usage::Present,
+            },
+            argument_style: ArgumentStyle::Explicit,
+            invocation_style: InvocationStyle::Once,
+        },
+    }
+}
@@ -1 +1,455 @@
 //! Checks whether two expressions are equal "by definition".
+//!
+//! The algorithm is modelled on Lean's kernel:
+//! <https://github.com/leanprover/lean4/blob/master/src/kernel/type_checker.cpp>
+
+use std::cmp::Ordering;
+
+use diagnostic::Dr;
+
+use crate::{
+    de_bruijn::{DeBruijnIndex, DeBruijnOffset},
+    expr::*,
+    Db,
+};
+
+use super::LocalContext;
+
+impl Expression {
+    /// Returns true if the two expressions are syntactically equal.
+    /// This is interned identity, including binder names; see [`Expression::alpha_eq`] to ignore them.
+    /// Because expressions are interned, this is a cheap comparison of ids,
+    /// and may be used as a fast path before checking for definitional equality.
+    pub fn syntactic_eq(self, other: Expression) -> bool {
+        self == other
+    }
+
+    /// Returns true if the two expressions are definitionally equal in the given local context.
+    ///
+    /// Alpha-equivalent expressions are equal.
+    /// Otherwise, both expressions are reduced to weak head normal form without unfolding definitions.
+    /// Definitions are then unfolded lazily, starting with the one with the greatest height,
+    /// until the expressions become syntactically equal or neither side can be unfolded further.
+    /// The results are then compared structurally.
+    /// If this fails, we try eta-expanding lambda abstractions,
+    /// and finally we check whether both expressions are proofs of the same proposition.
+    ///
+    /// This fails if reducing either expression runs out of fuel.
+    pub fn definitionally_equal(
+        self,
+        other: Expression,
+        db: &dyn Db,
+        ctx: &LocalContext,
+    ) -> Dr<bool> {
+        if self.alpha_eq(other, db) {
+            return Dr::new(true);
+        }
+
+        self.whnf_core(db)
+            .zip(other.whnf_core(db))
+            .bind(|(left, right)| {
+                if left.syntactic_eq(right) {
+                    Dr::new(true)
+                } else {
+                    lazy_delta_reduction(db, left, right)
+                        .bind(|(left, right)| compare_reduced(db, ctx, left, right))
+                }
+            })
+    }
+}
+
+/// Compares two expressions that have been reduced by [`lazy_delta_reduction`].
+fn compare_reduced(
+    db: &dyn Db,
+    ctx: &LocalContext,
+    left: Expression,
+    right: Expression,
+) -> Dr<bool> {
+    if left.syntactic_eq(right) {
+        return Dr::new(true);
+    }
+    and_not(structurally_equal(db, ctx, left, right), || {
+        and_not(eta_equal(db, ctx, left, right), || {
+            proof_irrelevant_equal(db, ctx, left, right)
+        })
+    })
+}
+
+/// Returns `right` if `left` is true, and false otherwise.
+/// The computation of `right` is skipped if it is not needed.
+fn and_then(left: Dr<bool>, right: impl FnOnce() -> Dr<bool>) -> Dr<bool> {
+    left.bind(|left| if left { right() } else { Dr::new(false) })
+}
+
+/// Returns true if `left` is true, and `right` otherwise.
+/// The computation of `right` is skipped if it is not needed.
+fn and_not(left: Dr<bool>, right: impl FnOnce() -> Dr<bool>) -> Dr<bool> {
+    left.bind(|left| if left { Dr::new(true) } else { right() })
+}
+
+/// Unfolds definitions at the heads of the two expressions until they are syntactically equal,
+/// or until neither can be unfolded any more.
+/// At each step, we unfold the definition with the greatest height,
+/// since it may unfold to an expression involving the other definition.
+/// This fails if reducing an unfolded definition runs out of fuel.
+fn lazy_delta_reduction(
+    db: &dyn Db,
+    mut left: Expression,
+    mut right: Expression,
+) -> Dr<(Expression, Expression)> {
+    while !left.syntactic_eq(right) {
+        db.unwind_if_cancelled();
+        let (unfold_left, unfold_right) = match (
+            left.head_definition_height(db),
+            right.head_definition_height(db),
+        ) {
+            (None, None) => break,
+            (Some(_), None) => (true, false),
+            (None, Some(_)) => (false, true),
+            (Some(left_height), Some(right_height)) => match left_height.cmp(&right_height) {
+                Ordering::Less => (false, true),
+                Ordering::Equal => (true, true),
+                Ordering::Greater => (true, false),
+            },
+        };
+
+        if unfold_left {
+            match left
+                .unfold_definition(db)
+                .map(|unfolded| unfolded.whnf_core(db))
+            {
+                Some(unfolded) => match unfolded.value() {
+                    Some(unfolded) => left = *unfolded,
+                    None => return unfolded.map(|_| (left, right)),
+                },
+                None => break,
+            }
+        }
+        if unfold_right {
+            match right
+                .unfold_definition(db)
+                .map(|unfolded| unfolded.whnf_core(db))
+            {
+                Some(unfolded) => match unfolded.value() {
+                    Some(unfolded) => right = *unfolded,
+                    None => return unfolded.map(|_| (left, right)),
+                },
+                None => break,
+            }
+        }
+    }
+    Dr::new((left, right))
+}
+
+/// Compares two expressions in weak head normal form by comparing their heads,
+/// and then checking that their corresponding sub-expressions are definitionally equal.
+fn structurally_equal(
+    db: &dyn Db,
+    ctx: &LocalContext,
+    left: Expression,
+    right: Expression,
+) -> Dr<bool> {
+    match (left.data(db), right.data(db)) {
+        (ExpressionData::Local(left), ExpressionData::Local(right)) => Dr::new(left == right),
+        (
+            ExpressionData::Apply {
+                left: left_fn,
+                right: left_arg,
+            },
+            ExpressionData::Apply {
+                left: right_fn,
+                right: right_arg,
+            },
+        ) => and_then(left_fn.definitionally_equal(right_fn, db, ctx), || {
+            left_arg.definitionally_equal(right_arg, db, ctx)
+        }),
+        (ExpressionData::Lambda(left), ExpressionData::Lambda(right))
+        | (ExpressionData::Pi(left), ExpressionData::Pi(right)) => {
+            binders_equal(db, ctx, left, right)
+        }
+        (ExpressionData::Sort(left), ExpressionData::Sort(right)) => {
+            Dr::new(left.equivalent(&right))
+        }
+        (
+            ExpressionData::Inst {
+                path: left_path,
+                universes: left_universes,
+            },
+            ExpressionData::Inst {
+                path: right_path,
+                universes: right_universes,
+            },
+        ) => Dr::new(
+            left_path == right_path
+                && left_universes.len() == right_universes.len()
+                && left_universes
+                    .iter()
+                    .zip(&right_universes)
+                    .all(|(left, right)| left.equivalent(right)),
+        ),
+        (
+            ExpressionData::Intro {
+                path: left_path,
+                parameters: left_parameters,
+                variant: left_variant,
+                fields: left_fields,
+            },
+            ExpressionData::Intro {
+                path: right_path,
+                parameters: right_parameters,
+                variant: right_variant,
+                fields: right_fields,
+            },
+        ) => {
+            if left_path == right_path
+                && left_variant == right_variant
+                && left_parameters.len() == right_parameters.len()
+                && left_fields.len() == right_fields.len()
+                && left_fields
+                    .iter()
+                    .zip(right_fields.iter())
+                    .all(|((left, _), (right, _))| left == right)
+            {
+                all_equal(
+                    db,
+                    ctx,
+                    left_parameters.into_iter().zip(right_parameters).chain(
+                        left_fields
+                            .into_iter()
+                            .zip(right_fields)
+                            .map(|((_, left), (_, right))| (left, right)),
+                    ),
+                )
+            } else {
+                Dr::new(false)
+            }
+        }
+        (
+            ExpressionData::Match {
+                subject: left_subject,
+                return_ty: left_return_ty,
+                cases: left_cases,
+            },
+            ExpressionData::Match {
+                subject: right_subject,
+                return_ty: right_return_ty,
+                cases: right_cases,
+            },
+        ) => {
+            if left_cases.len() == right_cases.len()
+                && left_cases
+                    .iter()
+                    .zip(right_cases.iter())
+                    .all(|((left, _), (right, _))| left == right)
+            {
+                all_equal(
+                    db,
+                    ctx,
+                    [
+                        (left_subject, right_subject),
+                        (left_return_ty, right_return_ty),
+                    ]
+                    .into_iter()
+                    .chain(
+                        left_cases
+                            .into_iter()
+                            .zip(right_cases)
+                            .map(|((_, left), (_, right))| (left, right)),
+                    ),
+                )
+            } else {
+                Dr::new(false)
+            }
+        }
+        (
+            ExpressionData::Fix {
+                binder: left_binder,
+                rec_name,
+                body: left_body,
+            },
+            ExpressionData::Fix {
+                binder: right_binder,
+                body: right_body,
+                ..
+            },
+        ) => and_then(binders_equal(db, ctx, left_binder, right_binder), || {
+            left_body.definitionally_equal(right_body, db, &ctx.with_fix(db, left_binder, rec_name))
+        }),
+        (ExpressionData::Ref(left), ExpressionData::Ref(right))
+        | (ExpressionData::Deref(left), ExpressionData::Deref(right)) => {
+            left.definitionally_equal(right, db, ctx)
+        }
+        (
+            ExpressionData::Loan {
+                local: left_local,
+                loan_as,
+                with,
+                body: left_body,
+            },
+            ExpressionData::Loan {
+                local: right_local,
+                body: right_body,
+                ..
+            },
+        ) => {
+            if left_local == right_local {
+                match ctx.with_loan(db, left_local, loan_as, with) {
+                    Some(body_ctx) => left_body.definitionally_equal(right_body, db, &body_ctx),
+                    None => Dr::new(false),
+                }
+            } else {
+                Dr::new(false)
+            }
+        }
+        (
+            ExpressionData::In {
+                reference: left_reference,
+                target: left_target,
+            },
+            ExpressionData::In {
+                reference: right_reference,
+                target: right_target,
+            },
+        ) => all_equal(
+            db,
+            ctx,
+            [
+                (left_reference, right_reference),
+                (left_target, right_target),
+            ],
+        ),
+        (ExpressionData::LocalConstant(left), ExpressionData::LocalConstant(right)) => {
+            Dr::new(left.id == right.id)
+        }
+        (ExpressionData::Hole(left), ExpressionData::Hole(right)) => Dr::new(left.id == right.id),
+        _ => Dr::new(false),
+    }
+}
+
+/// Returns true if the two binders have equal structure and definitionally equal bodies.
+/// The names of the bound variables are ignored.
+fn binders_equal(db: &dyn Db, ctx: &LocalContext, left: Binder, right: Binder) -> Dr<bool> {
+    if left.structure.argument_style == right.structure.argument_style
+        && left.structure.invocation_style == right.structure.invocation_style
+        && left.structure.bound.usage == right.structure.bound.usage
+    {
+        and_then(
+            left.structure
+                .bound
+                .ty
+                .definitionally_equal(right.structure.bound.ty, db, ctx),
+            || {
+                left.body
+                    .definitionally_equal(right.body, db, &ctx.with(left.structure.bound))
+            },
+        )
+    } else {
+        Dr::new(false)
+    }
+}
+
+/// Returns true if each pair of expressions is definitionally equal.
+fn all_equal(
+    db: &dyn Db,
+    ctx: &LocalContext,
+    pairs: impl IntoIterator<Item = (Expression, Expression)>,
+) -> Dr<bool> {
+    pairs
+        .into_iter()
+        .fold(Dr::new(true), |result, (left, right)| {
+            and_then(result, || left.definitionally_equal(right, db, ctx))
+        })
+}
+
+/// If exactly one of the expressions is a lambda abstraction `fun x => f x`,
+/// compare it with the eta-expansion of the other expression.
+/// Pi types are not functions, so they have no eta rule; they are handled by [`structurally_equal`].
+fn eta_equal(db: &dyn Db, ctx: &LocalContext, left: Expression, right: Expression) -> Dr<bool> {
+    match (left.data(db), right.data(db)) {
+        (ExpressionData::Lambda(_), ExpressionData::Lambda(_)) => Dr::new(false),
+        (ExpressionData::Lambda(binder), _) => eta_expanded_equal(db, ctx, binder, right),
+        (_, ExpressionData::Lambda(binder)) => eta_expanded_equal(db, ctx, binder, left),
+        _ => Dr::new(false),
+    }
+}
+
+/// Checks whether the body of the given lambda abstraction is definitionally equal to `other x`,
+/// where `x` is the variable bound by the lambda.
+fn eta_expanded_equal(
+    db: &dyn Db,
+    ctx: &LocalContext,
+    binder: Binder,
+    other: Expression,
+) -> Dr<bool> {
+    let expanded = Expression::new_apply(
+        db,
+        other.lift_free_vars(db, DeBruijnOffset::zero(), DeBruijnOffset::new(1)),
+        Expression::new_local(db, DeBruijnIndex::zero()),
+    );
+    binder
+        .body
+        .definitionally_equal(expanded, db, &ctx.with(binder.structure.bound))
+}
+
+/// Returns true if both expressions are proofs of definitionally equal propositions.
+/// A proposition is a type that lives in `Sort 0`.
+///
+/// Types are never proofs, so they are rejected without inferring anything.
+/// Otherwise, the type of `left` is inferred first, and `right` is only inspected if `left` is a proof.
+/// If a type cannot be inferred, for example because the expression is ill-typed
+/// or uses a feature that the type checker does not support, the expressions are not considered equal.
+fn proof_irrelevant_equal(
+    db: &dyn Db,
+    ctx: &LocalContext,
+    left: Expression,
+    right: Expression,
+) -> Dr<bool> {
+    let is_type = |expr: Expression| {
+        matches!(
+            expr.data(db),
+            ExpressionData::Sort(_)
+                | ExpressionData::Pi(_)
+                | ExpressionData::Ref(_)
+                | ExpressionData::In { .. }
+        )
+    };
+    if is_type(left) || is_type(right) {
+        return Dr::new(false);
+    }
+
+    let Some(&left_ty) = left.infer_type(db, ctx).value() else {
+        return Dr::new(false);
+    };
+    let is_proof = left_ty
+        .infer_sort(db, ctx)
+        .value()
+        .map_or(false, |universe| universe.equivalent(&Universe::zero()));
+    if !is_proof {
+        return Dr::new(false);
+    }
+    match right.infer_type(db, ctx).value() {
+        Some(&right_ty) => left_ty.definitionally_equal(right_ty, db, ctx),
+        None => Dr::new(false),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_db::{local_constant, TestDatabase};
+
+    #[test]
+    fn ill_typed_expressions_are_not_equal() {
+        let db = TestDatabase::default();
+        let [subject, motive, other] =
+            [0, 1, 2].map(|id| Expression::new_local_constant(&db, local_constant(&db, id, "x")));
+        // The motive is not a function, so the type of this match cannot be inferred.
+        let ill_typed = Expression::new_match(&db, subject, motive, Vec::new().into());
+        assert_eq!(
+            ill_typed
+                .definitionally_equal(other, &db, &LocalContext::new())
+                .value(),
+            Some(&false)
+        );
+    }
+}
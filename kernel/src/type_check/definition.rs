@@ -1,5 +1,7 @@
 use crate::{definition::Definition, expr::Universe};
 
+use super::Multiplicity;
+
 use std::fmt::Display;
 
 /// A definition that has been verified by the type checker.
@@ -12,6 +14,8 @@ pub struct CertifiedDefinition {
     reducibility: Reducibility,
     /// Why this definition exists.
     origin: DefinitionOrigin,
+    /// The multiplicity of each parameter bound by the leading lambda abstractions of the body.
+    parameter_usage: Vec<Multiplicity>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -22,18 +26,19 @@ pub enum DefinitionOrigin {
 
 impl CertifiedDefinition {
     /// Certified definitions can only be created by the type checker in the kernel.
-    #[allow(dead_code)]
     pub(in crate::type_check) fn new(
         def: Definition,
         universe: Universe,
         reducibility: Reducibility,
         origin: DefinitionOrigin,
+        parameter_usage: Vec<Multiplicity>,
     ) -> Self {
         Self {
             def,
             universe,
             reducibility,
             origin,
+            parameter_usage,
         }
     }
 
@@ -41,8 +46,8 @@ impl CertifiedDefinition {
         &self.def
     }
 
-    pub fn universe(&self) -> Universe {
-        self.universe
+    pub fn universe(&self) -> &Universe {
+        &self.universe
     }
 
     pub fn reducibility(&self) -> Reducibility {
@@ -52,6 +57,13 @@ impl CertifiedDefinition {
     pub fn origin(&self) -> DefinitionOrigin {
         self.origin
     }
+
+    /// The number of times each parameter of this definition is used at runtime.
+    /// Parameters with multiplicity zero can be removed when compiling the definition.
+    /// See [`check_usage`](super::check_usage).
+    pub fn parameter_usage(&self) -> &[Multiplicity] {
+        &self.parameter_usage
+    }
 }
 
 /// Information used by the definitional equality checker to choose which definitions to unfold first.
@@ -66,6 +78,7 @@ pub enum Reducibility {
         height: DefinitionHeight,
     },
     /// Irreducible definitions are never unfolded.
+    /// Axioms, which have no body, are always irreducible.
     /// They do not have a definition height.
     /// Irreducible definitions include recursive functions that may not terminate.
     Irreducible,
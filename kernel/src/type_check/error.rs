@@ -0,0 +1,194 @@
+use std::fmt::Display;
+
+use diagnostic::DynamicDiagnostic;
+use files::{SourceData, Span};
+use miette::{Diagnostic, LabeledSpan};
+use thiserror::Error;
+
+use crate::expr::Expression;
+
+/// An error emitted by the type checker.
+/// Expressions are stored as formatted strings, so that the error can be rendered without a database.
+///
+/// These errors do not contain source spans themselves.
+/// Errors raised while inferring the type of an expression are wrapped in an [`InferenceError`],
+/// which [`crate::certify_definition`] uses to label the offending sub-expression.
+#[derive(Error, Diagnostic, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TypeError {
+    #[error("type mismatch: expected {expected}, found {found}")]
+    TypeMismatch { expected: String, found: String },
+    #[error("universe mismatch: expected Sort {expected}, found Sort {found}")]
+    UniverseMismatch { expected: String, found: String },
+    #[error("expected a function, but {function} has type {ty}")]
+    NotAFunction { function: String, ty: String },
+    #[error("expected a type, but {expr} has type {ty}")]
+    NotAType { expr: String, ty: String },
+    #[error("the return type {motive} of a match expression must be a function returning a type, but it has type {ty}")]
+    InvalidMotive { motive: String, ty: String },
+    #[error("local variable {index} was not bound")]
+    #[diagnostic(help = "this is a bug in the compiler")]
+    UnboundLocal { index: String },
+    #[error("could not find a type correct definition named {path}")]
+    UnknownDefinition { path: String },
+    #[error("could not find the module {module} containing {path}")]
+    #[diagnostic(help = "each module is written in a file with the same path as the module")]
+    UnknownModule { module: String, path: String },
+    #[error("definitions cannot refer to themselves, but found the cycle {cycle}")]
+    #[diagnostic(help = "use a fix expression to write a recursive definition")]
+    CyclicDefinition { cycle: String },
+    #[error("{inductive} occurs in a negative position in the field {field} of {intro_rule}, which has type {ty}")]
+    #[diagnostic(
+        help = "an inductive type may only refer to itself to the right of every arrow in its fields"
+    )]
+    NonPositiveOccurrence {
+        inductive: String,
+        intro_rule: String,
+        field: String,
+        ty: String,
+    },
+    #[error("could not reduce {expr} after {fuel} reduction steps")]
+    #[diagnostic(
+        help = "this may be a bug in the compiler, or an expression that reduces forever"
+    )]
+    ReductionLimitExceeded { expr: String, fuel: u64 },
+    #[error("the universe variable {var} is not a universe parameter of this definition")]
+    #[diagnostic(
+        help = "universe parameters are listed after the name of the definition that uses them"
+    )]
+    UnboundUniverseVariable { var: String },
+    #[error("{path} has {expected} universe parameters, but was given {found} universe levels")]
+    UniverseArgumentMismatch {
+        path: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("universe levels above {max} are not supported")]
+    UniverseTooLarge { max: u32 },
+    #[error("expected a reference, but {expr} has type {ty}")]
+    NotAReference { expr: String, ty: String },
+    #[error("expected a proof, but {expr} has type {ty}, which is not a proposition")]
+    NotAProof { expr: String, ty: String },
+    #[error("cannot loan the erased variable {local}")]
+    #[diagnostic(help = "erased variables do not exist at runtime, so cannot be referenced")]
+    ErasedLoan { local: String },
+    #[error("the body of a loan has type {ty}, which refers to the loaned reference")]
+    #[diagnostic(help = "a reference created by a loan cannot outlive the loan")]
+    LoanEscapes { ty: String },
+    #[error("could not infer the value of hole {hole}")]
+    #[diagnostic(help = "try writing this expression explicitly")]
+    UnsolvedHole { hole: String },
+    #[error("{feature} are not yet supported by the type checker")]
+    #[diagnostic(help = "inductive types cannot yet be declared")]
+    Unsupported { feature: String },
+}
+
+/// An error emitted when a variable is not used according to its multiplicity.
+/// See [`check_usage`](super::check_usage).
+#[derive(Error, Diagnostic, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UsageError {
+    #[error("the erased variable {name} cannot be used at runtime")]
+    #[diagnostic(help = "erased variables may only be used in types and proofs")]
+    ErasedVariable { name: String },
+    #[error("the erased definition {path} cannot be used at runtime")]
+    #[diagnostic(help = "erased definitions may only be used in types and proofs")]
+    ErasedDefinition { path: String },
+    #[error("the function {name} may only be called once, but is used more than once")]
+    LinearVariableReused { name: String },
+}
+
+/// A diagnostic produced while inferring the type of an expression.
+/// This records the expressions that were being checked when the error occurred, from the innermost outwards,
+/// so that the error can later be reported at the offending sub-term.
+/// See [`crate::certify_definition`], which converts these into [`LocatedError`]s.
+#[derive(Error, Diagnostic, Debug, Clone, PartialEq, Eq)]
+#[error("{error}")]
+#[diagnostic(forward(error))]
+pub struct InferenceError {
+    pub error: DynamicDiagnostic,
+    pub exprs: Vec<Expression>,
+}
+
+impl InferenceError {
+    /// Records that the given diagnostic occurred while checking `expr`.
+    /// If the diagnostic is already an [`InferenceError`], `expr` is added as its outermost expression.
+    pub fn within(mut error: DynamicDiagnostic, expr: Expression) -> DynamicDiagnostic {
+        match error.downcast_mut::<InferenceError>() {
+            Some(inference) => {
+                if inference.exprs.last() != Some(&expr) {
+                    inference.exprs.push(expr);
+                }
+                error
+            }
+            None => DynamicDiagnostic::new(InferenceError {
+                error,
+                exprs: vec![expr],
+            }),
+        }
+    }
+}
+
+/// A diagnostic that occurred at a particular span of source code.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{error}")]
+pub struct LocatedError {
+    pub src: SourceData,
+    pub span: Span,
+    pub error: DynamicDiagnostic,
+    /// Diagnostics shown alongside this one, such as [`DefinedHere`].
+    /// These may point into other source files.
+    pub related: Vec<DynamicDiagnostic>,
+}
+
+impl Diagnostic for LocatedError {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.error.code()
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        self.error.severity()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.error.help()
+    }
+
+    fn url<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.error.url()
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        Some(&self.src)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        Some(Box::new(std::iter::once(LabeledSpan::new_with_span(
+            Some("error occurred here".to_owned()),
+            self.span,
+        ))))
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        if self.related.is_empty() {
+            self.error.related()
+        } else {
+            Some(Box::new(
+                self.related
+                    .iter()
+                    .map(|related| related as &dyn Diagnostic),
+            ))
+        }
+    }
+}
+
+/// Points to the definition of something that a [`LocatedError`] refers to,
+/// so that a definition in another source file can be shown alongside the error.
+#[derive(Error, Diagnostic, Debug, Clone, PartialEq, Eq, Hash)]
+#[error("{path} is defined here")]
+#[diagnostic(severity(Advice))]
+pub struct DefinedHere {
+    #[source_code]
+    pub src: SourceData,
+    #[label("{path} is defined here")]
+    pub span: Span,
+    pub path: String,
+}
@@ -0,0 +1,549 @@
+//! Infers the types of expressions.
+
+use std::sync::Arc;
+
+use diagnostic::{Dr, DynamicDiagnostic};
+use files::Str;
+
+use crate::{
+    de_bruijn::{DeBruijnIndex, DeBruijnOffset},
+    expr::*,
+    get_certified_definition, Db,
+};
+
+use super::{InferenceError, TypeError};
+
+/// The local variables that are in scope while we infer the type of an expression.
+/// The most recently bound variable has de Bruijn index zero.
+///
+/// An empty context can be used to infer the types of closed expressions, such as the bodies of definitions.
+///
+/// Contexts share their outer variables, so cloning or extending a context is cheap.
+/// This makes it easy to check several sub-expressions, such as the cases of a `match`, in separate contexts.
+#[derive(Debug, Clone, Default)]
+pub struct LocalContext {
+    /// The innermost bound variable, or [`None`] if the context is empty.
+    innermost: Option<Arc<ContextEntry>>,
+    /// The number of variables in the context.
+    len: usize,
+}
+
+#[derive(Debug)]
+struct ContextEntry {
+    /// The type of this variable is valid in the context formed by the variables in `outer`.
+    bound: BoundVariable,
+    outer: Option<Arc<ContextEntry>>,
+}
+
+impl LocalContext {
+    /// Creates an empty local context.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of variables bound in this context.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no variables are bound in this context.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Binds the given variable at de Bruijn index zero.
+    /// The indices of all other variables in the context are increased by one.
+    /// The type of the variable must be valid in the context before it was pushed.
+    pub fn push(&mut self, bound: BoundVariable) {
+        self.innermost = Some(Arc::new(ContextEntry {
+            bound,
+            outer: self.innermost.take(),
+        }));
+        self.len += 1;
+    }
+
+    /// Returns a new context with the given variable bound at de Bruijn index zero.
+    /// See [`LocalContext::push`].
+    pub fn with(&self, bound: BoundVariable) -> Self {
+        let mut ctx = self.clone();
+        ctx.push(bound);
+        ctx
+    }
+
+    /// Returns a new context containing the variables bound by the body of a fixed point expression.
+    /// Local variable `1` is the subject of the recursion,
+    /// and local variable `0` is the recursive function itself.
+    pub fn with_fix(&self, db: &dyn Db, binder: Binder, rec_name: Str) -> Self {
+        self.with(binder.structure.bound).with(BoundVariable {
+            name: rec_name,
+            ty: Expression::new_pi(db, binder).lift_free_vars(
+                db,
+                DeBruijnOffset::zero(),
+                DeBruijnOffset::new(1),
+            ),
+            usage: Usage::Present,
+        })
+    }
+
+    /// Returns a new context containing the variables bound by the body of a loan of the given local variable.
+    /// Local variable `1` is `loan_as`, a reference to the loaned variable,
+    /// and local variable `0` is `with`, an erased proof that `loan_as` refers to the loaned variable.
+    /// The kernel has no equality type, so this proof is expressed as `loan_as in local`.
+    ///
+    /// If the loaned variable is not bound in this context, return [`None`].
+    pub fn with_loan(
+        &self,
+        db: &dyn Db,
+        local: DeBruijnIndex,
+        loan_as: Str,
+        with: Str,
+    ) -> Option<Self> {
+        let ty = self.local_type(db, local)?;
+        Some(
+            self.with(BoundVariable {
+                name: loan_as,
+                ty: Expression::new_ref(db, ty),
+                usage: Usage::Present,
+            })
+            .with(BoundVariable {
+                name: with,
+                ty: Expression::new_in(
+                    db,
+                    Expression::new_local(db, DeBruijnIndex::zero()),
+                    Expression::new_local(db, local.succ()),
+                ),
+                usage: Usage::Erased,
+            }),
+        )
+    }
+
+    /// Returns the given local variable, or [`None`] if it is not bound in this context.
+    /// The type of the variable is stored as it was when the variable was bound;
+    /// use [`LocalContext::local_type`] to get a type that is valid in this context.
+    pub fn get(&self, index: DeBruijnIndex) -> Option<&BoundVariable> {
+        let mut entry = self.innermost.as_deref()?;
+        for _ in 0..index.value() {
+            entry = entry.outer.as_deref()?;
+        }
+        Some(&entry.bound)
+    }
+
+    /// Returns the type of the given local variable, lifted so that it is valid in this context.
+    /// If the variable is not bound in this context, return [`None`].
+    pub fn local_type(&self, db: &dyn Db, index: DeBruijnIndex) -> Option<Expression> {
+        self.get(index).map(|bound| {
+            bound.ty.lift_free_vars(
+                db,
+                DeBruijnOffset::zero(),
+                DeBruijnOffset::new(index.value() + 1),
+            )
+        })
+    }
+}
+
+fn type_error<T>(error: TypeError) -> Dr<T> {
+    Dr::new_err(DynamicDiagnostic::new(error))
+}
+
+impl Expression {
+    /// Infers the type of this expression in the given local context.
+    /// This also checks that the expression is type correct, emitting an error diagnostic if not.
+    ///
+    /// The local context must bind every free variable of this expression.
+    /// The returned type is valid in the same context.
+    ///
+    /// If type checking fails, the error is wrapped in an [`InferenceError`] that records this expression.
+    ///
+    /// If an input changes while this runs on another thread, salsa cancels it
+    /// the next time it visits a sub-expression, so outdated type checking is abandoned promptly.
+    pub fn infer_type(self, db: &dyn Db, ctx: &LocalContext) -> Dr<Expression> {
+        db.unwind_if_cancelled();
+        self.infer_type_core(db, ctx)
+            .map_err(|err| InferenceError::within(err, self))
+    }
+
+    fn infer_type_core(self, db: &dyn Db, ctx: &LocalContext) -> Dr<Expression> {
+        match self.data(db) {
+            ExpressionData::Local(index) => match ctx.local_type(db, index) {
+                Some(ty) => Dr::new(ty),
+                None => type_error(TypeError::UnboundLocal {
+                    index: index.to_string(),
+                }),
+            },
+            ExpressionData::Apply { left, right } => left.infer_type(db, ctx).bind(|left_ty| {
+                left_ty
+                    .weak_head_normal_form(db)
+                    .bind(|whnf| match whnf.data(db) {
+                        ExpressionData::Pi(binder) => right
+                            .check_type(db, ctx, binder.structure.bound.ty)
+                            .map(|()| binder.body.instantiate(db, right)),
+                        _ => type_error(TypeError::NotAFunction {
+                            function: db.format_expression(left),
+                            ty: db.format_expression(left_ty),
+                        }),
+                    })
+            }),
+            ExpressionData::Lambda(binder) => {
+                binder.structure.bound.ty.infer_sort(db, ctx).bind(|_| {
+                    binder
+                        .body
+                        .infer_type(db, &ctx.with(binder.structure.bound))
+                        .map(|body| {
+                            Expression::new_pi(
+                                db,
+                                Binder {
+                                    structure: binder.structure,
+                                    body,
+                                },
+                            )
+                        })
+                })
+            }
+            ExpressionData::Pi(binder) => {
+                binder
+                    .structure
+                    .bound
+                    .ty
+                    .infer_sort(db, ctx)
+                    .bind(|domain| {
+                        binder
+                            .body
+                            .infer_sort(db, &ctx.with(binder.structure.bound))
+                            .map(|codomain| {
+                                // Since `Sort 0` is impredicative, a pi type whose codomain is a proposition
+                                // is also a proposition.
+                                Expression::new_sort(db, domain.imax(codomain).normalise())
+                            })
+                    })
+            }
+            ExpressionData::Let {
+                to_assign, body, ..
+            } => to_assign
+                .infer_type(db, ctx)
+                .bind(|_| body.instantiate(db, to_assign).infer_type(db, ctx)),
+            ExpressionData::Sort(universe) => {
+                if universe.as_const() == Some(u32::MAX) {
+                    type_error(TypeError::UniverseTooLarge { max: u32::MAX - 1 })
+                } else {
+                    Dr::new(Expression::new_sort(db, universe.succ().normalise()))
+                }
+            }
+            // Definitions in other modules are certified in the same way as those in this module,
+            // which loads the source file of their module.
+            ExpressionData::Inst { path, universes } => match get_certified_definition(db, path) {
+                Some(def) if def.def().universe_params.len() != universes.len() => {
+                    type_error(TypeError::UniverseArgumentMismatch {
+                        path: path.display(db),
+                        expected: def.def().universe_params.len(),
+                        found: universes.len(),
+                    })
+                }
+                // The type of the definition is used at the given universe levels.
+                Some(def) => Dr::new(def.def().ty.instantiate_universes(
+                    db,
+                    &def.def().universe_params,
+                    &universes,
+                )),
+                None => {
+                    let (module, _) = path.split_last(db);
+                    if db.module_exists(module) {
+                        type_error(TypeError::UnknownDefinition {
+                            path: path.display(db),
+                        })
+                    } else {
+                        type_error(TypeError::UnknownModule {
+                            module: module.display(db),
+                            path: path.display(db),
+                        })
+                    }
+                }
+            },
+            ExpressionData::Intro {
+                parameters, fields, ..
+            } => Dr::sequence_lazy(
+                parameters
+                    .iter()
+                    .map(|param| move || param.infer_type(db, ctx)),
+            )
+            .bind(|_| {
+                Dr::sequence_lazy(
+                    fields
+                        .iter()
+                        .map(|(_, field)| move || field.infer_type(db, ctx)),
+                )
+            })
+            // Checking that the variant exists and that its fields have the right types requires
+            // the declaration of the inductive type, and inductive types cannot yet be declared.
+            .bind(|_| {
+                type_error(TypeError::Unsupported {
+                    feature: "intro expressions".to_owned(),
+                })
+            }),
+            ExpressionData::Match {
+                subject, return_ty, ..
+            } => subject.infer_type(db, ctx).bind(|subject_ty| {
+                return_ty.infer_type(db, ctx).bind(|motive_ty| {
+                    motive_ty
+                        .weak_head_normal_form(db)
+                        .bind(|whnf| match whnf.data(db) {
+                            ExpressionData::Pi(binder) => subject_ty
+                                .check_equal(db, ctx, binder.structure.bound.ty)
+                                .bind(|()| binder.body.weak_head_normal_form(db))
+                                .bind(|body| match body.data(db) {
+                                    // Checking the cases requires the variants of the subject's
+                                    // inductive type, and inductive types cannot yet be declared.
+                                    ExpressionData::Sort(_) => type_error(TypeError::Unsupported {
+                                        feature: "match expressions".to_owned(),
+                                    }),
+                                    _ => type_error(TypeError::InvalidMotive {
+                                        motive: db.format_expression(return_ty),
+                                        ty: db.format_expression(motive_ty),
+                                    }),
+                                }),
+                            _ => type_error(TypeError::InvalidMotive {
+                                motive: db.format_expression(return_ty),
+                                ty: db.format_expression(motive_ty),
+                            }),
+                        })
+                })
+            }),
+            ExpressionData::Fix {
+                binder,
+                rec_name,
+                body,
+            } => {
+                let fix_ty = Expression::new_pi(db, binder);
+                fix_ty.infer_sort(db, ctx).bind(|_| {
+                    body.check_type(
+                        db,
+                        &ctx.with_fix(db, binder, rec_name),
+                        binder.body.lift_free_vars(
+                            db,
+                            DeBruijnOffset::zero(),
+                            DeBruijnOffset::new(1),
+                        ),
+                    )
+                    .map(|()| fix_ty)
+                })
+            }
+            ExpressionData::Ref(ty) => ty
+                .infer_sort(db, ctx)
+                .map(|universe| Expression::new_sort(db, universe)),
+            ExpressionData::Deref(value) => value.infer_reference(db, ctx),
+            ExpressionData::Loan {
+                local,
+                loan_as,
+                with,
+                body,
+            } => match ctx.get(local) {
+                Some(bound) if bound.usage == Usage::Erased => type_error(TypeError::ErasedLoan {
+                    local: bound.name.text(db).to_owned(),
+                }),
+                Some(_) => {
+                    // The loaned variable is bound, so `with_loan` succeeds.
+                    let body_ctx = ctx.with_loan(db, local, loan_as, with).unwrap();
+                    body.infer_type(db, &body_ctx).bind(|ty| {
+                        if ty.local_is_bound(db, DeBruijnIndex::zero())
+                            || ty.local_is_bound(db, DeBruijnIndex::zero().succ())
+                        {
+                            type_error(TypeError::LoanEscapes {
+                                ty: db.format_expression(ty),
+                            })
+                        } else {
+                            // Neither of the variables bound by the loan occur in the type,
+                            // so instantiating them only lowers the indices of the other free variables.
+                            let unused = Expression::new_local(db, DeBruijnIndex::zero());
+                            Dr::new(ty.instantiate_many(db, &[unused, unused]))
+                        }
+                    })
+                }
+                None => type_error(TypeError::UnboundLocal {
+                    index: local.to_string(),
+                }),
+            },
+            ExpressionData::Take {
+                local,
+                proofs,
+                body,
+            } => {
+                // The proofs cannot yet state that the borrow is not stored in a variable,
+                // since the kernel has no negation, so we only check that they are proofs.
+                let unbound = std::iter::once(local)
+                    .chain(proofs.iter().map(|(index, _)| *index))
+                    .find(|index| ctx.get(*index).is_none());
+                match unbound {
+                    Some(index) => type_error(TypeError::UnboundLocal {
+                        index: index.to_string(),
+                    }),
+                    None => Dr::sequence_lazy(
+                        proofs
+                            .iter()
+                            .map(|(_, proof)| move || proof.check_proof(db, ctx)),
+                    )
+                    .bind(|_| body.infer_type(db, ctx)),
+                }
+            }
+            ExpressionData::In { reference, target } => reference
+                .infer_reference(db, ctx)
+                .bind(|_| target.infer_type(db, ctx))
+                .map(|_| Expression::new_sort(db, Universe::zero())),
+            ExpressionData::LocalConstant(constant) => Dr::new(constant.structure.bound.ty),
+            ExpressionData::Hole(hole) => Dr::new(hole.ty),
+        }
+    }
+
+    /// Checks that this expression is a type, and returns the universe that it lives in.
+    pub fn infer_sort(self, db: &dyn Db, ctx: &LocalContext) -> Dr<Universe> {
+        self.infer_type(db, ctx).bind(|ty| {
+            ty.weak_head_normal_form(db)
+                .bind(|whnf| match whnf.data(db) {
+                    ExpressionData::Sort(universe) => Dr::new(universe),
+                    _ => type_error(TypeError::NotAType {
+                        expr: db.format_expression(self),
+                        ty: db.format_expression(ty),
+                    }),
+                })
+        })
+    }
+
+    /// Checks that this expression is a reference, and returns the type that it refers to.
+    fn infer_reference(self, db: &dyn Db, ctx: &LocalContext) -> Dr<Expression> {
+        self.infer_type(db, ctx).bind(|ty| {
+            ty.weak_head_normal_form(db)
+                .bind(|whnf| match whnf.data(db) {
+                    ExpressionData::Ref(inner) => Dr::new(inner),
+                    _ => type_error(TypeError::NotAReference {
+                        expr: db.format_expression(self),
+                        ty: db.format_expression(ty),
+                    }),
+                })
+        })
+    }
+
+    /// Checks that this expression is a proof of a proposition, a type that lives in `Sort 0`.
+    fn check_proof(self, db: &dyn Db, ctx: &LocalContext) -> Dr<()> {
+        self.infer_type(db, ctx).bind(|ty| {
+            ty.infer_sort(db, ctx).bind(|universe| {
+                if universe.equivalent(&Universe::zero()) {
+                    Dr::new(())
+                } else {
+                    type_error(TypeError::NotAProof {
+                        expr: db.format_expression(self),
+                        ty: db.format_expression(ty),
+                    })
+                }
+            })
+        })
+    }
+
+    /// Checks that this expression has the given type.
+    pub fn check_type(self, db: &dyn Db, ctx: &LocalContext, expected: Expression) -> Dr<()> {
+        self.infer_type(db, ctx)
+            .bind(|found| found.check_equal(db, ctx, expected))
+            .map_err(|err| InferenceError::within(err, self))
+    }
+
+    /// Checks that this type is definitionally equal to the `expected` type.
+    fn check_equal(self, db: &dyn Db, ctx: &LocalContext, expected: Expression) -> Dr<()> {
+        self.definitionally_equal(expected, db, ctx).bind(|equal| {
+            if equal {
+                return Dr::new(());
+            }
+            self.weak_head_normal_form(db)
+                .zip(expected.weak_head_normal_form(db))
+                .bind(|(found_whnf, expected_whnf)| {
+                    if let (ExpressionData::Sort(found), ExpressionData::Sort(expected)) =
+                        (found_whnf.data(db), expected_whnf.data(db))
+                    {
+                        // Both types are sorts, so the only difference is their universe levels.
+                        type_error(TypeError::UniverseMismatch {
+                            expected: expected.display(db),
+                            found: found.display(db),
+                        })
+                    } else {
+                        type_error(TypeError::TypeMismatch {
+                            expected: db.format_expression(expected),
+                            found: db.format_expression(self),
+                        })
+                    }
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_db::{local_constant, TestDatabase};
+
+    #[test]
+    fn borrow_expressions() {
+        let db = TestDatabase::default();
+        let text = |name: &str| Str::new(&db, name.to_owned());
+        let a = Expression::new_local_constant(&db, local_constant(&db, 0, "a"));
+        let x = BoundVariable {
+            name: text("x"),
+            ty: a,
+            usage: Usage::Present,
+        };
+        let ctx = LocalContext::new().with(x);
+        let infer = |expr: Expression, ctx: &LocalContext| expr.infer_type(&db, ctx).to_reports();
+
+        let ref_a = Expression::new_ref(&db, a);
+        assert_eq!(
+            infer(ref_a, &ctx).0,
+            Some(Expression::new_sort(&db, Universe::zero()))
+        );
+
+        // `loan x as r with p ; * r` has the type of `x`.
+        let loan =
+            |body| Expression::new_loan(&db, DeBruijnIndex::zero(), text("r"), text("p"), body);
+        let r = Expression::new_local(&db, DeBruijnIndex::new(1));
+        assert_eq!(infer(loan(Expression::new_deref(&db, r)), &ctx).0, Some(a));
+
+        // `loan x as r with p ; r` would let the reference outlive the loan.
+        let (value, reports) = infer(loan(r), &ctx);
+        assert!(value.is_none());
+        assert!(reports[0]
+            .to_string()
+            .contains("refers to the loaned reference"));
+
+        // Erased variables cannot be loaned.
+        let erased = LocalContext::new().with(BoundVariable {
+            usage: Usage::Erased,
+            ..x
+        });
+        let (value, reports) = infer(loan(Expression::new_deref(&db, r)), &erased);
+        assert!(value.is_none());
+        assert!(reports[0]
+            .to_string()
+            .contains("cannot loan the erased variable x"));
+    }
+
+    #[test]
+    fn local_context_lifts_types() {
+        let db = TestDatabase::default();
+        let bound = |name: &str, ty| BoundVariable {
+            name: Str::new(&db, name.to_owned()),
+            ty,
+            usage: Usage::Present,
+        };
+        // `x : Sort 0, y : x`
+        let mut ctx = LocalContext::new();
+        ctx.push(bound("x", Expression::new_sort(&db, Universe::zero())));
+        let outer = ctx.clone();
+        ctx.push(bound(
+            "y",
+            Expression::new_local(&db, DeBruijnIndex::zero()),
+        ));
+
+        assert_eq!(ctx.len(), 2);
+        assert_eq!(outer.len(), 1);
+        assert_eq!(ctx.get(DeBruijnIndex::new(1)).unwrap().name.text(&db), "x");
+        assert!(ctx.get(DeBruijnIndex::new(2)).is_none());
+        assert_eq!(
+            ctx.local_type(&db, DeBruijnIndex::zero()),
+            Some(Expression::new_local(&db, DeBruijnIndex::new(1)))
+        );
+    }
+}
@@ -1,18 +1,30 @@
 //! Performs type checking and evaluation of expressions.
 
-use diagnostic::Dr;
+use diagnostic::{Dr, DynamicDiagnostic};
 use files::Path;
 
-use crate::{definition::Definition, Db};
+use crate::{
+    definition::Definition,
+    expr::{Expression, ExpressionData, Hole},
+    Db,
+};
 
 mod defeq;
 mod definition;
+mod error;
+mod infer;
+mod positivity;
 mod unfold;
+mod usage;
 mod whnf;
 
 pub use defeq::*;
 pub use definition::*;
+pub use error::*;
+pub use infer::*;
+pub use positivity::*;
 pub use unfold::*;
+pub use usage::*;
 pub use whnf::*;
 
 /// Type checks the definition with the given name.
@@ -24,10 +36,110 @@ pub use whnf::*;
 /// in most instances you should call [`crate::certify_definition`] or [`crate::get_certified_definition`].
 /// These functions are able to parse and certify both feather and quill definitions.
 pub fn certify_definition(
-    _db: &dyn Db,
-    _path: Path,
-    _def: &Definition,
-    _origin: DefinitionOrigin,
+    db: &dyn Db,
+    path: Path,
+    def: &Definition,
+    origin: DefinitionOrigin,
 ) -> Dr<CertifiedDefinition> {
-    todo!()
+    tracing::debug!("certifying {}", path.display(db));
+    let ctx = LocalContext::new();
+    check_no_holes(db, def)
+        .bind(|()| check_universe_params(db, def))
+        .bind(|()| def.ty.infer_sort(db, &ctx))
+        .bind(|universe| match def.body {
+            Some(body) => body
+                .check_type(db, &ctx, def.ty)
+                .bind(|()| check_usage(db, def))
+                .map(|parameter_usage| {
+                    CertifiedDefinition::new(
+                        def.clone(),
+                        universe,
+                        reducibility(db, body),
+                        origin,
+                        parameter_usage,
+                    )
+                }),
+            // An axiom only needs a valid type, which we checked above.
+            None => Dr::new(CertifiedDefinition::new(
+                def.clone(),
+                universe,
+                Reducibility::Irreducible,
+                origin,
+                Vec::new(),
+            )),
+        })
+}
+
+/// Reports a [`TypeError::UnsolvedHole`] for each hole remaining in the given definition.
+/// Certified definitions must not contain holes, since there is nothing to fill them with.
+///
+/// The type of a hole is usually another hole, which is not reported separately.
+/// Each error is wrapped in an [`InferenceError`] for the hole, so that it is reported where the hole was written.
+fn check_no_holes(db: &dyn Db, def: &Definition) -> Dr<()> {
+    let mut errors = Vec::new();
+    let mut hole_types = Vec::new();
+    for expr in std::iter::once(def.ty).chain(def.body) {
+        for id in expr.unsolved_holes(db) {
+            if hole_types.contains(&id) {
+                continue;
+            }
+            let hole = expr
+                .find(db, &|inner, _offset| {
+                    matches!(inner.data(db), ExpressionData::Hole(hole) if hole.id == id)
+                })
+                .expect("the hole occurs in the expression");
+            if let ExpressionData::Hole(Hole { ty, .. }) = hole.data(db) {
+                if let ExpressionData::Hole(ty) = ty.data(db) {
+                    hole_types.push(ty.id);
+                }
+            }
+            errors.push(DynamicDiagnostic::new(InferenceError {
+                error: DynamicDiagnostic::new(TypeError::UnsolvedHole {
+                    hole: id.to_string(),
+                }),
+                exprs: vec![hole],
+            }));
+        }
+    }
+    if errors.is_empty() {
+        Dr::new(())
+    } else {
+        Dr::new_err_many(errors)
+    }
+}
+
+/// Reports a [`TypeError::UnboundUniverseVariable`] if the type or body of the given definition
+/// uses a universe variable that is not one of its universe parameters.
+/// The error is wrapped in an [`InferenceError`] for the expression using the variable.
+fn check_universe_params(db: &dyn Db, def: &Definition) -> Dr<()> {
+    let free = std::iter::once(def.ty)
+        .chain(def.body)
+        .find_map(|expr| expr.free_universe_variable(db, &def.universe_params));
+    match free {
+        Some((expr, var)) => Dr::new_err(DynamicDiagnostic::new(InferenceError {
+            error: DynamicDiagnostic::new(TypeError::UnboundUniverseVariable {
+                var: var.0.text(db).clone(),
+            }),
+            exprs: vec![expr],
+        })),
+        None => Dr::new(()),
+    }
+}
+
+/// Computes the reducibility of a definition with the given body.
+/// Recursive definitions may not terminate, so they are never unfolded.
+/// Other definitions are one higher than the highest definition they refer to.
+fn reducibility(db: &dyn Db, body: Expression) -> Reducibility {
+    let is_recursive = body
+        .find(db, &|inner, _offset| {
+            matches!(inner.data(db), ExpressionData::Fix { .. })
+        })
+        .is_some();
+    if is_recursive {
+        Reducibility::Irreducible
+    } else {
+        Reducibility::Reducible {
+            height: body.get_max_height(db) + 1,
+        }
+    }
 }
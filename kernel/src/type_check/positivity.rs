@@ -0,0 +1,152 @@
+//! Checks that inductive types are strictly positive.
+//!
+//! An inductive type may only refer to itself in the fields of its introduction rules
+//! to the right of every arrow. For example, a field of type `for (x: inst A) -> inst T`
+//! is allowed in the inductive type `T`, but a field of type `for (x: inst T) -> inst A` is not.
+//! Without this restriction, we could write non-terminating programs, making the logic inconsistent.
+
+use diagnostic::{Dr, DynamicDiagnostic};
+use files::Path;
+
+use crate::{definition::Definition, expr::*, Db};
+
+use super::TypeError;
+
+/// Checks that the inductive type with the given name only occurs in strictly positive positions
+/// in the fields of the given introduction rules.
+/// The type of each introduction rule is a sequence of `for` binders, one for each field.
+pub fn check_strict_positivity(db: &dyn Db, inductive: Path, intro_rules: &[Definition]) -> Dr<()> {
+    Dr::sequence(
+        intro_rules
+            .iter()
+            .map(|rule| check_intro_rule(db, inductive, rule)),
+    )
+    .map(|_| ())
+}
+
+fn check_intro_rule(db: &dyn Db, inductive: Path, rule: &Definition) -> Dr<()> {
+    let mut ty = rule.ty;
+    while let ExpressionData::Pi(binder) = ty.data(db) {
+        let field_ty = binder.structure.bound.ty;
+        let positive = is_strictly_positive(db, inductive, field_ty);
+        match positive.value() {
+            Some(true) => {}
+            // Reducing the field's type ran out of fuel.
+            None => return positive.map(|_| ()),
+            Some(false) => {
+                return Dr::new_err(DynamicDiagnostic::new(TypeError::NonPositiveOccurrence {
+                    inductive: inductive.display(db),
+                    intro_rule: rule.name.contents.text(db).to_owned(),
+                    field: binder.structure.bound.name.text(db).to_owned(),
+                    ty: db.format_expression(field_ty),
+                }))
+            }
+        }
+        ty = binder.body;
+    }
+    Dr::new(())
+}
+
+/// Returns true if `inductive` appears anywhere inside `expr`.
+fn occurs(db: &dyn Db, inductive: Path, expr: Expression) -> bool {
+    expr.find(
+        db,
+        &|inner, _offset| matches!(inner.data(db), ExpressionData::Inst { path, .. } if path == inductive),
+    )
+    .is_some()
+}
+
+/// A type is strictly positive in `inductive` if `inductive` does not occur in it,
+/// or if it is a function type whose parameter types do not mention `inductive`,
+/// and whose return type is `inductive` applied to arguments that do not mention it.
+fn is_strictly_positive(db: &dyn Db, inductive: Path, ty: Expression) -> Dr<bool> {
+    ty.weak_head_normal_form(db).bind(|ty| {
+        if !occurs(db, inductive, ty) {
+            return Dr::new(true);
+        }
+
+        match ty.data(db) {
+            ExpressionData::Pi(binder) => {
+                if occurs(db, inductive, binder.structure.bound.ty) {
+                    Dr::new(false)
+                } else {
+                    is_strictly_positive(db, inductive, binder.body)
+                }
+            }
+            _ => {
+                let mut head = ty;
+                let mut arguments = Vec::new();
+                while let ExpressionData::Apply { left, right } = head.data(db) {
+                    arguments.push(right);
+                    head = left;
+                }
+                Dr::new(
+                    matches!(head.data(db), ExpressionData::Inst { path, .. } if path == inductive)
+                        && arguments
+                            .into_iter()
+                            .all(|argument| !occurs(db, inductive, argument)),
+                )
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use files::{Str, WithProvenance};
+
+    use super::*;
+    use crate::test_db::TestDatabase;
+
+    #[test]
+    fn strict_positivity() {
+        let db = TestDatabase::default();
+        let inst = |name: &str| {
+            Expression::new_inst(&db, Path::new(&db, vec![Str::new(&db, name.to_owned())]))
+        };
+        // The non-dependent function type `for (name: param) -> result`.
+        let arrow = |name: &str, param, result| {
+            Expression::new_pi(
+                &db,
+                Binder {
+                    structure: BinderStructure {
+                        bound: BoundVariable {
+                            name: Str::new(&db, name.to_owned()),
+                            ty: param,
+                            usage: Usage::Present,
+                        },
+                        argument_style: ArgumentStyle::Explicit,
+                        invocation_style: InvocationStyle::Once,
+                    },
+                    body: result,
+                },
+            )
+        };
+        let intro_rule = |name: &str, ty| {
+            Definition::new(
+                WithProvenance::new(None, Str::new(&db, name.to_owned())),
+                Vec::new(),
+                Usage::Present,
+                ty,
+                None,
+            )
+        };
+        let path = |name: &str| Path::new(&db, vec![Str::new(&db, name.to_owned())]);
+
+        // `Nat.succ: for (n: inst Nat) -> inst Nat` is strictly positive.
+        let succ = intro_rule("succ", arrow("n", inst("Nat"), inst("Nat")));
+        assert!(succ.is_axiom());
+        assert!(check_strict_positivity(&db, path("Nat"), &[succ])
+            .value()
+            .is_some());
+
+        // `Bad.mk: for (f: for (x: inst Bad) -> inst Bool) -> inst Bad` is not.
+        let mk = intro_rule(
+            "mk",
+            arrow("f", arrow("x", inst("Bad"), inst("Bool")), inst("Bad")),
+        );
+        let (value, reports) = check_strict_positivity(&db, path("Bad"), &[mk]).to_reports();
+        assert!(value.is_none());
+        assert!(reports[0].to_string().contains("the field f of mk"));
+    }
+}
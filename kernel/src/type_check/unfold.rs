@@ -26,7 +26,7 @@ impl Expression {
     /// Intuitively, the number returned is higher for more complicated definitions.
     pub fn head_definition_height(self, db: &dyn Db) -> Option<DefinitionHeight> {
         match self.data(db) {
-            ExpressionData::Inst(path) => definition_height(db, path),
+            ExpressionData::Inst { path, .. } => definition_height(db, path),
             ExpressionData::Apply { left, .. } => left.head_definition_height(db),
             _ => None,
         }
@@ -34,17 +34,21 @@ impl Expression {
 
     /// If the head of this expression is a definition, unfold it.
     /// This is sometimes called delta-reduction.
-    /// If the definition was marked [`Reducibility::Irreducible`], do nothing.
+    /// If the definition was marked [`Reducibility::Irreducible`], or is an axiom with no body, do nothing.
     ///
     /// If we couldn't unfold anything, return [`None`].
     /// This will always return a value if [`head_definition_height`] returned a [`Some`] value.
     pub fn unfold_definition(self, db: &dyn Db) -> Option<Self> {
         match self.data(db) {
-            ExpressionData::Inst(path) => {
+            ExpressionData::Inst { path, universes } => {
                 get_certified_definition(db, path).as_ref().and_then(|def| {
-                    match def.reducibility() {
-                        Reducibility::Reducible { .. } => def.def().body,
-                        Reducibility::Irreducible => None,
+                    match (def.reducibility(), def.def().body) {
+                        // The body is instantiated at the universe levels that this `inst` gives.
+                        (Reducibility::Reducible { .. }, Some(body)) => Some(
+                            body.instantiate_universes(db, &def.def().universe_params, &universes),
+                        ),
+                        // Axioms are always irreducible, but we check for a body rather than relying on this.
+                        _ => None,
                     }
                 })
             }
@@ -0,0 +1,367 @@
+//! Checks that variables are used according to their multiplicities.
+//!
+//! A variable is used *relevantly* if it appears in a position that exists at runtime.
+//! Types, propositions, the parameters of `intro` expressions, the return types of `match` expressions,
+//! the proofs in `take` expressions, and arguments to erased parameters are not runtime-relevant.
+//!
+//! Variables bound with [`Usage::Erased`] must never be used relevantly.
+//! Functions with [`InvocationStyle::Once`] are linear resources, so a variable of such a type
+//! may be used relevantly at most once.
+
+use diagnostic::{Dr, DynamicDiagnostic};
+
+use crate::{
+    de_bruijn::DeBruijnIndex, definition::Definition, expr::*, get_certified_definition, Db,
+};
+
+use super::{LocalContext, UsageError};
+
+/// The number of times a variable is used in runtime-relevant positions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Multiplicity {
+    Zero,
+    One,
+    Many,
+}
+
+impl Multiplicity {
+    /// Records one more use of a variable.
+    fn succ(self) -> Self {
+        match self {
+            Multiplicity::Zero => Multiplicity::One,
+            Multiplicity::One | Multiplicity::Many => Multiplicity::Many,
+        }
+    }
+}
+
+/// Checks that the variables bound in the body of the given definition are used according to their multiplicities,
+/// and returns the multiplicity of each parameter bound by the leading lambda abstractions of the body.
+///
+/// Erased definitions have no runtime representation, so their bodies are not checked,
+/// and all of their parameters have multiplicity zero.
+/// This should only be called on definitions that have already been type checked.
+pub fn check_usage(db: &dyn Db, def: &Definition) -> Dr<Vec<Multiplicity>> {
+    let mut checker = UsageChecker {
+        db,
+        ctx: LocalContext::new(),
+        counts: Vec::new(),
+        errors: Vec::new(),
+    };
+    let mut body = match def.body {
+        Some(body) => body,
+        None => return Dr::new(Vec::new()),
+    };
+    while let ExpressionData::Lambda(binder) = body.data(db) {
+        checker.ctx = checker.ctx.with(binder.structure.bound);
+        checker.counts.push(Multiplicity::Zero);
+        body = binder.body;
+    }
+    if def.usage == Usage::Present {
+        checker.relevant(body);
+    }
+
+    if checker.errors.is_empty() {
+        Dr::new(checker.counts)
+    } else {
+        Dr::new_err_many(checker.errors)
+    }
+}
+
+struct UsageChecker<'a> {
+    db: &'a dyn Db,
+    /// The variables currently in scope.
+    ctx: LocalContext,
+    /// The multiplicity of each variable in scope so far, with the innermost variable last.
+    counts: Vec<Multiplicity>,
+    errors: Vec<DynamicDiagnostic>,
+}
+
+impl<'a> UsageChecker<'a> {
+    fn error(&mut self, error: UsageError) {
+        self.report(DynamicDiagnostic::new(error));
+    }
+
+    fn report(&mut self, error: DynamicDiagnostic) {
+        if !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
+
+    /// Records the uses of variables in an expression that appears in a runtime-relevant position.
+    fn relevant(&mut self, expr: Expression) {
+        let db = self.db;
+        db.unwind_if_cancelled();
+        match expr.data(db) {
+            ExpressionData::Local(index) => self.use_local(index),
+            ExpressionData::Apply { left, right } => {
+                self.relevant(left);
+                if !self.is_erased_argument(left) {
+                    self.relevant(right);
+                }
+            }
+            ExpressionData::Lambda(binder) => {
+                let before = self.counts.clone();
+                self.under(&[binder.structure.bound], |checker| {
+                    checker.relevant(binder.body)
+                });
+                // A function that may be invoked many times may evaluate its body many times,
+                // so each variable it captures is used many times.
+                if binder.structure.invocation_style != InvocationStyle::Once {
+                    for (position, count) in before.into_iter().enumerate() {
+                        if self.counts[position] != count {
+                            self.use_many(position);
+                        }
+                    }
+                }
+            }
+            ExpressionData::Let {
+                name,
+                to_assign,
+                body,
+            } => {
+                self.relevant(to_assign);
+                match to_assign.infer_type(db, &self.ctx).into_result().0 {
+                    Ok(ty) => {
+                        let bound = BoundVariable {
+                            name,
+                            ty,
+                            usage: Usage::Present,
+                        };
+                        self.under(&[bound], |checker| checker.relevant(body));
+                    }
+                    // The let expression should have been type checked already,
+                    // but if its value has no type, its body cannot be checked.
+                    Err(err) => self.report(err),
+                }
+            }
+            ExpressionData::Inst { path, .. } => {
+                if let Some(def) = get_certified_definition(db, path) {
+                    if def.def().usage == Usage::Erased {
+                        self.error(UsageError::ErasedDefinition {
+                            path: path.display(db),
+                        });
+                    }
+                }
+            }
+            ExpressionData::Intro { fields, .. } => {
+                for (_, field) in fields.iter() {
+                    self.relevant(*field);
+                }
+            }
+            ExpressionData::Match { subject, cases, .. } => {
+                self.relevant(subject);
+                // Only one case is evaluated at runtime,
+                // so a variable is used as many times as the case that uses it most.
+                let before = self.counts.clone();
+                let mut after = before.clone();
+                for (_, case) in cases.iter() {
+                    self.counts = before.clone();
+                    self.relevant(*case);
+                    for (total, count) in after.iter_mut().zip(&self.counts) {
+                        *total = (*total).max(*count);
+                    }
+                }
+                self.counts = after;
+            }
+            ExpressionData::Fix {
+                binder,
+                rec_name,
+                body,
+            } => {
+                let ctx = self.ctx.with_fix(db, binder, rec_name);
+                self.under_context(ctx, 2, |checker| checker.relevant(body));
+            }
+            ExpressionData::Deref(value) => self.relevant(value),
+            ExpressionData::Loan {
+                local,
+                loan_as,
+                with,
+                body,
+            } => {
+                // Loaning a variable does not consume it.
+                if let Some(ctx) = self.ctx.with_loan(db, local, loan_as, with) {
+                    self.under_context(ctx, 2, |checker| checker.relevant(body));
+                }
+            }
+            ExpressionData::Take { body, .. } => self.relevant(body),
+            ExpressionData::Pi(_)
+            | ExpressionData::Sort(_)
+            | ExpressionData::Ref(_)
+            | ExpressionData::In { .. }
+            | ExpressionData::LocalConstant(_)
+            | ExpressionData::Hole(_) => {}
+        }
+    }
+
+    /// Records a runtime-relevant use of the given local variable.
+    fn use_local(&mut self, index: DeBruijnIndex) {
+        let db = self.db;
+        let Some(bound) = self.ctx.get(index).copied() else {
+            return;
+        };
+        let position = self.counts.len() - index.value() as usize - 1;
+        let count = self.counts[position].succ();
+        self.counts[position] = count;
+
+        if bound.usage == Usage::Erased {
+            self.error(UsageError::ErasedVariable {
+                name: bound.name.text(db).to_owned(),
+            });
+        } else if count == Multiplicity::Many && self.is_linear(index) {
+            self.error(UsageError::LinearVariableReused {
+                name: bound.name.text(db).to_owned(),
+            });
+        }
+    }
+
+    /// Records that the variable at the given position in `counts` is used many times.
+    fn use_many(&mut self, position: usize) {
+        self.counts[position] = Multiplicity::Many;
+        let index = DeBruijnIndex::new((self.counts.len() - position - 1) as u32);
+        if self.is_linear(index) {
+            if let Some(bound) = self.ctx.get(index).copied() {
+                self.error(UsageError::LinearVariableReused {
+                    name: bound.name.text(self.db).to_owned(),
+                });
+            }
+        }
+    }
+
+    /// Returns true if the given local variable is a function that may only be invoked once.
+    fn is_linear(&mut self, index: DeBruijnIndex) -> bool {
+        let db = self.db;
+        let Some(ty) = self.ctx.local_type(db, index) else {
+            return false;
+        };
+        let linear = ty.weak_head_normal_form(db).map(|ty| {
+            matches!(
+                ty.data(db),
+                ExpressionData::Pi(binder) if binder.structure.invocation_style == InvocationStyle::Once
+            )
+        });
+        self.unwrap_or_report(linear)
+    }
+
+    /// Returns true if the parameter of the given function is erased,
+    /// so the argument it is applied to is not runtime-relevant.
+    fn is_erased_argument(&mut self, function: Expression) -> bool {
+        let db = self.db;
+        let erased = function
+            .infer_type(db, &self.ctx)
+            .bind(|ty| ty.weak_head_normal_form(db))
+            .map(|ty| {
+                matches!(
+                    ty.data(db),
+                    ExpressionData::Pi(binder) if binder.structure.bound.usage == Usage::Erased
+                )
+            });
+        self.unwrap_or_report(erased)
+    }
+
+    /// Returns the value of the given result, or reports its error and returns false.
+    /// The definition has already been type checked, so this should only fail if reduction runs out of fuel.
+    fn unwrap_or_report(&mut self, result: Dr<bool>) -> bool {
+        match result.into_result().0 {
+            Ok(value) => value,
+            Err(err) => {
+                self.report(err);
+                false
+            }
+        }
+    }
+
+    /// Runs `f` in a context extended by the given variables, with the innermost variable last.
+    fn under(&mut self, bound: &[BoundVariable], f: impl FnOnce(&mut Self)) {
+        let ctx = bound
+            .iter()
+            .fold(self.ctx.clone(), |ctx, bound| ctx.with(*bound));
+        self.under_context(ctx, bound.len(), f);
+    }
+
+    /// Runs `f` in the given context, which must extend the current context by `count` variables.
+    fn under_context(&mut self, ctx: LocalContext, count: usize, f: impl FnOnce(&mut Self)) {
+        let outer = std::mem::replace(&mut self.ctx, ctx);
+        self.counts
+            .extend(std::iter::repeat(Multiplicity::Zero).take(count));
+        f(self);
+        self.counts.truncate(self.counts.len() - count);
+        self.ctx = outer;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use files::{Path, Str, WithProvenance};
+
+    use super::*;
+    use crate::test_db::{local_constant, TestDatabase};
+
+    #[test]
+    fn erased_variables_are_not_used() {
+        let db = TestDatabase::default();
+        let a = local_constant(&db, 0, "a");
+        let mut b = local_constant(&db, 1, "b");
+        b.structure.bound.usage = Usage::Erased;
+        let function = |body: Expression| Definition {
+            name: WithProvenance::new(None, Str::new(&db, "f".to_owned())),
+            universe_params: Vec::new(),
+            usage: Usage::Present,
+            ty: Expression::new_sort(&db, Universe::zero()),
+            body: Some([a, b].iter().rev().fold(body, |body, local| {
+                Expression::new_lambda(&db, body.abstract_binder(&db, *local))
+            })),
+        };
+
+        let uses_a = function(Expression::new_local_constant(&db, a));
+        assert_eq!(
+            check_usage(&db, &uses_a).value(),
+            Some(&vec![Multiplicity::One, Multiplicity::Zero])
+        );
+
+        let uses_b = function(Expression::new_local_constant(&db, b));
+        let (value, reports) = check_usage(&db, &uses_b).to_reports();
+        assert!(value.is_none());
+        assert!(reports[0].to_string().contains("the erased variable b"));
+    }
+
+    #[test]
+    fn captured_variables_are_used_many_times() {
+        let db = TestDatabase::default();
+        let text = |name: &str| Str::new(&db, name.to_owned());
+        let a = local_constant(&db, 0, "a");
+        let x = local_constant(&db, 1, "x");
+        // `fun a -> intro T { f = fun x -> a }`, where the inner function has the given invocation style.
+        let capture = |invocation_style| {
+            let mut x = x;
+            x.structure.invocation_style = invocation_style;
+            let inner = Expression::new_lambda(
+                &db,
+                Expression::new_local_constant(&db, a).abstract_binder(&db, x),
+            );
+            let path = Path::new(&db, vec![text("T")]);
+            let intro = Expression::new_intro(
+                &db,
+                path,
+                Vec::new(),
+                text("mk"),
+                vec![(text("f"), inner)].into(),
+            );
+            Definition {
+                name: WithProvenance::new(None, text("f")),
+                universe_params: Vec::new(),
+                usage: Usage::Present,
+                ty: Expression::new_sort(&db, Universe::zero()),
+                body: Some(Expression::new_lambda(&db, intro.abstract_binder(&db, a))),
+            }
+        };
+
+        assert_eq!(
+            check_usage(&db, &capture(InvocationStyle::Once)).value(),
+            Some(&vec![Multiplicity::One])
+        );
+        assert_eq!(
+            check_usage(&db, &capture(InvocationStyle::Many)).value(),
+            Some(&vec![Multiplicity::Many])
+        );
+    }
+}
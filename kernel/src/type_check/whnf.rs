@@ -2,101 +2,309 @@
 //!
 //! Conversion rules: <https://coq.inria.fr/refman/language/core/conversion.html>
 
+use diagnostic::{Dr, DynamicDiagnostic};
+
 use crate::{expr::*, Db};
 
-impl Expression {
-    /// Reduces an expression to weak head normal form.
-    #[must_use]
-    pub fn weak_head_normal_form(mut self, db: &dyn Db) -> Self {
-        loop {
-            self = self.whnf_core(db);
-            match self.unfold_definition(db) {
-                Some(new) => self = new,
-                None => break,
+use super::TypeError;
+
+/// The number of reduction steps that [`Expression::weak_head_normal_form`] may take
+/// before giving up on reducing an expression.
+/// Unfolding a definition and each beta, fix, zeta and match reduction all count as one step.
+/// This is far more than any reasonable expression needs, but protects against definitions
+/// whose [`Reducibility`](super::Reducibility) metadata wrongly allows them to unfold forever,
+/// and against `fix` expressions that never terminate.
+pub const DEFAULT_REDUCTION_FUEL: u64 = 100_000;
+
+/// Reduces an expression to weak head normal form.
+/// The result is cached, so each distinct expression is only reduced once,
+/// and the cache is invalidated when a definition unfolded during reduction changes.
+///
+/// Definitions are unfolded according to their [`Reducibility`](super::Reducibility),
+/// so the cached result never unfolds an irreducible definition,
+/// and caching cannot make a recursive definition unfold where it would not have before.
+/// If reducing an expression requires reducing that same expression as a matched subject,
+/// the query cycle is recovered from by leaving the expression unreduced, rather than panicking.
+///
+/// At most [`DEFAULT_REDUCTION_FUEL`] reduction steps are taken.
+/// If this limit is reached, a [`TypeError::ReductionLimitExceeded`] is returned,
+/// so the type checker reports that it gave up rather than hanging.
+///
+/// Reduction checks for cancellation at each step, so if an input changes while an expression is being reduced
+/// on another thread, the outdated reduction unwinds promptly rather than running to completion.
+#[salsa::tracked(recovery_fn = recover_whnf_cycle)]
+pub fn cached_whnf(db: &dyn Db, expr: Expression) -> Dr<Expression> {
+    expr.weak_head_normal_form_with_fuel(db, DEFAULT_REDUCTION_FUEL)
+}
+
+fn recover_whnf_cycle(db: &dyn Db, _cycle: &salsa::Cycle, expr: Expression) -> Dr<Expression> {
+    tracing::warn!(
+        "gave up reducing {} because its reduction depends on itself",
+        expr.display(db, &DisplayOptions::default())
+    );
+    Dr::new(expr)
+}
+
+/// The number of reduction steps that may still be taken while reducing an expression.
+#[derive(Debug)]
+struct Fuel {
+    remaining: u64,
+    exhausted: bool,
+}
+
+impl Fuel {
+    fn new(remaining: u64) -> Self {
+        Self {
+            remaining,
+            exhausted: false,
+        }
+    }
+
+    /// Spends the fuel for one reduction step.
+    /// Returns false, and records that the fuel ran out, if there is none left.
+    fn consume(&mut self) -> bool {
+        match self.remaining.checked_sub(1) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                true
+            }
+            None => {
+                self.exhausted = true;
+                false
             }
         }
-        self
+    }
+}
+
+/// Reduces an expression to weak head normal form, spending the given fuel.
+/// If the fuel runs out, the partially reduced expression is returned.
+fn reduce_with_fuel(db: &dyn Db, mut expr: Expression, fuel: &mut Fuel) -> Expression {
+    loop {
+        expr = expr.whnf_core_with_fuel(db, fuel);
+        if fuel.exhausted {
+            return expr;
+        }
+        match expr.unfold_definition(db) {
+            Some(new) if fuel.consume() => expr = new,
+            _ => return expr,
+        }
+    }
+}
+
+/// Runs the given reduction with `fuel` reduction steps,
+/// reporting a [`TypeError::ReductionLimitExceeded`] if the fuel runs out.
+fn with_fuel(
+    db: &dyn Db,
+    expr: Expression,
+    fuel: u64,
+    reduce: impl FnOnce(&mut Fuel) -> Expression,
+) -> Dr<Expression> {
+    let mut remaining = Fuel::new(fuel);
+    let result = reduce(&mut remaining);
+    if remaining.exhausted {
+        Dr::new_err(DynamicDiagnostic::new(TypeError::ReductionLimitExceeded {
+            expr: db.format_expression(expr),
+            fuel,
+        }))
+    } else {
+        Dr::new(result)
+    }
+}
+
+impl Expression {
+    /// Reduces an expression to weak head normal form.
+    /// See [`cached_whnf`].
+    pub fn weak_head_normal_form(self, db: &dyn Db) -> Dr<Self> {
+        cached_whnf(db, self)
+    }
+
+    /// Reduces an expression to weak head normal form, taking at most `fuel` reduction steps.
+    /// Unlike [`Expression::weak_head_normal_form`], the result is not cached.
+    /// Running out of fuel is reported as a [`TypeError::ReductionLimitExceeded`].
+    pub fn weak_head_normal_form_with_fuel(self, db: &dyn Db, fuel: u64) -> Dr<Self> {
+        with_fuel(db, self, fuel, |fuel| reduce_with_fuel(db, self, fuel))
     }
 
     /// Tries to put an expression in weak head normal form, but does not perform delta reduction.
-    fn whnf_core(self, db: &dyn Db) -> Expression {
-        match self.data(db) {
-            ExpressionData::Apply { left, right } => {
-                // Reduce the function to weak head normal form first.
-                let left = left.whnf_core(db);
-                match left.data(db) {
-                    ExpressionData::Lambda(binder) => {
-                        // If the function is a lambda, we can apply a beta-reduction to expand the lambda.
-                        binder.body.instantiate(db, right).whnf_core(db)
-                    }
-                    ExpressionData::Fix { body, .. } => {
-                        // If the function is a fixpoint expression, we can apply a fix-reduction to expand it.
-                        body.instantiate(db, left)
-                            .instantiate(db, right)
-                            .whnf_core(db)
+    /// At most [`DEFAULT_REDUCTION_FUEL`] reduction steps are taken,
+    /// and running out of fuel is reported as a [`TypeError::ReductionLimitExceeded`].
+    pub(super) fn whnf_core(self, db: &dyn Db) -> Dr<Expression> {
+        with_fuel(db, self, DEFAULT_REDUCTION_FUEL, |fuel| {
+            self.whnf_core_with_fuel(db, fuel)
+        })
+    }
+
+    /// Tries to put an expression in weak head normal form, but does not perform delta reduction.
+    /// If the fuel runs out, the partially reduced expression is returned.
+    ///
+    /// Reductions at the head of the expression are performed in a loop rather than by recursion,
+    /// so long chains of reductions cannot overflow the stack.
+    fn whnf_core_with_fuel(self, db: &dyn Db, fuel: &mut Fuel) -> Expression {
+        let mut expr = self;
+        loop {
+            // Beta and fix reductions may go on for a long time without unfolding any definitions.
+            db.unwind_if_cancelled();
+            expr = match expr.data(db) {
+                ExpressionData::Apply { left, right } => {
+                    // Reduce the function to weak head normal form first.
+                    let left = left.whnf_core_with_fuel(db, fuel);
+                    match left.data(db) {
+                        ExpressionData::Lambda(binder) if fuel.consume() => {
+                            // If the function is a lambda, we can apply a beta-reduction to expand the lambda.
+                            binder.body.instantiate(db, right)
+                        }
+                        ExpressionData::Fix { body, .. } if fuel.consume() => {
+                            // If the function is a fixpoint expression, we can apply a fix-reduction to expand it.
+                            // Local variable `0` is the fixpoint itself, and local variable `1` is its argument.
+                            body.instantiate_many(db, &[left, right])
+                        }
+                        _ => return Expression::new_apply(db, left, right),
                     }
-                    _ => Expression::new_apply(db, left, right),
                 }
-            }
-            ExpressionData::Let {
-                to_assign, body, ..
-            } => {
-                // We substitute the value into the body of the let expression, then continue to evaluate the expression.
-                // This is called zeta-reduction.
-                body.instantiate(db, to_assign).whnf_core(db)
-            }
-            ExpressionData::Match {
-                subject,
-                return_ty,
-                cases,
-            } => {
-                // Reduce the major premise to weak head normal form first.
-                let subject = subject.weak_head_normal_form(db);
-                if let ExpressionData::Intro {
-                    variant, fields, ..
-                } = subject.data(db)
-                {
-                    // We can unfold this match expression.
-                    // Since the match expression is type correct, the unwrap is ok.
+                ExpressionData::Let {
+                    to_assign, body, ..
+                } if fuel.consume() => {
+                    // We substitute the value into the body of the let expression, then continue to evaluate the expression.
+                    // This is called zeta-reduction.
+                    body.instantiate(db, to_assign)
+                }
+                ExpressionData::Match {
+                    subject,
+                    return_ty,
+                    cases,
+                } => {
+                    // Reduce the major premise to weak head normal form first.
+                    // This spends the same fuel, so nested matches cannot escape the limit.
+                    let subject = reduce_with_fuel(db, subject, fuel);
+                    // If the subject is an introduction rule, we can unfold this match expression.
                     // This is called match-reduction.
-                    let (_, result) = cases
-                        .iter()
-                        .find(|(name, _)| *name == variant)
-                        .copied()
-                        .unwrap();
-
-                    fields
-                        .iter()
-                        .fold(result, |result, (_, field)| {
-                            Expression::new_apply(db, result, *field)
-                        })
-                        .whnf_core(db)
-                } else {
-                    Expression::new_match(db, subject, return_ty, cases)
+                    let reduct = match subject.data(db) {
+                        ExpressionData::Intro {
+                            variant, fields, ..
+                        } => cases.get(&variant).map(|result| {
+                            fields.values().fold(*result, |result, field| {
+                                Expression::new_apply(db, result, *field)
+                            })
+                        }),
+                        _ => None,
+                    };
+                    match reduct {
+                        Some(reduct) if fuel.consume() => reduct,
+                        // If there is no case for the subject's variant, the match is stuck.
+                        _ => return Expression::new_match(db, subject, return_ty, cases),
+                    }
                 }
-            }
-            ExpressionData::Fix {
-                binder,
-                rec_name,
-                body,
-            } => todo!(),
-            ExpressionData::Ref(_) => todo!(),
-            ExpressionData::Deref(_) => todo!(),
-            ExpressionData::Loan {
-                local,
-                loan_as,
-                with,
-                body,
-            } => todo!(),
-            ExpressionData::Take {
-                local,
-                proofs,
-                body,
-            } => todo!(),
-            ExpressionData::In { reference, target } => todo!(),
-            ExpressionData::LocalConstant(_) => todo!(),
-            ExpressionData::Hole(_) => todo!(),
-            _ => self,
+                ExpressionData::Deref(value) => {
+                    // Dereferencing a reference to a value cancels out.
+                    let value = value.whnf_core_with_fuel(db, fuel);
+                    match value.data(db) {
+                        ExpressionData::Ref(inner) => inner,
+                        _ => return Expression::new_deref(db, value),
+                    }
+                }
+                ExpressionData::Take { body, .. } => {
+                    // The proofs in a `take` expression are only used for type checking,
+                    // so taking back a loan has no computational content.
+                    body
+                }
+                // A `fix` expression that is not applied to anything is already a value.
+                // We only unfold it when it is applied to an argument, to avoid diverging.
+                ExpressionData::Fix { .. } => return expr,
+                // `ref` and `in` expressions are type formers, so are already in weak head normal form.
+                ExpressionData::Ref(_) | ExpressionData::In { .. } => return expr,
+                // The body of a `loan` expression is under two binders, so we don't reduce it.
+                ExpressionData::Loan { .. } => return expr,
+                // Local constants and holes are neutral terms.
+                ExpressionData::LocalConstant(_) | ExpressionData::Hole(_) => return expr,
+                _ => return expr,
+            };
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use files::{Path, Str};
+
+    use super::*;
+    use crate::{
+        de_bruijn::DeBruijnIndex,
+        test_db::{local_constant, TestDatabase},
+    };
+
+    #[test]
+    fn matches_reduce_to_their_case() {
+        let db = TestDatabase::default();
+        let nat = Path::new(&db, vec![Str::new(&db, "Nat".to_owned())]);
+        let text = |name: &str| Str::new(&db, name.to_owned());
+        let [motive, minor_zero, minor_succ] =
+            [0, 1, 2].map(|id| Expression::new_local_constant(&db, local_constant(&db, id, "x")));
+        let intro_zero =
+            Expression::new_intro(&db, nat, Vec::new(), text("zero"), Vec::new().into());
+        let intro_succ = Expression::new_intro(
+            &db,
+            nat,
+            Vec::new(),
+            text("succ"),
+            vec![(text("n"), intro_zero)].into(),
+        );
+        // `match intro Nat/succ { n = intro Nat/zero {} } return motive { zero -> minor_zero, succ -> minor_succ }`
+        // should reduce to `minor_succ (intro Nat/zero {})`.
+        let matched = Expression::new_match(
+            &db,
+            intro_succ,
+            motive,
+            vec![(text("zero"), minor_zero), (text("succ"), minor_succ)].into(),
+        );
+        assert_eq!(
+            matched.weak_head_normal_form(&db).value(),
+            Some(&Expression::new_apply(&db, minor_succ, intro_zero))
+        );
+    }
+
+    #[test]
+    fn matches_without_a_case_are_stuck() {
+        let db = TestDatabase::default();
+        let nat = Path::new(&db, vec![Str::new(&db, "Nat".to_owned())]);
+        let text = |name: &str| Str::new(&db, name.to_owned());
+        let intro_zero =
+            Expression::new_intro(&db, nat, Vec::new(), text("zero"), Vec::new().into());
+        let motive = Expression::new_local_constant(&db, local_constant(&db, 0, "motive"));
+        let minor = Expression::new_local_constant(&db, local_constant(&db, 1, "minor"));
+        let stuck =
+            Expression::new_match(&db, intro_zero, motive, vec![(text("succ"), minor)].into());
+        assert_eq!(stuck.weak_head_normal_form(&db).value(), Some(&stuck));
+    }
+
+    #[test]
+    fn diverging_fixpoints_run_out_of_fuel() {
+        let db = TestDatabase::default();
+        let x = local_constant(&db, 0, "x");
+        // `(fix f (x: Sort 0) -> Sort 0 => f x) a`, which reduces to itself forever.
+        let fix = Expression::new_fix(
+            &db,
+            Binder {
+                structure: x.structure,
+                body: Expression::new_sort(&db, Universe::zero()),
+            },
+            Str::new(&db, "f".to_owned()),
+            Expression::new_apply(
+                &db,
+                Expression::new_local(&db, DeBruijnIndex::zero()),
+                Expression::new_local(&db, DeBruijnIndex::zero().succ()),
+            ),
+        );
+        let a = Expression::new_local_constant(&db, local_constant(&db, 1, "a"));
+        let diverging = Expression::new_apply(&db, fix, a);
+        let (value, reports) = diverging
+            .weak_head_normal_form_with_fuel(&db, 1000)
+            .to_reports();
+        assert!(value.is_none());
+        assert!(reports[0]
+            .to_string()
+            .ends_with("after 1000 reduction steps"));
+        // Without an explicit limit, reduction gives up after the default number of steps
+        // without overflowing the stack.
+        assert!(diverging.weak_head_normal_form(&db).value().is_none());
+    }
+}
@@ -21,6 +21,81 @@ impl<K, V> VecMap<K, V> {
     }
 }
 
+/// The error returned by [`VecMap::try_from_vec`] when a key appears more than once.
+/// Contains the positions of the first two entries in the input list that share a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DuplicateKey {
+    pub first: usize,
+    pub second: usize,
+}
+
+impl<K, V> VecMap<K, V>
+where
+    K: PartialEq,
+{
+    /// Creates a map from a list of key-value pairs, checking that no key appears more than once.
+    /// Unlike the [`From`] implementation, this will not silently create a map with duplicate keys.
+    pub fn try_from_vec(value: Vec<(K, V)>) -> Result<Self, DuplicateKey> {
+        for (second, (key, _)) in value.iter().enumerate() {
+            if let Some(first) = value[..second].iter().position(|(other, _)| other == key) {
+                return Err(DuplicateKey { first, second });
+            }
+        }
+        Ok(Self(value))
+    }
+
+    /// Returns the value associated with the given key, if it exists.
+    pub fn get(&self, k: &K) -> Option<&V> {
+        self.0
+            .iter()
+            .find_map(|(key, value)| if key == k { Some(value) } else { None })
+    }
+
+    /// Inserts a key-value pair into the map.
+    /// If the key was already present, its value is overwritten in place, and the old value is returned.
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        match self.0.iter_mut().find(|(key, _)| *key == k) {
+            Some((_, value)) => Some(std::mem::replace(value, v)),
+            None => {
+                self.0.push((k, v));
+                None
+            }
+        }
+    }
+
+    /// Returns whether the given key is present in the map.
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.0.iter().any(|(key, _)| key == k)
+    }
+
+    /// Removes the given key from the map, returning its value if it was present.
+    /// The order of the remaining entries is preserved.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let position = self.0.iter().position(|(key, _)| key == k)?;
+        Some(self.0.remove(position).1)
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterates over the keys of the map, in insertion order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.0.iter().map(|(key, _)| key)
+    }
+
+    /// Iterates over the values of the map, in insertion order.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.0.iter().map(|(_, value)| value)
+    }
+}
+
 impl<K, V> Default for VecMap<K, V> {
     fn default() -> Self {
         Self::new()